@@ -9,31 +9,6 @@ resource "digitalocean_app" "app_platform" {
       zone = var.domain_name
     }
 
-    job {
-      name = "migrate"
-
-      kind        = "PRE_DEPLOY"
-      run_command = "migration up -u ${digitalocean_database_cluster.main.uri}"
-
-      env {
-        key   = "DATABASE_URL"
-        value = digitalocean_database_cluster.main.uri
-
-        scope = "RUN_TIME"
-        type  = "SECRET"
-      }
-
-      image {
-        registry_type = var.registry_type
-        repository    = var.task_runner_docker_image_name
-        tag           = var.task_runner_docker_image_tag
-
-        deploy_on_push {
-          enabled = true
-        }
-      }
-    }
-
     service {
       name               = var.service_name
       instance_count     = var.instance_count
@@ -47,6 +22,12 @@ resource "digitalocean_app" "app_platform" {
         scope = "RUN_TIME"
       }
 
+      env {
+        key   = "RUN_MIGRATIONS_ON_STARTUP"
+        value = "true"
+        scope = "RUN_TIME"
+      }
+
       env {
         key   = "RUST_BACKTRACE"
         value = "1"
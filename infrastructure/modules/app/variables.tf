@@ -34,10 +34,3 @@ variable "docker_image_tag" {
   default = ""
 }
 
-variable "task_runner_docker_image_name" {
-  default = ""
-}
-
-variable "task_runner_docker_image_tag" {
-  default = ""
-}
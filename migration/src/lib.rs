@@ -2,6 +2,17 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
 mod m20250530_124142_add_comments;
+mod m20250614_101500_add_row_versions;
+mod m20250621_090000_add_soft_delete;
+mod m20250705_100000_add_search_documents;
+mod m20250712_080000_add_topic_activity;
+mod m20250719_090000_add_outbox;
+mod m20250726_090000_add_dead_letters;
+mod m20250802_090000_add_idempotency_keys;
+mod m20250809_090000_add_job_runs;
+mod m20250816_090000_add_outbox_topics;
+mod m20250823_090000_add_withdrawn_with_topic;
+mod m20250830_090000_add_outbox_attempts;
 
 pub struct Migrator;
 
@@ -11,6 +22,17 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_create_table::Migration),
             Box::new(m20250530_124142_add_comments::Migration),
+            Box::new(m20250614_101500_add_row_versions::Migration),
+            Box::new(m20250621_090000_add_soft_delete::Migration),
+            Box::new(m20250705_100000_add_search_documents::Migration),
+            Box::new(m20250712_080000_add_topic_activity::Migration),
+            Box::new(m20250719_090000_add_outbox::Migration),
+            Box::new(m20250726_090000_add_dead_letters::Migration),
+            Box::new(m20250802_090000_add_idempotency_keys::Migration),
+            Box::new(m20250809_090000_add_job_runs::Migration),
+            Box::new(m20250816_090000_add_outbox_topics::Migration),
+            Box::new(m20250823_090000_add_withdrawn_with_topic::Migration),
+            Box::new(m20250830_090000_add_outbox_attempts::Migration),
         ]
     }
 }
@@ -0,0 +1,29 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("ALTER TABLE topics ADD COLUMN version INTEGER NOT NULL DEFAULT 0;")
+            .await?;
+        db.execute_unprepared(
+            "ALTER TABLE comments ADD COLUMN version INTEGER NOT NULL DEFAULT 0;",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("ALTER TABLE comments DROP COLUMN version;")
+            .await?;
+        db.execute_unprepared("ALTER TABLE topics DROP COLUMN version;")
+            .await?;
+
+        Ok(())
+    }
+}
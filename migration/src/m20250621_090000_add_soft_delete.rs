@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for table in ["topics", "comments"] {
+            db.execute_unprepared(&format!(
+                "ALTER TABLE {table} ADD COLUMN deleted_at TIMESTAMPTZ;"
+            ))
+            .await?;
+            db.execute_unprepared(&format!("ALTER TABLE {table} ADD COLUMN deleted_by TEXT;"))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for table in ["comments", "topics"] {
+            db.execute_unprepared(&format!("ALTER TABLE {table} DROP COLUMN deleted_by;"))
+                .await?;
+            db.execute_unprepared(&format!("ALTER TABLE {table} DROP COLUMN deleted_at;"))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
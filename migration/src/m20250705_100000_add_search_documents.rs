@@ -0,0 +1,59 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+/// Each table's searched text, weighted in order of significance like `Searchable::SEARCHED_FIELDS`
+const SEARCH_DOCUMENTS: [(&str, &str); 2] = [
+    (
+        "topics",
+        "setweight(to_tsvector('english', coalesce(title, '')), 'A') || \
+        setweight(to_tsvector('english', coalesce(content, '')), 'B')",
+    ),
+    (
+        "comments",
+        "setweight(to_tsvector('english', coalesce(content, '')), 'A')",
+    ),
+];
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Full-text search is only indexed on Postgres, other backends scan the rows instead
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Postgres {
+            return Ok(());
+        }
+
+        let db = manager.get_connection();
+        for (table, document) in SEARCH_DOCUMENTS {
+            db.execute_unprepared(&format!(
+                "ALTER TABLE {table} ADD COLUMN search_document tsvector \
+                GENERATED ALWAYS AS ({document}) STORED;"
+            ))
+            .await?;
+            db.execute_unprepared(&format!(
+                "CREATE INDEX idx_{table}_search_document ON {table} USING GIN (search_document);"
+            ))
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Postgres {
+            return Ok(());
+        }
+
+        let db = manager.get_connection();
+        for (table, _) in SEARCH_DOCUMENTS {
+            db.execute_unprepared(&format!("DROP INDEX idx_{table}_search_document;"))
+                .await?;
+            db.execute_unprepared(&format!("ALTER TABLE {table} DROP COLUMN search_document;"))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "ALTER TABLE topics ADD COLUMN comment_count INTEGER NOT NULL DEFAULT 0;",
+        )
+        .await?;
+        db.execute_unprepared("ALTER TABLE topics ADD COLUMN last_activity_time TIMESTAMPTZ;")
+            .await?;
+        db.execute_unprepared(
+            "UPDATE topics SET \
+                comment_count = ( \
+                    SELECT COUNT(*) FROM comments WHERE comments.topic_id = topics.id \
+                ), \
+                last_activity_time = COALESCE( \
+                    (SELECT MAX(creation_time) FROM comments WHERE comments.topic_id = topics.id), \
+                    creation_time \
+                );",
+        )
+        .await?;
+        // SQLite cannot tighten a column after the fact, the application fills it in regardless
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            db.execute_unprepared(
+                "ALTER TABLE topics ALTER COLUMN last_activity_time SET NOT NULL;",
+            )
+            .await?;
+        }
+        db.execute_unprepared(
+            "CREATE INDEX idx_topics_last_activity_time ON topics (last_activity_time);",
+        )
+        .await?;
+        db.execute_unprepared("CREATE INDEX idx_topics_comment_count ON topics (comment_count);")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP INDEX idx_topics_comment_count;")
+            .await?;
+        db.execute_unprepared("DROP INDEX idx_topics_last_activity_time;")
+            .await?;
+        db.execute_unprepared("ALTER TABLE topics DROP COLUMN last_activity_time;")
+            .await?;
+        db.execute_unprepared("ALTER TABLE topics DROP COLUMN comment_count;")
+            .await?;
+
+        Ok(())
+    }
+}
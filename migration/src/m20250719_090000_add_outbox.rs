@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        // Entries are applied in the order they were written, which the identifier keeps track of
+        let id_column = match manager.get_database_backend() {
+            DatabaseBackend::Sqlite => "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            _ => "id BIGSERIAL PRIMARY KEY",
+        };
+        db.execute_unprepared(&format!(
+            "CREATE TABLE outbox (
+    {id_column},
+    operation TEXT NOT NULL,
+    enqueued_at TIMESTAMPTZ NOT NULL,
+    claimed_at TIMESTAMPTZ
+);"
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP TABLE outbox;").await?;
+
+        Ok(())
+    }
+}
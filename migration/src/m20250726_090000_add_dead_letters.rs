@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        // Writes keep the identifier they had in the outbox, so they can be traced back in the logs
+        db.execute_unprepared(
+            "CREATE TABLE dead_letters (
+    id BIGINT PRIMARY KEY,
+    operation TEXT NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    enqueued_at TIMESTAMPTZ NOT NULL,
+    failed_at TIMESTAMPTZ NOT NULL
+);",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP TABLE dead_letters;").await?;
+
+        Ok(())
+    }
+}
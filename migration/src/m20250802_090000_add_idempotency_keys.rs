@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "CREATE TABLE idempotency_keys (
+    key UUID PRIMARY KEY,
+    remembered_at TIMESTAMPTZ NOT NULL
+);",
+        )
+        .await?;
+        // Keys past the window are cleared by age
+        db.execute_unprepared(
+            "CREATE INDEX idx_idempotency_keys_remembered_at ON idempotency_keys (remembered_at);",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP TABLE idempotency_keys;").await?;
+
+        Ok(())
+    }
+}
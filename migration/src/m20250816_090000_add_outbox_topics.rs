@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        // Entries already waiting have none, and are claimed like writes about no petty matter
+        db.execute_unprepared("ALTER TABLE outbox ADD COLUMN topic_id UUID;")
+            .await?;
+        db.execute_unprepared("CREATE INDEX idx_outbox_topic_id ON outbox (topic_id);")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP INDEX idx_outbox_topic_id;")
+            .await?;
+        db.execute_unprepared("ALTER TABLE outbox DROP COLUMN topic_id;")
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "ALTER TABLE comments ADD COLUMN withdrawn_with_topic BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .await?;
+        // Comments withdrawn along with their petty matter so far shared its withdrawal time
+        db.execute_unprepared(
+            "UPDATE comments SET withdrawn_with_topic = TRUE
+             WHERE deleted_at IS NOT NULL
+               AND deleted_at = (SELECT deleted_at FROM topics WHERE topics.id = comments.topic_id);",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("ALTER TABLE comments DROP COLUMN withdrawn_with_topic;")
+            .await?;
+
+        Ok(())
+    }
+}
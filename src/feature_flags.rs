@@ -3,14 +3,20 @@ use std::sync::LazyLock;
 
 pub struct FeatureFlags {
     pub is_ephemeral_db_allowed: bool,
+    /// Whether consecutive comments on the same petty matter are inserted together
+    pub is_comment_batching_enabled: bool,
 }
 
 pub static FEATURE_FLAGS: LazyLock<FeatureFlags> = LazyLock::new(|| {
     let is_ephemeral_db_allowed: bool = env::var("EPHEMERAL_DB_ALLOWED")
         .unwrap_or_else(|_| "false".to_string())
         .eq_ignore_ascii_case("true");
+    let is_comment_batching_enabled: bool = env::var("BATCH_COMMENT_INSERTS")
+        .unwrap_or_else(|_| "false".to_string())
+        .eq_ignore_ascii_case("true");
 
     FeatureFlags {
         is_ephemeral_db_allowed,
+        is_comment_batching_enabled,
     }
 });
@@ -1,11 +1,24 @@
+// #[tokio::test] expands to an .expect() on the runtime builder of every test returning a Result
+#![cfg_attr(test, allow(clippy::unwrap_in_result))]
+
 use crate::authn::views::auth_router;
 use crate::config::APP_CONFIG;
 use crate::error::AnyError;
+use crate::persistence::health::{DATABASE_HEALTH, DatabaseStatus};
 use crate::petty_matters::service::petty_matters_service_factory;
 use crate::petty_matters::views::petty_matters_router;
+use crate::queue::events::EventCounts;
+use crate::queue::outbox::OutboxQueue;
+use crate::views::read_your_writes::read_your_writes;
+use axum::http::{HeaderName, StatusCode, header};
+use axum::middleware;
 use axum::response::Redirect;
 use axum::{Router, routing::get};
 use persistence::rdbms;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal;
 use tower_http::services::ServeDir;
 
 mod authn;
@@ -15,38 +28,149 @@ mod feature_flags;
 mod persistence;
 mod petty_matters;
 mod queue;
+mod scheduler;
 mod templates;
+#[cfg(test)]
+mod testing;
 mod time;
 mod views;
 
 static MAIN_ENTRY_POINT: &str = "/petty-matters";
 
-#[tokio::main]
-async fn main() -> Result<(), AnyError> {
+fn main() -> Result<(), AnyError> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(serve())
+}
+
+async fn serve() -> Result<(), AnyError> {
     println!("Starting up");
 
     let database_connection = rdbms::connect(&APP_CONFIG.database_url).await;
-    let petty_matters_service = petty_matters_service_factory(database_connection)?;
+    let petty_matters_service = petty_matters_service_factory(database_connection).await?;
+    let write_queue = petty_matters_service.write_queue.clone();
+    let event_counts = Arc::new(EventCounts::default());
+    petty_matters_service
+        .events
+        .subscribe(event_counts.clone())
+        .await;
 
     println!("Configuring routes and middlewares");
     let app = Router::new()
         .route("/", get(|| async { Redirect::to(MAIN_ENTRY_POINT) }))
+        .route("/health", get(report_health))
+        .route(
+            "/metrics",
+            get({
+                let write_queue = write_queue.clone();
+                move || {
+                    let metrics = report_metrics(&write_queue, &event_counts);
+                    async { metrics }
+                }
+            }),
+        )
         .nest("/auth", auth_router())
         .nest(
             MAIN_ENTRY_POINT,
             petty_matters_router(petty_matters_service),
         )
-        .nest_service("/assets", ServeDir::new("assets"));
+        .nest_service("/assets", ServeDir::new("assets"))
+        .layer(middleware::from_fn(read_your_writes));
 
     run_server(app, APP_CONFIG.get_address()).await?;
 
+    println!("Applying the remaining writes before shutting down");
+    let drain_timeout = Duration::from_secs(u64::from(APP_CONFIG.shutdown_drain_timeout.0));
+    if !write_queue.drain(drain_timeout).await {
+        eprintln!(
+            "Gave up waiting on the write queue after {}, the rest will be applied on the next start",
+            APP_CONFIG.shutdown_drain_timeout
+        );
+    }
+    println!("Shut down");
+
     Ok(())
 }
 
+async fn report_health() -> (StatusCode, String) {
+    let status = DATABASE_HEALTH.status();
+    match status {
+        DatabaseStatus::Available => (StatusCode::OK, status.to_string()),
+        DatabaseStatus::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, status.to_string()),
+    }
+}
+
+/// In the Prometheus text format, for whatever scrapes them
+fn report_metrics(
+    write_queue: &OutboxQueue,
+    event_counts: &EventCounts,
+) -> ([(HeaderName, &'static str); 1], String) {
+    let event_counts =
+        event_counts
+            .counts()
+            .into_iter()
+            .fold(String::new(), |mut lines, (event, count)| {
+                let _ = writeln!(lines, "domain_events_total{{event=\"{event}\"}} {count}");
+                lines
+            });
+    let metrics = format!(
+        "# HELP write_queue_depth Writes waiting to be applied.
+# TYPE write_queue_depth gauge
+write_queue_depth {}
+# HELP write_queue_capacity Writes that may wait before new ones are turned away.
+# TYPE write_queue_capacity gauge
+write_queue_capacity {}
+# HELP domain_events_total Events published once the writes behind them were applied.
+# TYPE domain_events_total counter
+{event_counts}",
+        write_queue.depth(),
+        write_queue.capacity()
+    );
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics,
+    )
+}
+
 async fn run_server(app: Router, address: String) -> Result<(), AnyError> {
     println!("Starting listener on {address}");
     let listener = tokio::net::TcpListener::bind(&address).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
 
     Ok(())
 }
+
+/// Resolves on Ctrl+C or SIGTERM, after which no more connections are accepted,
+/// but the requests already being handled are seen through
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = signal::ctrl_c().await {
+            eprintln!("Could not listen for Ctrl+C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Could not listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = interrupt => {},
+        () = terminate => {},
+    }
+    println!("Shutting down, finishing the requests under way");
+}
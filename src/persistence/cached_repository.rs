@@ -0,0 +1,457 @@
+use crate::persistence::replication::prefers_primary;
+use crate::persistence::repository::{HasId, ListParameters, Page, Repository, RepositoryError};
+use crate::persistence::unit_of_work::Transaction;
+use crate::time::Seconds;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use moka::future::Cache;
+use moka::policy::EvictionPolicy;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Serves repeated reads from memory, and forgets what a write through it may have changed.
+///
+/// Any write can move entities between listing pages, so every cached listing is dropped
+/// along with the written entity. Entities written within a transaction are forgotten once
+/// it commits, since that's when they become visible to everyone else.
+/// Reads meant for the primary skip the cache, which may hold what a lagging replica returned.
+/// Missing entities aren't cached, so they show up as soon as they're created elsewhere.
+pub struct CachedRepository<R, ID, Entity>
+where
+    R: ?Sized,
+{
+    repository: Arc<R>,
+    entities: Cache<ID, Entity>,
+    pages: Cache<ListParameters, Page<Entity>>,
+}
+
+impl<R, ID, Entity> CachedRepository<R, ID, Entity>
+where
+    R: Repository<ID, Entity> + Send + Sync + ?Sized,
+    ID: Send + Sync + Eq + Hash + Clone + 'static,
+    Entity: Send + Sync + Clone + HasId<ID> + 'static,
+{
+    pub fn new(repository: Arc<R>, capacity: u64, time_to_live: &Seconds) -> Self {
+        let time_to_live = Duration::from_secs(u64::from(time_to_live.0));
+
+        Self {
+            repository,
+            entities: Cache::builder()
+                .eviction_policy(EvictionPolicy::tiny_lfu())
+                .time_to_live(time_to_live)
+                .max_capacity(capacity)
+                .support_invalidation_closures()
+                .build(),
+            pages: Cache::builder()
+                .eviction_policy(EvictionPolicy::tiny_lfu())
+                .time_to_live(time_to_live)
+                .max_capacity(capacity)
+                .build(),
+        }
+    }
+
+    async fn forget(&self, id: &ID) {
+        self.entities.invalidate(id).await;
+        self.pages.invalidate_all();
+    }
+
+    /// Without awaiting, as it happens in a commit hook
+    fn forget_written(
+        entities: &Cache<ID, Entity>,
+        pages: &Cache<ListParameters, Page<Entity>>,
+        written: Written<ID>,
+    ) {
+        if written.is_nothing() {
+            return;
+        }
+        pages.invalidate_all();
+        if written.is_everything {
+            entities.invalidate_all();
+            return;
+        }
+        let ids = written.ids;
+        if entities
+            .invalidate_entries_if(move |id, _| ids.contains(id))
+            .is_err()
+        {
+            entities.invalidate_all();
+        }
+    }
+}
+
+/// What a transaction wrote through a [`CachedRepository`], to be forgotten once it commits
+struct Written<ID> {
+    ids: HashSet<ID>,
+    /// Set by writes that can't tell which entities they touched
+    is_everything: bool,
+}
+
+impl<ID> Written<ID> {
+    fn nothing() -> Self {
+        Self {
+            ids: HashSet::new(),
+            is_everything: false,
+        }
+    }
+
+    fn everything() -> Self {
+        Self {
+            ids: HashSet::new(),
+            is_everything: true,
+        }
+    }
+
+    fn is_nothing(&self) -> bool {
+        self.ids.is_empty() && !self.is_everything
+    }
+}
+
+/// Passes a transaction's reads and writes on, noting which entities the writes touched
+struct NotingWrites<R, ID, Entity>
+where
+    R: ?Sized,
+{
+    cache: Arc<CachedRepository<R, ID, Entity>>,
+    repository: Arc<dyn Repository<ID, Entity> + Send + Sync>,
+    written: Arc<Mutex<Written<ID>>>,
+}
+
+impl<R, ID, Entity> NotingWrites<R, ID, Entity>
+where
+    R: ?Sized,
+    ID: Eq + Hash,
+{
+    fn note(&self, ids: impl IntoIterator<Item = ID>) {
+        if let Ok(mut written) = self.written.lock() {
+            written.ids.extend(ids);
+        }
+    }
+
+    fn note_everything(&self) {
+        if let Ok(mut written) = self.written.lock() {
+            written.is_everything = true;
+        }
+    }
+}
+
+#[async_trait]
+impl<R, ID, Entity> Repository<ID, Entity> for CachedRepository<R, ID, Entity>
+where
+    R: Repository<ID, Entity> + Send + Sync + ?Sized + 'static,
+    ID: Send + Sync + Eq + Hash + Clone + 'static,
+    Entity: Send + Sync + Clone + HasId<ID> + 'static,
+{
+    async fn list(&self, list_parameters: ListParameters) -> Result<Page<Entity>, RepositoryError> {
+        if prefers_primary() {
+            return self.repository.list(list_parameters).await;
+        }
+        if let Some(cached) = self.pages.get(&list_parameters).await {
+            return Ok(cached);
+        }
+
+        let page = self.repository.list(list_parameters.clone()).await?;
+        self.pages.insert(list_parameters, page.clone()).await;
+
+        Ok(page)
+    }
+
+    async fn create(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let id = entity.id();
+        let outcome = self.repository.create(entity).await;
+        self.forget(&id).await;
+
+        outcome
+    }
+
+    async fn create_many(&self, entities: Vec<Entity>) -> Result<(), RepositoryError> {
+        let ids: Vec<ID> = entities.iter().map(HasId::id).collect();
+        let outcome = self.repository.create_many(entities).await;
+        for id in &ids {
+            self.forget(id).await;
+        }
+
+        outcome
+    }
+
+    async fn update(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let id = entity.id();
+        let outcome = self.repository.update(entity).await;
+        self.forget(&id).await;
+
+        outcome
+    }
+
+    async fn overwrite(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let id = entity.id();
+        let outcome = self.repository.overwrite(entity).await;
+        self.forget(&id).await;
+
+        outcome
+    }
+
+    async fn get_by_id(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        if prefers_primary() {
+            return self.repository.get_by_id(id).await;
+        }
+        if let Some(cached) = self.entities.get(id).await {
+            return Ok(Some(cached));
+        }
+
+        let entity = self.repository.get_by_id(id).await?;
+        if let Some(entity) = &entity {
+            self.entities.insert(id.clone(), entity.clone()).await;
+        }
+
+        Ok(entity)
+    }
+
+    async fn get_by_id_including_deleted(
+        &self,
+        id: &ID,
+    ) -> Result<Option<Entity>, RepositoryError> {
+        self.repository.get_by_id_including_deleted(id).await
+    }
+
+    async fn get_for_update(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        self.repository.get_for_update(id).await
+    }
+
+    async fn delete(&self, id: &ID) -> Result<(), RepositoryError> {
+        let outcome = self.repository.delete(id).await;
+        self.forget(id).await;
+
+        outcome
+    }
+
+    async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let outcome = self.repository.purge_deleted(deleted_before).await;
+        self.entities.invalidate_all();
+        self.pages.invalidate_all();
+
+        outcome
+    }
+
+    fn within(
+        self: Arc<Self>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<ID, Entity> + Send + Sync> {
+        let written = Arc::new(Mutex::new(Written::nothing()));
+        let (entities, pages, noted) = (self.entities.clone(), self.pages.clone(), written.clone());
+        transaction.after_commit(move || {
+            let written = noted.lock().map_or_else(
+                |_| Written::everything(),
+                |mut written| std::mem::replace(&mut *written, Written::nothing()),
+            );
+            Self::forget_written(&entities, &pages, written);
+        });
+
+        Arc::new(NotingWrites {
+            repository: self.repository.clone().within(transaction),
+            cache: self,
+            written,
+        })
+    }
+}
+
+#[async_trait]
+impl<R, ID, Entity> Repository<ID, Entity> for NotingWrites<R, ID, Entity>
+where
+    R: Repository<ID, Entity> + Send + Sync + ?Sized + 'static,
+    ID: Send + Sync + Eq + Hash + Clone + 'static,
+    Entity: Send + Sync + Clone + HasId<ID> + 'static,
+{
+    async fn list(&self, list_parameters: ListParameters) -> Result<Page<Entity>, RepositoryError> {
+        self.repository.list(list_parameters).await
+    }
+
+    async fn create(&self, entity: Entity) -> Result<(), RepositoryError> {
+        self.note([entity.id()]);
+        self.repository.create(entity).await
+    }
+
+    async fn create_many(&self, entities: Vec<Entity>) -> Result<(), RepositoryError> {
+        self.note(entities.iter().map(HasId::id));
+        self.repository.create_many(entities).await
+    }
+
+    async fn update(&self, entity: Entity) -> Result<(), RepositoryError> {
+        self.note([entity.id()]);
+        self.repository.update(entity).await
+    }
+
+    async fn overwrite(&self, entity: Entity) -> Result<(), RepositoryError> {
+        self.note([entity.id()]);
+        self.repository.overwrite(entity).await
+    }
+
+    async fn get_by_id(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        self.repository.get_by_id(id).await
+    }
+
+    async fn get_by_id_including_deleted(
+        &self,
+        id: &ID,
+    ) -> Result<Option<Entity>, RepositoryError> {
+        self.repository.get_by_id_including_deleted(id).await
+    }
+
+    async fn get_for_update(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        self.repository.get_for_update(id).await
+    }
+
+    async fn delete(&self, id: &ID) -> Result<(), RepositoryError> {
+        self.note([id.clone()]);
+        self.repository.delete(id).await
+    }
+
+    async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        self.note_everything();
+        self.repository.purge_deleted(deleted_before).await
+    }
+
+    /// Scoped afresh, so writes within the other transaction are forgotten when that one commits
+    fn within(
+        self: Arc<Self>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<ID, Entity> + Send + Sync> {
+        self.cache.clone().within(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::in_memory_repository::InMemoryRepository;
+    use crate::persistence::unit_of_work::UnitOfWork;
+    use crate::petty_matters::topic::{Topic, TopicId};
+
+    fn cached(
+        repository: Arc<InMemoryRepository<TopicId, Topic>>,
+    ) -> Arc<CachedRepository<InMemoryRepository<TopicId, Topic>, TopicId, Topic>> {
+        Arc::new(CachedRepository::new(repository, 100, &Seconds(60)))
+    }
+
+    #[tokio::test]
+    async fn created_entities_show_up_in_cached_listings() -> Result<(), AnyError> {
+        let repository = cached(Arc::new(InMemoryRepository::new()));
+        let topic = Topic::default();
+        repository.list(ListParameters::default()).await?;
+        repository.get_by_id(&topic.id).await?;
+
+        repository.create(topic.clone()).await?;
+
+        assert!(
+            repository
+                .list(ListParameters::default())
+                .await
+                .is_ok_and(|page| page.items == vec![topic.clone()])
+        );
+        assert!(
+            repository
+                .get_by_id(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_some())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn updates_replace_the_cached_entity() -> Result<(), AnyError> {
+        let repository = cached(Arc::new(InMemoryRepository::new()));
+        let mut topic = Topic::default();
+        repository.create(topic.clone()).await?;
+        repository.get_by_id(&topic.id).await?;
+
+        topic.edit("Amended".to_string(), "Amended".to_string());
+        repository.update(topic.clone()).await?;
+
+        assert!(
+            repository
+                .get_by_id(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_some_and(|t| t.title == "Amended"))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_within_a_transaction_are_visible_once_committed() -> Result<(), AnyError> {
+        let repository = cached(Arc::new(InMemoryRepository::new()));
+        let topic = Topic::default();
+        repository.list(ListParameters::default()).await?;
+        let transaction = UnitOfWork::default().begin().await?;
+        let scoped = repository.clone().within(&transaction);
+        scoped.create(topic.clone()).await?;
+        drop(scoped);
+
+        transaction.commit().await?;
+
+        assert!(
+            repository
+                .list(ListParameters::default())
+                .await
+                .is_ok_and(|page| page.total_count == 1)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn committing_forgets_only_the_entities_written_within() -> Result<(), AnyError> {
+        let inner = Arc::new(InMemoryRepository::new());
+        let repository = cached(inner.clone());
+        let (mut untouched, mut written) = (Topic::default(), Topic::default());
+        repository.create(untouched.clone()).await?;
+        repository.create(written.clone()).await?;
+        repository.get_by_id(&untouched.id).await?;
+        repository.get_by_id(&written.id).await?;
+        untouched.edit("Behind the cache".to_string(), "Untouched".to_string());
+        inner.update(untouched.clone()).await?;
+        let transaction = UnitOfWork::default().begin().await?;
+        let scoped = repository.clone().within(&transaction);
+        written.edit("Amended".to_string(), "Amended".to_string());
+        scoped.update(written.clone()).await?;
+        drop(scoped);
+
+        transaction.commit().await?;
+
+        assert!(
+            repository
+                .get_by_id(&untouched.id)
+                .await
+                .is_ok_and(|result| result.is_some_and(|t| t.title != "Behind the cache"))
+        );
+        assert!(
+            repository
+                .get_by_id(&written.id)
+                .await
+                .is_ok_and(|result| result.is_some_and(|t| t.title == "Amended"))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_entities_are_not_cached() -> Result<(), AnyError> {
+        let inner = Arc::new(InMemoryRepository::new());
+        let repository = cached(inner.clone());
+        let topic = Topic::default();
+        repository.get_by_id(&topic.id).await?;
+
+        inner.create(topic.clone()).await?;
+
+        assert!(
+            repository
+                .get_by_id(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_some())
+        );
+
+        Ok(())
+    }
+}
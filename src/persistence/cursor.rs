@@ -0,0 +1,179 @@
+use crate::persistence::filter::FilterableAttributes;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+static SEPARATOR: char = '\u{1f}';
+static ID_FIELD: &str = "id";
+
+/// A typed value of an entity attribute, comparable across backends.
+/// `Null` is declared last so it sorts after every other value, like `NULLS LAST` in SQL
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum FieldValue {
+    Integer(i64),
+    Text(String),
+    Timestamp(DateTime<Utc>),
+    Uuid(Uuid),
+    Null,
+}
+
+impl FieldValue {
+    fn encode(&self) -> String {
+        match self {
+            Self::Integer(i) => format!("i:{i}"),
+            Self::Text(t) => format!("t:{t}"),
+            Self::Timestamp(ts) => format!("d:{}", ts.to_rfc3339()),
+            Self::Uuid(u) => format!("u:{u}"),
+            Self::Null => "n:".to_string(),
+        }
+    }
+
+    fn decode(raw: &str) -> Option<Self> {
+        let (tag, value) = raw.split_once(':')?;
+        match tag {
+            "i" => value.parse().ok().map(Self::Integer),
+            "t" => Some(Self::Text(value.to_string())),
+            "d" => DateTime::parse_from_rfc3339(value)
+                .ok()
+                .map(|ts| Self::Timestamp(ts.with_timezone(&Utc))),
+            "u" => Uuid::parse_str(value).ok().map(Self::Uuid),
+            "n" => Some(Self::Null),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Into<Self>> From<Option<T>> for FieldValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<DateTime<Utc>> for FieldValue {
+    fn from(value: DateTime<Utc>) -> Self {
+        Self::Timestamp(value)
+    }
+}
+
+impl From<Uuid> for FieldValue {
+    fn from(value: Uuid) -> Self {
+        Self::Uuid(value)
+    }
+}
+
+impl From<u32> for FieldValue {
+    fn from(value: u32) -> Self {
+        Self::Integer(i64::from(value))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CursorDirection {
+    After,
+    Before,
+}
+
+/// Position within an ordered listing: the value of the `order_by` column and the id of a row,
+/// so the next query can continue right after (or before) it instead of skipping with an OFFSET
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Cursor {
+    pub direction: CursorDirection,
+    pub value: FieldValue,
+    pub id: FieldValue,
+}
+
+impl Cursor {
+    pub fn after<T: FilterableAttributes>(item: &T, sort_field: &str) -> Self {
+        Self {
+            direction: CursorDirection::After,
+            value: item.get_field_value(sort_field),
+            id: item.get_field_value(ID_FIELD),
+        }
+    }
+
+    pub fn before<T: FilterableAttributes>(item: &T, sort_field: &str) -> Self {
+        Self {
+            direction: CursorDirection::Before,
+            value: item.get_field_value(sort_field),
+            id: item.get_field_value(ID_FIELD),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            CursorDirection::After => "a",
+            CursorDirection::Before => "b",
+        };
+        let raw = format!(
+            "{direction}{SEPARATOR}{}{SEPARATOR}{}",
+            self.value.encode(),
+            self.id.encode()
+        );
+
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(token).ok()?;
+        let raw = String::from_utf8(bytes).ok()?;
+        let (direction, rest) = raw.split_once(SEPARATOR)?;
+        let (value, id) = rest.rsplit_once(SEPARATOR)?;
+        let direction = match direction {
+            "a" => CursorDirection::After,
+            "b" => CursorDirection::Before,
+            _ => return None,
+        };
+
+        Some(Self {
+            direction,
+            value: FieldValue::decode(value)?,
+            id: FieldValue::decode(id)?,
+        })
+    }
+
+    /// Whether `(value, id)` lies strictly past the cursor when walking in the given direction
+    pub fn is_beyond(&self, value: &FieldValue, id: &FieldValue, ascending: bool) -> bool {
+        let ordering = value.cmp(&self.value).then_with(|| id.cmp(&self.id));
+        if ascending {
+            ordering == Ordering::Greater
+        } else {
+            ordering == Ordering::Less
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_survives_an_encoding_round_trip() {
+        let cursor = Cursor {
+            direction: CursorDirection::Before,
+            value: FieldValue::Text("petty:matter\u{1f}with separators".to_string()),
+            id: FieldValue::Uuid(Uuid::new_v4()),
+        };
+
+        let decoded = Cursor::decode(&cursor.encode());
+
+        assert_eq!(decoded, Some(cursor));
+    }
+
+    #[test]
+    fn test_garbage_cursor_is_rejected() {
+        assert_eq!(Cursor::decode("definitely not a cursor"), None);
+    }
+
+    #[test]
+    fn test_null_sorts_after_values() {
+        assert!(FieldValue::Null > FieldValue::Timestamp(Utc::now()));
+    }
+}
@@ -0,0 +1,508 @@
+use crate::error::AnyError;
+use crate::persistence::filter::FilterableAttributes;
+use crate::persistence::in_memory_repository::InMemoryRepository;
+use crate::persistence::repository::{
+    HasId, ListParameters, Page, Repository, RepositoryError, SoftDeletable, Versioned,
+};
+use crate::persistence::search::{SearchHit, SearchIndex, Searchable};
+use crate::persistence::unit_of_work::{StagedRepository, Transaction};
+use crate::scheduler::base::Job;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+#[derive(Serialize, Deserialize)]
+enum LogEntry<ID, Entity> {
+    Put(Entity),
+    Delete(ID),
+}
+
+struct AppendLog {
+    file: File,
+    entries_since_snapshot: usize,
+}
+
+/// Keeps every entity in memory and makes each write durable before it becomes visible,
+/// by appending it to a log on local disk. On open, and whenever [`CompactionJob`] comes round,
+/// the log is folded into a snapshot of the whole collection and started afresh.
+///
+/// Files are stored as `<name>.snapshot.jsonl` and `<name>.log.jsonl` in the given directory.
+pub struct FileRepository<ID, Entity> {
+    entities: InMemoryRepository<ID, Entity>,
+    log: Mutex<AppendLog>,
+    snapshot_path: PathBuf,
+}
+
+impl From<std::io::Error> for RepositoryError {
+    fn from(err: std::io::Error) -> Self {
+        Self::GenericError(format!("Storage failure: {err}"))
+    }
+}
+
+impl From<serde_json::Error> for RepositoryError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::GenericError(format!("Corrupt record: {err}"))
+    }
+}
+
+pub async fn read_lines(path: &Path) -> Result<Vec<String>, RepositoryError> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Ok(contents.lines().map(str::to_string).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl<ID, Entity> FileRepository<ID, Entity>
+where
+    ID: Send + Sync + Eq + Hash + Clone + Serialize + DeserializeOwned + 'static,
+    Entity: Send
+        + Sync
+        + Clone
+        + HasId<ID>
+        + Versioned
+        + SoftDeletable
+        + FilterableAttributes
+        + Serialize
+        + DeserializeOwned
+        + 'static,
+{
+    /// Restores the collection from the snapshot and the log, then compacts them
+    pub async fn open(directory: &Path, name: &str) -> Result<Self, RepositoryError> {
+        tokio::fs::create_dir_all(directory).await?;
+        let snapshot_path = directory.join(format!("{name}.snapshot.jsonl"));
+        let log_path = directory.join(format!("{name}.log.jsonl"));
+
+        let mut entities = vec![];
+        for line in read_lines(&snapshot_path).await? {
+            entities.push(serde_json::from_str::<Entity>(&line)?);
+        }
+        let entities = InMemoryRepository::from_entities(entities);
+
+        let log_lines = read_lines(&log_path).await?;
+        let last_line_number = log_lines.len();
+        for (line_number, line) in (1..).zip(log_lines) {
+            match serde_json::from_str::<LogEntry<ID, Entity>>(&line) {
+                Ok(LogEntry::Put(entity)) => entities.put(entity).await,
+                Ok(LogEntry::Delete(id)) => entities.delete(&id).await?,
+                // A write interrupted by a crash was never acknowledged, so it is dropped
+                Err(e) if line_number == last_line_number => {
+                    eprintln!(
+                        "Discarding incomplete entry at the end of {}: {e}",
+                        log_path.display()
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .await?;
+        let repository = Self {
+            entities,
+            log: Mutex::new(AppendLog {
+                file,
+                entries_since_snapshot: last_line_number,
+            }),
+            snapshot_path,
+        };
+        repository.compact().await?;
+
+        Ok(repository)
+    }
+
+    async fn append(
+        &self,
+        log: &mut AppendLog,
+        entry: &LogEntry<ID, Entity>,
+    ) -> Result<(), RepositoryError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        log.file.write_all(&line).await?;
+        log.file.sync_data().await?;
+        log.entries_since_snapshot += 1;
+
+        Ok(())
+    }
+
+    /// Writes the snapshot next to the current one and swaps it in, so a crash midway leaves
+    /// either the old snapshot with its log or the new one
+    pub async fn compact(&self) -> Result<(), RepositoryError> {
+        let mut log = self.log.lock().await;
+        if log.entries_since_snapshot == 0 {
+            return Ok(());
+        }
+
+        let mut contents = vec![];
+        for entity in self.entities.entities().await {
+            contents.extend(serde_json::to_vec(&entity)?);
+            contents.push(b'\n');
+        }
+        let pending_snapshot_path = self.snapshot_path.with_extension("jsonl.tmp");
+        let mut pending_snapshot = File::create(&pending_snapshot_path).await?;
+        pending_snapshot.write_all(&contents).await?;
+        pending_snapshot.sync_all().await?;
+        tokio::fs::rename(&pending_snapshot_path, &self.snapshot_path).await?;
+
+        log.file.set_len(0).await?;
+        log.file.sync_all().await?;
+        log.entries_since_snapshot = 0;
+        drop(log);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<ID, Entity> Repository<ID, Entity> for FileRepository<ID, Entity>
+where
+    ID: Send + Sync + Eq + Hash + Clone + Serialize + DeserializeOwned + 'static,
+    Entity: Send
+        + Sync
+        + Clone
+        + HasId<ID>
+        + Versioned
+        + SoftDeletable
+        + FilterableAttributes
+        + Serialize
+        + DeserializeOwned
+        + 'static,
+{
+    async fn list(&self, list_parameters: ListParameters) -> Result<Page<Entity>, RepositoryError> {
+        self.entities.list(list_parameters).await
+    }
+
+    async fn create(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let mut log = self.log.lock().await;
+        if self
+            .entities
+            .get_by_id_including_deleted(&entity.id())
+            .await?
+            .is_some()
+        {
+            return Err(RepositoryError::Duplicate(
+                "An entity with the same id exists already".to_string(),
+            ));
+        }
+        self.append(&mut log, &LogEntry::Put(entity.clone()))
+            .await?;
+        self.entities.create(entity).await
+    }
+
+    async fn update(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let mut log = self.log.lock().await;
+        let Some(stored_entity) = self
+            .entities
+            .get_by_id_including_deleted(&entity.id())
+            .await?
+        else {
+            return Err(RepositoryError::NotFound(
+                "Cannot update an entity that does not exist".to_string(),
+            ));
+        };
+        if stored_entity.version() != entity.version() {
+            return Err(RepositoryError::Conflict(
+                "The entity was modified since it was read".to_string(),
+            ));
+        }
+        let mut updated_entity = entity.clone();
+        updated_entity.increment_version();
+        self.append(&mut log, &LogEntry::Put(updated_entity))
+            .await?;
+        self.entities.update(entity).await
+    }
+
+    async fn overwrite(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let mut log = self.log.lock().await;
+        if self
+            .entities
+            .get_by_id_including_deleted(&entity.id())
+            .await?
+            .is_none()
+        {
+            return Err(RepositoryError::NotFound(
+                "Cannot overwrite an entity that does not exist".to_string(),
+            ));
+        }
+        self.append(&mut log, &LogEntry::Put(entity.clone()))
+            .await?;
+        self.entities.overwrite(entity).await
+    }
+
+    async fn get_by_id(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        self.entities.get_by_id(id).await
+    }
+
+    async fn get_by_id_including_deleted(
+        &self,
+        id: &ID,
+    ) -> Result<Option<Entity>, RepositoryError> {
+        self.entities.get_by_id_including_deleted(id).await
+    }
+
+    async fn delete(&self, id: &ID) -> Result<(), RepositoryError> {
+        let mut log = self.log.lock().await;
+        self.append(&mut log, &LogEntry::Delete(id.clone())).await?;
+        self.entities.delete(id).await
+    }
+
+    async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let mut log = self.log.lock().await;
+        for entity in self.entities.entities().await {
+            if entity
+                .deleted_at()
+                .is_some_and(|deleted_at| deleted_at < deleted_before)
+            {
+                self.append(&mut log, &LogEntry::Delete(entity.id()))
+                    .await?;
+            }
+        }
+        let purged_count = self.entities.purge_deleted(deleted_before).await?;
+        drop(log);
+
+        Ok(purged_count)
+    }
+
+    fn within(
+        self: Arc<Self>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<ID, Entity> + Send + Sync> {
+        StagedRepository::scoped(self, transaction)
+    }
+}
+
+#[async_trait]
+impl<ID, Entity> SearchIndex<ID, Entity> for FileRepository<ID, Entity>
+where
+    ID: Send + Sync + Eq + Hash + Clone,
+    Entity: Send + Sync + Clone + HasId<ID> + Searchable,
+{
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit<Entity>>, RepositoryError> {
+        self.entities.search(query, limit).await
+    }
+}
+
+/// Folds a repository's log into its snapshot every so often, so the log doesn't grow
+/// without end between restarts
+pub struct CompactionJob<ID, Entity> {
+    pub repository: Arc<FileRepository<ID, Entity>>,
+}
+
+#[async_trait]
+impl<ID, Entity> Job for CompactionJob<ID, Entity>
+where
+    ID: Send + Sync + Eq + Hash + Clone + Serialize + DeserializeOwned + 'static,
+    Entity: Send
+        + Sync
+        + Clone
+        + HasId<ID>
+        + Versioned
+        + SoftDeletable
+        + FilterableAttributes
+        + Serialize
+        + DeserializeOwned
+        + 'static,
+{
+    async fn run(&self) -> Result<(), AnyError> {
+        Ok(self.repository.compact().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::repository_contract::repository_contract_tests;
+    use crate::petty_matters::comment::{Comment, CommentId};
+    use crate::petty_matters::topic::{Topic, TopicId};
+    use tempfile::TempDir;
+
+    async fn open(directory: &Path) -> Result<FileRepository<TopicId, Topic>, RepositoryError> {
+        FileRepository::open(directory, "topics").await
+    }
+
+    #[tokio::test]
+    async fn writes_survive_a_restart() -> Result<(), AnyError> {
+        let directory = TempDir::new()?;
+        let repository = open(directory.path()).await?;
+        let kept = Topic::default();
+        let mut edited = Topic::default();
+        let deleted = Topic::default();
+        for topic in [&kept, &edited, &deleted] {
+            repository.create(topic.clone()).await?;
+        }
+        edited.edit("Amended".to_string(), "Amended".to_string());
+        repository.update(edited.clone()).await?;
+        repository.delete(&deleted.id).await?;
+        drop(repository);
+
+        let repository = open(directory.path()).await?;
+
+        let get = |id| repository.get_by_id(id);
+        assert_eq!(get(&kept.id).await?, Some(kept.clone()));
+        assert!(
+            get(&edited.id)
+                .await?
+                .is_some_and(|topic| topic.title == "Amended" && topic.version == 1)
+        );
+        assert_eq!(get(&deleted.id).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compaction_keeps_every_entity() -> Result<(), AnyError> {
+        let directory = TempDir::new()?;
+        let repository = Arc::new(open(directory.path()).await?);
+        for _ in 0..5 {
+            repository.create(Topic::default()).await?;
+        }
+
+        CompactionJob {
+            repository: repository.clone(),
+        }
+        .run()
+        .await?;
+        let log = tokio::fs::metadata(directory.path().join("topics.log.jsonl")).await?;
+        drop(repository);
+        let repository = open(directory.path()).await?;
+        let page = repository.list(ListParameters::default()).await?;
+
+        assert_eq!(log.len(), 0);
+        assert_eq!(page.total_count, 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn an_incomplete_last_entry_is_discarded() -> Result<(), AnyError> {
+        let directory = TempDir::new()?;
+        let repository = open(directory.path()).await?;
+        let topic = Topic::default();
+        repository.create(topic.clone()).await?;
+        drop(repository);
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(directory.path().join("topics.log.jsonl"))
+            .await?;
+        log.write_all(b"{\"Put\":{\"id\":").await?;
+
+        let repository = open(directory.path()).await?;
+
+        let page = repository.list(ListParameters::default()).await?;
+        assert_eq!(page.items, vec![topic]);
+
+        Ok(())
+    }
+
+    /// A repository in a directory of its own, removed once the repository is dropped
+    struct OnDisk<ID, Entity> {
+        repository: FileRepository<ID, Entity>,
+        _directory: TempDir,
+    }
+
+    #[async_trait]
+    impl<ID, Entity> Repository<ID, Entity> for OnDisk<ID, Entity>
+    where
+        ID: Send + Sync + Eq + Hash + Clone + Serialize + DeserializeOwned + 'static,
+        Entity: Send
+            + Sync
+            + Clone
+            + HasId<ID>
+            + Versioned
+            + SoftDeletable
+            + FilterableAttributes
+            + Serialize
+            + DeserializeOwned
+            + 'static,
+    {
+        async fn list(
+            &self,
+            list_parameters: ListParameters,
+        ) -> Result<Page<Entity>, RepositoryError> {
+            self.repository.list(list_parameters).await
+        }
+
+        async fn create(&self, entity: Entity) -> Result<(), RepositoryError> {
+            self.repository.create(entity).await
+        }
+
+        async fn update(&self, entity: Entity) -> Result<(), RepositoryError> {
+            self.repository.update(entity).await
+        }
+
+        async fn overwrite(&self, entity: Entity) -> Result<(), RepositoryError> {
+            self.repository.overwrite(entity).await
+        }
+
+        async fn get_by_id(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+            self.repository.get_by_id(id).await
+        }
+
+        async fn get_by_id_including_deleted(
+            &self,
+            id: &ID,
+        ) -> Result<Option<Entity>, RepositoryError> {
+            self.repository.get_by_id_including_deleted(id).await
+        }
+
+        async fn delete(&self, id: &ID) -> Result<(), RepositoryError> {
+            self.repository.delete(id).await
+        }
+
+        async fn purge_deleted(
+            &self,
+            deleted_before: DateTime<Utc>,
+        ) -> Result<u64, RepositoryError> {
+            self.repository.purge_deleted(deleted_before).await
+        }
+
+        fn within(
+            self: Arc<Self>,
+            transaction: &Transaction,
+        ) -> Arc<dyn Repository<ID, Entity> + Send + Sync> {
+            StagedRepository::scoped(self, transaction)
+        }
+    }
+
+    async fn on_disk<ID, Entity>(
+        name: &str,
+    ) -> Result<Arc<dyn Repository<ID, Entity> + Send + Sync>, AnyError>
+    where
+        ID: Send + Sync + Eq + Hash + Clone + Serialize + DeserializeOwned + 'static,
+        Entity: Send
+            + Sync
+            + Clone
+            + HasId<ID>
+            + Versioned
+            + SoftDeletable
+            + FilterableAttributes
+            + Serialize
+            + DeserializeOwned
+            + 'static,
+    {
+        let directory = TempDir::new()?;
+        Ok(Arc::new(OnDisk {
+            repository: FileRepository::<ID, Entity>::open(directory.path(), name).await?,
+            _directory: directory,
+        }))
+    }
+
+    repository_contract_tests!(topic_contract, TopicId, Topic, on_disk("topics"));
+    repository_contract_tests!(comment_contract, CommentId, Comment, on_disk("comments"));
+}
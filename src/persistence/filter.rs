@@ -0,0 +1,379 @@
+use crate::persistence::cursor::FieldValue;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Display;
+use uuid::Uuid;
+
+static OPERATOR_SEPARATOR: &str = "__";
+static ALTERNATIVE_PREFIX: &str = "or.";
+pub static LIKE_ESCAPE: char = '\\';
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FieldType {
+    Integer,
+    Text,
+    Timestamp,
+    Uuid,
+}
+
+impl FieldType {
+    fn parse(self, raw: &str) -> Option<FieldValue> {
+        match self {
+            Self::Integer => raw.parse().ok().map(FieldValue::Integer),
+            Self::Text => Some(FieldValue::Text(raw.to_string())),
+            Self::Timestamp => DateTime::parse_from_rfc3339(raw)
+                .map(|ts| ts.with_timezone(&Utc))
+                .ok()
+                .or_else(|| {
+                    NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                        .map(|midnight| midnight.and_utc())
+                })
+                .map(FieldValue::Timestamp),
+            Self::Uuid => Uuid::parse_str(raw).ok().map(FieldValue::Uuid),
+        }
+    }
+}
+
+/// Typed access to entity attributes, shared by filtering and cursor pagination.
+/// Field names double as column names in the relational backend.
+pub trait FilterableAttributes {
+    const FIELDS: &'static [(&'static str, FieldType)];
+
+    fn get_field_value(&self, field: &str) -> FieldValue;
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Filter {
+    Eq(String, FieldValue),
+    Ne(String, FieldValue),
+    /// SQL `LIKE` semantics: `%` matches any run of characters, `_` exactly one, `\` escapes
+    Like(String, String),
+    /// Inclusive on both ends; a missing end is unbounded
+    Range {
+        field: String,
+        from: Option<FieldValue>,
+        to: Option<FieldValue>,
+    },
+    In(String, Vec<FieldValue>),
+    And(Vec<Self>),
+    Or(Vec<Self>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FilterError {
+    UnknownField(String),
+    UnknownOperator(String),
+    InvalidValue { field: String, value: String },
+    UnknownSortField(String),
+    InvalidCursor(String),
+}
+
+impl Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownField(field) => write!(f, "Cannot filter by unknown field '{field}'"),
+            Self::UnknownOperator(operator) => {
+                write!(f, "Unknown filter operator '{operator}'")
+            }
+            Self::InvalidValue { field, value } => {
+                write!(f, "'{value}' is not a valid value for '{field}'")
+            }
+            Self::UnknownSortField(field) => write!(f, "Cannot sort by unknown field '{field}'"),
+            Self::InvalidCursor(cursor) => write!(f, "'{cursor}' is not a position in the listing"),
+        }
+    }
+}
+
+impl Error for FilterError {}
+
+impl Filter {
+    /// Parses query parameters such as `title__like=%25cat%25&creation_time__gte=2025-01-01`.
+    /// A bare field name means equality; the operators are `ne`, `like`, `gte`, `lte` and `in`
+    /// (comma separated). Parameters are combined with AND, except those prefixed with `or.`
+    /// which are grouped into a single OR.
+    pub fn from_query_params<T: FilterableAttributes>(
+        params: &BTreeMap<String, String>,
+    ) -> Result<Option<Self>, FilterError> {
+        let mut conjunction = vec![];
+        let mut alternatives = vec![];
+        for (key, raw_value) in params {
+            match key.strip_prefix(ALTERNATIVE_PREFIX) {
+                Some(key) => alternatives.push(Self::parse_param::<T>(key, raw_value)?),
+                None => conjunction.push(Self::parse_param::<T>(key, raw_value)?),
+            }
+        }
+        if !alternatives.is_empty() {
+            conjunction.push(Self::Or(alternatives));
+        }
+
+        Ok(match conjunction.len() {
+            0 => None,
+            1 => conjunction.pop(),
+            _ => Some(Self::And(conjunction)),
+        })
+    }
+
+    fn parse_param<T: FilterableAttributes>(
+        key: &str,
+        raw_value: &str,
+    ) -> Result<Self, FilterError> {
+        let (field, operator) = key.split_once(OPERATOR_SEPARATOR).unwrap_or((key, "eq"));
+        let field_type = T::FIELDS
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, field_type)| *field_type)
+            .ok_or_else(|| FilterError::UnknownField(field.to_string()))?;
+        let parse = |raw: &str| {
+            field_type
+                .parse(raw)
+                .ok_or_else(|| FilterError::InvalidValue {
+                    field: field.to_string(),
+                    value: raw.to_string(),
+                })
+        };
+
+        Ok(match operator {
+            "eq" => Self::Eq(field.to_string(), parse(raw_value)?),
+            "ne" => Self::Ne(field.to_string(), parse(raw_value)?),
+            "like" if field_type == FieldType::Text => {
+                Self::Like(field.to_string(), raw_value.to_string())
+            }
+            "gte" => Self::Range {
+                field: field.to_string(),
+                from: Some(parse(raw_value)?),
+                to: None,
+            },
+            "lte" => Self::Range {
+                field: field.to_string(),
+                from: None,
+                to: Some(parse(raw_value)?),
+            },
+            "in" => Self::In(
+                field.to_string(),
+                raw_value
+                    .split(',')
+                    .map(parse)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            _ => return Err(FilterError::UnknownOperator(operator.to_string())),
+        })
+    }
+
+    /// Combines with another filter, both of which must hold
+    #[must_use]
+    pub fn and(self, other: Self) -> Self {
+        match self {
+            Self::And(mut filters) => {
+                filters.push(other);
+                Self::And(filters)
+            }
+            filter => Self::And(vec![filter, other]),
+        }
+    }
+
+    /// Evaluates the filter the way SQL would: comparisons against NULL never match
+    pub fn matches<T: FilterableAttributes>(&self, entity: &T) -> bool {
+        let non_null =
+            |field: &str| Some(entity.get_field_value(field)).filter(|v| *v != FieldValue::Null);
+        match self {
+            Self::Eq(field, value) => non_null(field).is_some_and(|v| v == *value),
+            Self::Ne(field, value) => non_null(field).is_some_and(|v| v != *value),
+            Self::Like(field, pattern) => non_null(field).is_some_and(|v| match v {
+                FieldValue::Text(text) => is_like(&text, pattern),
+                _ => false,
+            }),
+            Self::Range { field, from, to } => non_null(field).is_some_and(|v| {
+                from.as_ref().is_none_or(|from| v >= *from) && to.as_ref().is_none_or(|to| v <= *to)
+            }),
+            Self::In(field, values) => non_null(field).is_some_and(|v| values.contains(&v)),
+            Self::And(filters) => filters.iter().all(|filter| filter.matches(entity)),
+            Self::Or(filters) => filters.iter().any(|filter| filter.matches(entity)),
+        }
+    }
+}
+
+/// Text to be matched as is within a `LIKE` pattern, with its wildcards escaped
+pub fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '%' || c == '_' || c == LIKE_ESCAPE {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn is_escaped_match(escaped: Option<&char>, actual: Option<&char>) -> bool {
+    escaped.is_some() && escaped == actual
+}
+
+/// Case-sensitive `LIKE` matching, as done by Postgres
+fn is_like(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == LIKE_ESCAPE && is_escaped_match(pattern.get(p + 1), text.get(t)) => {
+                p += 2;
+                t += 1;
+            }
+            Some(&c) if c != LIKE_ESCAPE && (c == '_' || Some(&c) == text.get(t)) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched_until)) => {
+                    p = star + 1;
+                    t = matched_until + 1;
+                    backtrack = Some((star, matched_until + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern
+        .get(p..)
+        .is_some_and(|rest| rest.iter().all(|c| *c == '%'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+
+    struct Stub {
+        title: String,
+        score: i64,
+        edited: Option<DateTime<Utc>>,
+    }
+
+    impl FilterableAttributes for Stub {
+        const FIELDS: &'static [(&'static str, FieldType)] = &[
+            ("title", FieldType::Text),
+            ("score", FieldType::Integer),
+            ("edited", FieldType::Timestamp),
+        ];
+
+        fn get_field_value(&self, field: &str) -> FieldValue {
+            match field {
+                "title" => self.title.clone().into(),
+                "score" => FieldValue::Integer(self.score),
+                "edited" => self.edited.into(),
+                _ => FieldValue::Null,
+            }
+        }
+    }
+
+    fn stub() -> Stub {
+        Stub {
+            title: "The neighbour's cat".to_string(),
+            score: 7,
+            edited: None,
+        }
+    }
+
+    fn parse(params: &[(&str, &str)]) -> Result<Option<Filter>, FilterError> {
+        let params = params
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        Filter::from_query_params::<Stub>(&params)
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        assert_eq!(
+            parse(&[("colour", "red")]),
+            Err(FilterError::UnknownField("colour".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_values_are_validated_against_the_field_type() {
+        assert!(matches!(
+            parse(&[("score__gte", "lots")]),
+            Err(FilterError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_like_is_only_allowed_on_text() {
+        assert_eq!(
+            parse(&[("score__like", "7%")]),
+            Err(FilterError::UnknownOperator("like".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parameters_are_combined_with_and() -> Result<(), AnyError> {
+        let filter = parse(&[("title__like", "%cat"), ("score__lte", "6")])?
+            .ok_or("Filter should be present")?;
+
+        assert!(!filter.matches(&stub()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_or_prefixed_parameters_are_alternatives() -> Result<(), AnyError> {
+        let filter = parse(&[("or.title__like", "%cat"), ("or.score__lte", "6")])?
+            .ok_or("Filter should be present")?;
+
+        assert!(filter.matches(&stub()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_matches_any_listed_value() -> Result<(), AnyError> {
+        let filter = parse(&[("score__in", "1,7,9")])?.ok_or("Filter should be present")?;
+
+        assert!(filter.matches(&stub()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparisons_against_null_never_match() {
+        let after = Filter::Range {
+            field: "edited".to_string(),
+            from: Some(Utc::now().into()),
+            to: None,
+        };
+        let not_now = Filter::Ne("edited".to_string(), Utc::now().into());
+
+        assert!(!after.matches(&stub()));
+        assert!(!not_now.matches(&stub()));
+    }
+
+    #[test]
+    fn test_like_follows_sql_semantics() {
+        assert!(is_like("The neighbour's cat", "%cat"));
+        assert!(is_like("The neighbour's cat", "The%'s ca_"));
+        assert!(!is_like("The neighbour's cat", "the%"));
+        assert!(is_like("100%", "100\\%"));
+        assert!(!is_like("1000", "100\\%"));
+        assert!(is_like("", "%"));
+        assert!(!is_like("cat", ""));
+    }
+
+    #[test]
+    fn escaped_text_matches_only_itself() {
+        let pattern = format!("%{}%", escape_like("0%_\\"));
+
+        assert!(is_like("100%_\\ off", &pattern));
+        assert!(!is_like("1000a\\ off", &pattern));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::persistence::schema::prepare_schema;
+use crate::time::{Backoff, Seconds};
+use sea_orm::{DatabaseConnection, DbErr};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+static INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+static MAX_BACKOFF: Duration = Duration::from_mins(1);
+
+/// What the pages read to tell visitors that the Ministry is read-only for now
+pub static DATABASE_HEALTH: LazyLock<Arc<DatabaseHealth>> =
+    LazyLock::new(|| Arc::new(DatabaseHealth::default()));
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DatabaseStatus {
+    Available,
+    /// Reads may still be served from cache, but nothing can be written
+    Unavailable,
+}
+
+impl Display for DatabaseStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Available => write!(f, "available"),
+            Self::Unavailable => write!(f, "unavailable"),
+        }
+    }
+}
+
+/// Whether the schema was prepared on startup, or has to wait for the database to answer
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SchemaState {
+    Prepared,
+    Pending { apply_migrations: bool },
+}
+
+pub struct DatabaseHealth {
+    is_available: AtomicBool,
+}
+
+impl Default for DatabaseHealth {
+    fn default() -> Self {
+        Self {
+            is_available: AtomicBool::new(true),
+        }
+    }
+}
+
+impl DatabaseHealth {
+    pub fn status(&self) -> DatabaseStatus {
+        if self.is_available.load(Ordering::Acquire) {
+            DatabaseStatus::Available
+        } else {
+            DatabaseStatus::Unavailable
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.status() == DatabaseStatus::Unavailable
+    }
+
+    /// Records the outcome of a check, returning whether the status changed with it
+    pub fn record(&self, status: DatabaseStatus) -> bool {
+        let is_available = status == DatabaseStatus::Available;
+        self.is_available.swap(is_available, Ordering::AcqRel) != is_available
+    }
+}
+
+/// Pings the database every `interval` while it answers, and more eagerly with backoff while
+/// it doesn't. The connection pool opens fresh connections on its own once the database
+/// is back, so a successful ping is all a reconnection takes. A schema still pending
+/// is prepared the first time the database answers, which only counts as available once it is.
+pub async fn start_health_check(
+    db: DatabaseConnection,
+    health: Arc<DatabaseHealth>,
+    interval: Seconds,
+    mut schema: SchemaState,
+) {
+    let interval = Duration::from_secs(u64::from(interval.0));
+    let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+    loop {
+        let wait = match check(&db, &mut schema).await {
+            Ok(()) => {
+                if health.record(DatabaseStatus::Available) {
+                    println!("Database connection restored, accepting writes again");
+                }
+                backoff.reset();
+                interval
+            }
+            Err(e) => {
+                if health.record(DatabaseStatus::Unavailable) {
+                    eprintln!("Database unavailable: {e}, switching to read-only mode");
+                }
+                backoff.next()
+            }
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+async fn check(db: &DatabaseConnection, schema: &mut SchemaState) -> Result<(), DbErr> {
+    db.ping().await?;
+    if let SchemaState::Pending { apply_migrations } = *schema {
+        if let Err(e) = prepare_schema(db, apply_migrations).await {
+            eprintln!("Database answering, but its schema isn't ready: {e}");
+            return Err(e);
+        }
+        *schema = SchemaState::Prepared;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::rdbms::connect;
+
+    #[test]
+    fn only_changes_of_status_are_reported() {
+        let health = DatabaseHealth::default();
+
+        let outcomes = [
+            health.record(DatabaseStatus::Available),
+            health.record(DatabaseStatus::Unavailable),
+            health.record(DatabaseStatus::Unavailable),
+            health.record(DatabaseStatus::Available),
+        ];
+
+        assert_eq!(outcomes, [false, true, false, true]);
+        assert!(!health.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn a_pending_schema_is_prepared_once_the_database_answers() -> Result<(), AnyError> {
+        let db = connect(&"sqlite::memory:".to_string()).await?;
+        let mut behind = SchemaState::Pending {
+            apply_migrations: false,
+        };
+        let mut migrated = SchemaState::Pending {
+            apply_migrations: true,
+        };
+
+        let refused = check(&db, &mut behind).await;
+        let prepared = check(&db, &mut migrated).await;
+
+        assert!(refused.is_err());
+        assert_eq!(
+            behind,
+            SchemaState::Pending {
+                apply_migrations: false
+            }
+        );
+        assert!(prepared.is_ok());
+        assert_eq!(migrated, SchemaState::Prepared);
+
+        Ok(())
+    }
+}
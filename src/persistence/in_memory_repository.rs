@@ -1,6 +1,14 @@
-use crate::persistence::repository::{HasId, ListParameters, Page, Repository, RepositoryError};
+use crate::persistence::filter::FilterableAttributes;
+use crate::persistence::repository::{
+    HasId, ListParameters, Page, Repository, RepositoryError, SoftDeletable, Versioned,
+};
+use crate::persistence::search::{SearchHit, SearchIndex, Searchable, search_entities};
+use crate::persistence::unit_of_work::{StagedRepository, Transaction};
+use crate::views::pagination::Ordering;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::hash::Hash;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -21,43 +29,159 @@ where
     }
 }
 
+impl<ID, Entity> InMemoryRepository<ID, Entity>
+where
+    ID: Eq + Hash + Clone,
+    Entity: Clone + HasId<ID>,
+{
+    pub(crate) fn from_entities(entities: Vec<Entity>) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(
+                entities
+                    .into_iter()
+                    .map(|entity| (entity.id(), entity))
+                    .collect(),
+            )),
+        }
+    }
+
+    pub(crate) async fn entities(&self) -> Vec<Entity> {
+        self.store.lock().await.values().cloned().collect()
+    }
+
+    /// Stores the entity as given, whether or not it exists already
+    pub(crate) async fn put(&self, entity: Entity) {
+        self.store.lock().await.insert(entity.id(), entity);
+    }
+}
+
 #[async_trait]
 impl<ID, Entity> Repository<ID, Entity> for InMemoryRepository<ID, Entity>
 where
-    ID: Send + Sync + Eq + Hash + Clone,
-    Entity: Send + Sync + Clone + HasId<ID> + FilterableAttributes<Output = Option<String>>,
+    ID: Send + Sync + Eq + Hash + Clone + 'static,
+    Entity: Send
+        + Sync
+        + Clone
+        + HasId<ID>
+        + Versioned
+        + SoftDeletable
+        + FilterableAttributes
+        + 'static,
 {
-    #[allow(clippy::significant_drop_tightening)]
     async fn list(&self, list_parameters: ListParameters) -> Result<Page<Entity>, RepositoryError> {
-        let offset = list_parameters.calculate_offset();
+        let sort_field = list_parameters.sort_field::<Entity>();
+        let is_ascending = list_parameters
+            .is_walking_ascending(list_parameters.sort_ordering::<Entity>() == Ordering::Ascending);
         let collection = self.store.lock().await;
-        let key_value_pairs = collection.values();
-        let page = Page {
-            current_page_number: list_parameters.page_number,
-            size: list_parameters.page_size,
-            total_count: key_value_pairs.len() as u64,
-            items: key_value_pairs
-                .filter(|entity| {
-                    list_parameters.filters.as_ref().is_none_or(|filters| {
-                        filters.iter().all(|(key, val)| {
-                            entity.get_field_value(key).is_some_and(|v| v == *val)
-                        })
-                    })
+        let mut matching_entities: Vec<Entity> = collection
+            .values()
+            .filter(|entity| list_parameters.include_deleted || !entity.is_deleted())
+            .filter(|entity| {
+                list_parameters
+                    .filters
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(*entity))
+            })
+            .cloned()
+            .collect();
+        drop(collection);
+
+        let total_count = matching_entities.len() as u64;
+        matching_entities.sort_by(|a, b| {
+            let ordering = a
+                .get_field_value(sort_field)
+                .cmp(&b.get_field_value(sort_field))
+                .then_with(|| a.get_field_value("id").cmp(&b.get_field_value("id")));
+            if is_ascending {
+                ordering
+            } else {
+                ordering.reverse()
+            }
+        });
+        let rows = matching_entities
+            .into_iter()
+            .filter(|entity| {
+                list_parameters.cursor.as_ref().is_none_or(|cursor| {
+                    cursor.is_beyond(
+                        &entity.get_field_value(sort_field),
+                        &entity.get_field_value("id"),
+                        is_ascending,
+                    )
                 })
-                .skip(offset)
-                .take(list_parameters.calculate_limit())
-                .cloned()
-                .collect(),
-        };
-        Ok(page)
+            })
+            .skip(if list_parameters.cursor.is_some() {
+                0
+            } else {
+                list_parameters.calculate_offset()
+            })
+            .take(list_parameters.calculate_limit() + 1)
+            .collect();
+
+        Ok(Page::from_rows(
+            &list_parameters,
+            sort_field,
+            total_count,
+            rows,
+        ))
     }
 
     async fn create(&self, entity: Entity) -> Result<(), RepositoryError> {
-        self.store.lock().await.insert(entity.id(), entity);
+        match self.store.lock().await.entry(entity.id()) {
+            Entry::Occupied(_) => Err(RepositoryError::Duplicate(
+                "An entity with the same id exists already".to_string(),
+            )),
+            Entry::Vacant(slot) => {
+                slot.insert(entity);
+                Ok(())
+            }
+        }
+    }
+
+    async fn update(&self, mut entity: Entity) -> Result<(), RepositoryError> {
+        let mut collection = self.store.lock().await;
+        let Some(stored_entity) = collection.get_mut(&entity.id()) else {
+            return Err(RepositoryError::NotFound(
+                "Cannot update an entity that does not exist".to_string(),
+            ));
+        };
+        if stored_entity.version() != entity.version() {
+            return Err(RepositoryError::Conflict(
+                "The entity was modified since it was read".to_string(),
+            ));
+        }
+        entity.increment_version();
+        *stored_entity = entity;
+        drop(collection);
+
         Ok(())
     }
 
+    async fn overwrite(&self, entity: Entity) -> Result<(), RepositoryError> {
+        match self.store.lock().await.entry(entity.id()) {
+            Entry::Occupied(mut stored_entity) => {
+                stored_entity.insert(entity);
+                Ok(())
+            }
+            Entry::Vacant(_) => Err(RepositoryError::NotFound(
+                "Cannot overwrite an entity that does not exist".to_string(),
+            )),
+        }
+    }
+
     async fn get_by_id(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        Ok(self
+            .store
+            .lock()
+            .await
+            .get(id)
+            .filter(|entity| !entity.is_deleted())
+            .cloned())
+    }
+
+    async fn get_by_id_including_deleted(
+        &self,
+        id: &ID,
+    ) -> Result<Option<Entity>, RepositoryError> {
         Ok(self.store.lock().await.get(id).cloned())
     }
 
@@ -65,23 +189,94 @@ where
         self.store.lock().await.remove(id);
         Ok(())
     }
+
+    async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let mut collection = self.store.lock().await;
+        let count_before = collection.len();
+        collection.retain(|_, entity| {
+            entity
+                .deleted_at()
+                .is_none_or(|deleted_at| deleted_at >= deleted_before)
+        });
+        let purged_count = count_before - collection.len();
+        drop(collection);
+
+        Ok(purged_count as u64)
+    }
+
+    fn within(
+        self: Arc<Self>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<ID, Entity> + Send + Sync> {
+        StagedRepository::scoped(self, transaction)
+    }
 }
 
+#[async_trait]
+impl<ID, Entity> SearchIndex<ID, Entity> for InMemoryRepository<ID, Entity>
+where
+    ID: Send + Sync + Eq + Hash + Clone,
+    Entity: Send + Sync + Clone + HasId<ID> + Searchable,
+{
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit<Entity>>, RepositoryError> {
+        Ok(search_entities(self.entities().await, query, limit))
+    }
+}
 #[cfg(test)]
 mod tests {
-    use crate::persistence::in_memory_repository::{FilterableAttributes, InMemoryRepository};
-    use crate::persistence::repository::{HasId, ListParameters, PageNumber, PageSize, Repository};
+    use crate::error::AnyError;
+    use crate::persistence::cursor::FieldValue;
+    use crate::persistence::filter::{FieldType, Filter, FilterableAttributes};
+    use crate::persistence::in_memory_repository::InMemoryRepository;
+    use crate::persistence::repository::{
+        HasId, ListParameters, PageNumber, PageSize, Repository, RepositoryError, SoftDeletable,
+        Versioned,
+    };
+    use crate::persistence::repository_contract::repository_contract_tests;
+    use crate::petty_matters::comment::{Comment, CommentId};
+    use crate::petty_matters::topic::{Topic, TopicId};
+    use chrono::{DateTime, Duration, Utc};
+    use std::hash::Hash;
+    use std::sync::Arc;
 
     type StubId = i32;
 
     #[derive(Clone)]
     struct StubEntity {
         id: StubId,
+        name: String,
+        version: u32,
+        deleted_at: Option<DateTime<Utc>>,
     }
 
     impl StubEntity {
         fn new(id: StubId) -> Self {
-            Self { id }
+            Self {
+                id,
+                name: String::new(),
+                version: 0,
+                deleted_at: None,
+            }
+        }
+    }
+
+    impl SoftDeletable for StubEntity {
+        fn deleted_at(&self) -> Option<DateTime<Utc>> {
+            self.deleted_at
+        }
+    }
+
+    impl Versioned for StubEntity {
+        fn version(&self) -> u32 {
+            self.version
+        }
+
+        fn increment_version(&mut self) {
+            self.version += 1;
         }
     }
 
@@ -92,72 +287,114 @@ mod tests {
     }
 
     impl FilterableAttributes for StubEntity {
-        type Output = Option<String>;
+        const FIELDS: &'static [(&'static str, FieldType)] =
+            &[("id", FieldType::Integer), ("name", FieldType::Text)];
 
-        fn get_field_value(&self, _field: &str) -> Self::Output {
-            None
+        fn get_field_value(&self, field: &str) -> FieldValue {
+            match field {
+                "id" => FieldValue::Integer(i64::from(self.id)),
+                "name" => self.name.clone().into(),
+                _ => FieldValue::Null,
+            }
         }
     }
 
     #[tokio::test]
-    async fn get_by_id_returns_result() {
+    async fn get_by_id_returns_result() -> Result<(), AnyError> {
         let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
         let stub_entity = StubEntity::new(1);
-        repository
-            .create(stub_entity)
-            .await
-            .expect("Failed to create entity");
+        repository.create(stub_entity).await?;
 
-        let result = repository
-            .get_by_id(&1)
-            .await
-            .expect("Failed to retrieve entity");
+        let result = repository.get_by_id(&1).await?;
 
         assert!(result.is_some_and(|e| e.id() == 1));
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn get_by_id_returns_none_if_not_found() {
+    async fn get_by_id_returns_none_if_not_found() -> Result<(), AnyError> {
         let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
 
-        let result = repository
-            .get_by_id(&1)
-            .await
-            .expect("Failed to retrieve entity");
+        let result = repository.get_by_id(&1).await?;
 
         assert!(result.is_none());
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn delete_by_id_removes_entity() {
+    async fn delete_by_id_removes_entity() -> Result<(), AnyError> {
         let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
         let stub_entity = StubEntity::new(1);
-        repository
-            .create(stub_entity.clone())
-            .await
-            .expect("Failed to create entity");
-        repository
-            .delete(&stub_entity.id)
-            .await
-            .expect("Failed to create entity");
+        repository.create(stub_entity.clone()).await?;
+        repository.delete(&stub_entity.id).await?;
 
-        let result = repository
-            .get_by_id(&1)
-            .await
-            .expect("Failed to retrieve entity");
+        let result = repository.get_by_id(&1).await?;
 
         assert!(result.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_replaces_the_stored_entity() -> Result<(), AnyError> {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        let mut stub_entity = StubEntity::new(1);
+        repository.create(stub_entity.clone()).await?;
+        stub_entity.name = "Renamed".to_string();
+
+        repository.update(stub_entity).await?;
+
+        let result = repository.get_by_id(&1).await?;
+        assert!(result.is_some_and(|e| e.name == "Renamed"));
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn list_yields_a_collection_of_give_size_with_an_offset() {
+    async fn update_increments_the_version() -> Result<(), AnyError> {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        let stub_entity = StubEntity::new(1);
+        repository.create(stub_entity.clone()).await?;
+
+        repository.update(stub_entity).await?;
+
+        let result = repository.get_by_id(&1).await?;
+        assert!(result.is_some_and(|e| e.version() == 1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_with_a_stale_version_is_a_conflict() -> Result<(), AnyError> {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        let stub_entity = StubEntity::new(1);
+        repository.create(stub_entity.clone()).await?;
+        repository.update(stub_entity.clone()).await?;
+
+        let result = repository.update(stub_entity).await;
+
+        assert!(matches!(result, Err(RepositoryError::Conflict(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_fails_if_entity_does_not_exist() {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+
+        let result = repository.update(StubEntity::new(1)).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn list_yields_a_collection_of_give_size_with_an_offset() -> Result<(), AnyError> {
         let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
         for iteration in 1..10 {
             let stub_entity = StubEntity::new(iteration);
-            repository
-                .create(stub_entity)
-                .await
-                .expect("Failed to create entity");
+            repository.create(stub_entity).await?;
         }
 
         let list_parameters = ListParameters {
@@ -166,19 +403,168 @@ mod tests {
             filters: None,
             order_by: None,
             ordering: None,
+            cursor: None,
+            include_deleted: false,
         };
+        let page = repository.list(list_parameters).await?;
+
+        assert_eq!(page.items.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_continues_after_the_cursor_without_skipping_or_repeating() -> Result<(), AnyError>
+    {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        for iteration in 1..=5 {
+            repository.create(StubEntity::new(iteration)).await?;
+        }
+        let first_page = repository
+            .list(ListParameters {
+                page_size: PageSize(2),
+                ..ListParameters::default()
+            })
+            .await?;
+        repository.create(StubEntity::new(6)).await?;
+
+        let second_page = repository
+            .list(ListParameters {
+                page_size: PageSize(2),
+                cursor: first_page.next_cursor.clone(),
+                ..ListParameters::default()
+            })
+            .await?;
+
+        let ids: Vec<StubId> = first_page
+            .items
+            .iter()
+            .chain(second_page.items.iter())
+            .map(HasId::id)
+            .collect();
+        assert_eq!(ids, vec![5, 4, 3, 2]);
+        assert!(second_page.previous_cursor.is_some());
+        assert!(second_page.next_cursor.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_walks_back_before_the_cursor() -> Result<(), AnyError> {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        for iteration in 1..=5 {
+            repository.create(StubEntity::new(iteration)).await?;
+        }
+        let second_page = repository
+            .list(ListParameters {
+                page_size: PageSize(2),
+                page_number: PageNumber(2),
+                ..ListParameters::default()
+            })
+            .await?;
+
+        let first_page = repository
+            .list(ListParameters {
+                page_size: PageSize(2),
+                cursor: second_page.previous_cursor.clone(),
+                ..ListParameters::default()
+            })
+            .await?;
+
+        let ids: Vec<StubId> = first_page.items.iter().map(HasId::id).collect();
+        assert_eq!(ids, vec![5, 4]);
+        assert!(first_page.previous_cursor.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_counts_and_returns_only_matching_entities() -> Result<(), AnyError> {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        for (id, name) in [(1, "cat"), (2, "dog"), (3, "catfish")] {
+            let mut stub_entity = StubEntity::new(id);
+            stub_entity.name = name.to_string();
+            repository.create(stub_entity).await?;
+        }
+
         let page = repository
-            .list(list_parameters)
-            .await
-            .expect("Failed to create entity");
+            .list(ListParameters {
+                filters: Some(Filter::Like("name".to_string(), "cat%".to_string())),
+                ..ListParameters::default()
+            })
+            .await?;
 
-        assert_eq!(page.items.clone().len(), 2);
+        assert_eq!(page.total_count, 2);
+        assert!(page.items.iter().all(|e| e.name.starts_with("cat")));
+
+        Ok(())
     }
-}
 
-/// Used by in-memory repositories to perform filtering
-pub trait FilterableAttributes {
-    type Output;
+    #[tokio::test]
+    async fn soft_deleted_entities_are_hidden_unless_asked_for() -> Result<(), AnyError> {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        let mut deleted_entity = StubEntity::new(1);
+        deleted_entity.deleted_at = Some(Utc::now());
+        repository.create(deleted_entity).await?;
+        repository.create(StubEntity::new(2)).await?;
+
+        let default_page = repository.list(ListParameters::default()).await?;
+        let full_page = repository
+            .list(ListParameters {
+                include_deleted: true,
+                ..ListParameters::default()
+            })
+            .await?;
+
+        assert_eq!(default_page.total_count, 1);
+        assert_eq!(full_page.total_count, 2);
+        assert!(repository.get_by_id(&1).await?.is_none());
+        assert!(repository.get_by_id_including_deleted(&1).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn purge_removes_only_entities_deleted_before_the_cutoff() -> Result<(), AnyError> {
+        let repository: InMemoryRepository<StubId, StubEntity> = InMemoryRepository::new();
+        let cutoff = Utc::now() - Duration::days(30);
+        for (id, deleted_at) in [
+            (1, Some(cutoff - Duration::days(1))),
+            (2, Some(cutoff + Duration::days(1))),
+            (3, None),
+        ] {
+            let mut stub_entity = StubEntity::new(id);
+            stub_entity.deleted_at = deleted_at;
+            repository.create(stub_entity).await?;
+        }
+
+        let purged_count = repository.purge_deleted(cutoff).await?;
+
+        assert_eq!(purged_count, 1);
+        assert!(repository.get_by_id_including_deleted(&1).await?.is_none());
+
+        Ok(())
+    }
+
+    fn in_memory<ID, Entity>() -> Arc<dyn Repository<ID, Entity> + Send + Sync>
+    where
+        ID: Send + Sync + Eq + Hash + Clone + 'static,
+        Entity: Send
+            + Sync
+            + Clone
+            + HasId<ID>
+            + Versioned
+            + SoftDeletable
+            + FilterableAttributes
+            + 'static,
+    {
+        Arc::new(InMemoryRepository::<ID, Entity>::new())
+    }
 
-    fn get_field_value(&self, field: &str) -> Self::Output;
+    repository_contract_tests!(topic_contract, TopicId, Topic, async {
+        Ok::<_, AnyError>(in_memory())
+    });
+    repository_contract_tests!(comment_contract, CommentId, Comment, async {
+        Ok::<_, AnyError>(in_memory())
+    });
 }
@@ -1,3 +1,14 @@
+pub mod cached_repository;
+pub mod cursor;
+pub mod file_repository;
+pub mod filter;
+pub mod health;
 pub mod in_memory_repository;
 pub mod rdbms;
+pub mod replication;
 pub mod repository;
+#[cfg(test)]
+pub mod repository_contract;
+pub mod schema;
+pub mod search;
+pub mod unit_of_work;
@@ -1,22 +1,47 @@
-use crate::persistence::repository::{HasId, ListParameters, Page, Repository, RepositoryError};
+use crate::config::APP_CONFIG;
+use crate::persistence::cursor::{Cursor, FieldValue};
+use crate::persistence::filter::{Filter, FilterableAttributes, LIKE_ESCAPE, escape_like};
+use crate::persistence::repository::{
+    HasId, ListParameters, Page, Repository, RepositoryError, SoftDeletable, Versioned,
+};
+use crate::persistence::search::{
+    HIGHLIGHT_START, HIGHLIGHT_STOP, SNIPPET_WORDS, SearchHit, SearchIndex, Searchable,
+    parse_highlighted, search_entities, search_terms,
+};
+use crate::persistence::unit_of_work::{StagedRepository, Transaction};
 use crate::views::pagination::Ordering;
 use async_trait::async_trait;
-use sea_orm::sea_query::Expr;
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::{Expr, Func, LikeExpr, NullOrdering};
+use sea_orm::sqlx;
+use sea_orm::sqlx::ConnectOptions as _;
+use sea_orm::sqlx::error::ErrorKind;
+use sea_orm::sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sea_orm::sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
 use sea_orm::{
-    Condition, ConnectOptions, Database, DatabaseConnection, DbErr, DeriveColumn, EntityTrait,
-    EnumIter,
+    ColumnTrait, Condition, ConnectOptions, ConnectionTrait, Database, DatabaseConnection,
+    DatabaseTransaction, DbBackend, DbErr, DeriveColumn, EntityTrait, EnumIter, FromQueryResult,
+    IdenStatic, Statement, Value,
 };
+use sea_orm::{ConnAcquireErr, RuntimeErr, SqlxPostgresConnector, SqlxSqliteConnector};
 use sea_orm::{IntoActiveModel, Order, PrimaryKeyTrait, QueryFilter, QueryOrder, QuerySelect};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 pub trait ModelDatabaseInterface<E: EntityTrait, M, Id> {
-    fn filter_from_params(list_parameters: &ListParameters) -> Condition;
     fn order_by_from_params(list_parameters: &ListParameters) -> (E::Column, Order);
     fn model_from_record(record: E::Model) -> M;
     fn model_to_record(model: M) -> E::ActiveModel;
     fn id_to_primary_key(id: &Id) -> <<E>::PrimaryKey as PrimaryKeyTrait>::ValueType;
+    fn id_column() -> E::Column;
+    fn version_column() -> E::Column;
+    fn deleted_at_column() -> E::Column;
 }
 
+/// The `tsvector` column the full-text index is built on, kept up to date by Postgres itself
+static SEARCH_DOCUMENT_COLUMN: &str = "search_document";
+
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
 enum Counter {
     Count,
@@ -31,13 +56,125 @@ impl From<Ordering> for Order {
     }
 }
 
-pub struct RdbmsRepository<E> {
-    db: DatabaseConnection,
+impl From<FieldValue> for Value {
+    fn from(value: FieldValue) -> Self {
+        match value {
+            FieldValue::Integer(i) => i.into(),
+            FieldValue::Text(t) => t.into(),
+            FieldValue::Timestamp(ts) => ts.into(),
+            FieldValue::Uuid(u) => u.into(),
+            FieldValue::Null => Self::String(None),
+        }
+    }
+}
+
+/// Translates a filter into a WHERE clause, resolving field names to the entity's columns
+fn filter_condition<C: ColumnTrait>(filter: &Filter) -> Result<Condition, RepositoryError> {
+    let column = |field: &str| {
+        C::from_str(field).map_err(|_| {
+            RepositoryError::GenericError(format!("No column backs the '{field}' field"))
+        })
+    };
+    let condition = match filter {
+        Filter::Eq(field, value) => Condition::all().add(column(field)?.eq(value.clone())),
+        Filter::Ne(field, value) => Condition::all().add(column(field)?.ne(value.clone())),
+        Filter::Like(field, pattern) => {
+            Condition::all().add(column(field)?.like(LikeExpr::new(pattern).escape(LIKE_ESCAPE)))
+        }
+        Filter::Range { field, from, to } => {
+            let column = column(field)?;
+            let mut condition = Condition::all();
+            if let Some(from) = from {
+                condition = condition.add(column.gte(from.clone()));
+            }
+            if let Some(to) = to {
+                condition = condition.add(column.lte(to.clone()));
+            }
+            condition
+        }
+        Filter::In(field, values) => Condition::all().add(column(field)?.is_in(values.clone())),
+        Filter::And(filters) => filters.iter().try_fold(
+            Condition::all(),
+            |condition, filter| -> Result<_, RepositoryError> {
+                Ok(condition.add(filter_condition::<C>(filter)?))
+            },
+        )?,
+        Filter::Or(filters) => filters.iter().try_fold(
+            Condition::any(),
+            |condition, filter| -> Result<_, RepositoryError> {
+                Ok(condition.add(filter_condition::<C>(filter)?))
+            },
+        )?,
+    };
+
+    Ok(condition)
+}
+
+/// Rows strictly past the cursor in walking order; NULLs sort last, matching `FieldValue`
+fn keyset_condition<C: ColumnTrait>(
+    column: C,
+    id_column: C,
+    cursor: &Cursor,
+    is_ascending: bool,
+) -> Condition {
+    let id = Value::from(cursor.id.clone());
+    match (&cursor.value, is_ascending) {
+        (FieldValue::Null, true) => Condition::all().add(column.is_null()).add(id_column.gt(id)),
+        (FieldValue::Null, false) => Condition::any()
+            .add(column.is_not_null())
+            .add(Condition::all().add(column.is_null()).add(id_column.lt(id))),
+        (value, true) => {
+            let value = Value::from(value.clone());
+            Condition::any()
+                .add(column.gt(value.clone()))
+                .add(Condition::all().add(column.eq(value)).add(id_column.gt(id)))
+                .add(column.is_null())
+        }
+        (value, false) => {
+            let value = Value::from(value.clone());
+            Condition::any()
+                .add(column.lt(value.clone()))
+                .add(Condition::all().add(column.eq(value)).add(id_column.lt(id)))
+        }
+    }
+}
+
+/// Where queries run: straight on the connection pool, or within a transaction
+pub trait Executor: Send + Sync + 'static {
+    type Connection: ConnectionTrait;
+
+    /// Where writes go, along with the reads that have to see them
+    fn connection(&self) -> &Self::Connection;
+
+    /// Where plain reads go, which may lag somewhat behind the writes
+    fn reader(&self) -> &Self::Connection {
+        self.connection()
+    }
+}
+
+impl Executor for DatabaseConnection {
+    type Connection = Self;
+
+    fn connection(&self) -> &Self {
+        self
+    }
+}
+
+impl Executor for Arc<DatabaseTransaction> {
+    type Connection = DatabaseTransaction;
+
+    fn connection(&self) -> &DatabaseTransaction {
+        self
+    }
+}
+
+pub struct RdbmsRepository<E, C = DatabaseConnection> {
+    db: C,
     _marker: std::marker::PhantomData<E>,
 }
 
-impl<E> RdbmsRepository<E> {
-    pub const fn new(db: DatabaseConnection) -> Self {
+impl<E, C> RdbmsRepository<E, C> {
+    pub const fn new(db: C) -> Self {
         Self {
             db,
             _marker: std::marker::PhantomData,
@@ -47,17 +184,62 @@ impl<E> RdbmsRepository<E> {
 
 impl From<DbErr> for RepositoryError {
     fn from(err: DbErr) -> Self {
-        Self::GenericError(err.to_string())
+        let message = err.to_string();
+        match err {
+            DbErr::RecordNotFound(_) => Self::NotFound(message),
+            DbErr::ConnectionAcquire(ConnAcquireErr::Timeout) => Self::Timeout(message),
+            DbErr::ConnectionAcquire(ConnAcquireErr::ConnectionClosed) | DbErr::Conn(_) => {
+                Self::Unavailable(message)
+            }
+            DbErr::Exec(RuntimeErr::SqlxError(e)) | DbErr::Query(RuntimeErr::SqlxError(e)) => {
+                from_sqlx_error(&e, message)
+            }
+            _ => Self::GenericError(message),
+        }
+    }
+}
+
+/// Postgres reports cancelled statements, e.g. ones running past `statement_timeout`, as 57014,
+/// and a server that is shutting down or starting up with the rest of class 57P.
+/// `SQLite` reports a database locked by another writer as 5 (`SQLITE_BUSY`).
+fn from_sqlx_error(error: &sqlx::Error, message: String) -> RepositoryError {
+    match error {
+        sqlx::Error::Database(e) => match e.kind() {
+            ErrorKind::UniqueViolation => RepositoryError::Duplicate(message),
+            ErrorKind::ForeignKeyViolation
+            | ErrorKind::NotNullViolation
+            | ErrorKind::CheckViolation => RepositoryError::ConstraintViolation(message),
+            _ => match e.code().as_deref() {
+                Some("57014" | "5") => RepositoryError::Timeout(message),
+                Some(code) if code.starts_with("57P") => RepositoryError::Unavailable(message),
+                _ => RepositoryError::GenericError(message),
+            },
+        },
+        sqlx::Error::PoolTimedOut => RepositoryError::Timeout(message),
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => RepositoryError::Unavailable(message),
+        _ => RepositoryError::GenericError(message),
     }
 }
 
 #[async_trait]
-impl<DbRecord, Id, ModelType> Repository<Id, ModelType> for RdbmsRepository<DbRecord>
+impl<DbRecord, Id, ModelType, C> Repository<Id, ModelType> for RdbmsRepository<DbRecord, C>
 where
-    Id: Send + Sync + Clone,
-    ModelType: Send + Sync + Clone + HasId<Id> + 'static,
+    C: Executor,
+    Id: Send + Sync + Clone + 'static,
+    ModelType: Send
+        + Sync
+        + Clone
+        + HasId<Id>
+        + Versioned
+        + SoftDeletable
+        + FilterableAttributes
+        + 'static,
     DbRecord: Send
         + Sync
+        + 'static
         + EntityTrait<Model: IntoActiveModel<<DbRecord as EntityTrait>::ActiveModel>>
         + ModelDatabaseInterface<DbRecord, ModelType, Id>,
     <DbRecord as EntityTrait>::Model: Send + Sync,
@@ -68,68 +250,535 @@ where
         &self,
         list_parameters: ListParameters,
     ) -> Result<Page<ModelType>, RepositoryError> {
-        let resulting_rows =
-            DbRecord::find().filter(DbRecord::filter_from_params(&list_parameters));
+        let visible_rows = if list_parameters.include_deleted {
+            DbRecord::find()
+        } else {
+            DbRecord::find().filter(DbRecord::deleted_at_column().is_null())
+        };
+        let resulting_rows = match list_parameters.filters.as_ref() {
+            Some(filter) => visible_rows.filter(filter_condition::<DbRecord::Column>(filter)?),
+            None => visible_rows,
+        };
         // Workaround: .count() is ambiguous, it wants to use an iterable count
         let count: Option<i64> = resulting_rows
             .clone()
             .select_only()
             .column_as(Expr::val(1).count(), "count")
             .into_values::<_, Counter>()
-            .one(&self.db)
+            .one(self.db.reader())
             .await?;
         let (order_by_column, order_direction) = DbRecord::order_by_from_params(&list_parameters);
-        let data = resulting_rows
-            .offset(Some(list_parameters.calculate_offset() as u64))
-            .limit(Some(list_parameters.calculate_limit() as u64))
-            .order_by(order_by_column, order_direction)
-            .all(&self.db)
+        let is_ascending = list_parameters.is_walking_ascending(order_direction == Order::Asc);
+        let (walking_order, null_ordering) = if is_ascending {
+            (Order::Asc, NullOrdering::Last)
+        } else {
+            (Order::Desc, NullOrdering::First)
+        };
+        let ordered_rows = resulting_rows
+            .order_by_with_nulls(order_by_column, walking_order.clone(), null_ordering)
+            .order_by(DbRecord::id_column(), walking_order);
+        let paginated_rows = match list_parameters.cursor.as_ref() {
+            Some(cursor) => ordered_rows.filter(keyset_condition(
+                order_by_column,
+                DbRecord::id_column(),
+                cursor,
+                is_ascending,
+            )),
+            None => ordered_rows.offset(Some(list_parameters.calculate_offset() as u64)),
+        };
+        let data = paginated_rows
+            .limit(Some(list_parameters.calculate_limit() as u64 + 1))
+            .all(self.db.reader())
             .await?;
 
-        Ok(Page {
-            items: data.into_iter().map(DbRecord::model_from_record).collect(),
-            size: list_parameters.page_size,
-            current_page_number: list_parameters.page_number,
-            total_count: count.unwrap_or_default() as u64,
-        })
+        Ok(Page::from_rows(
+            &list_parameters,
+            order_by_column.as_str(),
+            count.unwrap_or_default() as u64,
+            data.into_iter().map(DbRecord::model_from_record).collect(),
+        ))
     }
 
     #[allow(clippy::cast_possible_wrap)]
     async fn create(&self, entity: ModelType) -> Result<(), RepositoryError> {
+        // UUID keys are not rowids, so SQLite cannot report them back as the last insert id
         DbRecord::insert(DbRecord::model_to_record(entity))
-            .exec(&self.db)
+            .exec_without_returning(self.db.connection())
             .await?;
 
         Ok(())
     }
 
+    /// In a single multi-row insert, so either all of them are stored or none is
+    async fn create_many(&self, entities: Vec<ModelType>) -> Result<(), RepositoryError> {
+        if entities.is_empty() {
+            return Ok(());
+        }
+        DbRecord::insert_many(entities.into_iter().map(DbRecord::model_to_record))
+            .exec_without_returning(self.db.connection())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update(&self, mut entity: ModelType) -> Result<(), RepositoryError> {
+        let id = entity.id();
+        let expected_version = entity.version();
+        entity.increment_version();
+        match DbRecord::update(DbRecord::model_to_record(entity))
+            .filter(DbRecord::version_column().eq(expected_version))
+            .exec(self.db.connection())
+            .await
+        {
+            Ok(_) => Ok(()),
+            // Asked of the primary, as a replica may not have seen the record yet
+            Err(DbErr::RecordNotUpdated) => {
+                match DbRecord::find_by_id(DbRecord::id_to_primary_key(&id))
+                    .one(self.db.connection())
+                    .await?
+                {
+                    Some(_) => Err(RepositoryError::Conflict(
+                        "The record was modified since it was read".to_string(),
+                    )),
+                    None => Err(RepositoryError::NotFound(
+                        "Cannot update a record that does not exist".to_string(),
+                    )),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn overwrite(&self, entity: ModelType) -> Result<(), RepositoryError> {
+        match DbRecord::update(DbRecord::model_to_record(entity))
+            .exec(self.db.connection())
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(DbErr::RecordNotUpdated) => Err(RepositoryError::NotFound(
+                "Cannot overwrite a record that does not exist".to_string(),
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     #[allow(clippy::cast_sign_loss)]
     async fn get_by_id(&self, id: &Id) -> Result<Option<ModelType>, RepositoryError> {
         DbRecord::find_by_id(DbRecord::id_to_primary_key(id))
-            .one(&self.db)
+            .filter(DbRecord::deleted_at_column().is_null())
+            .one(self.db.reader())
+            .await
+            .map(|record| record.map(DbRecord::model_from_record))
+            .map_err(RepositoryError::from)
+    }
+
+    async fn get_by_id_including_deleted(
+        &self,
+        id: &Id,
+    ) -> Result<Option<ModelType>, RepositoryError> {
+        DbRecord::find_by_id(DbRecord::id_to_primary_key(id))
+            .one(self.db.reader())
+            .await
+            .map(|record| record.map(DbRecord::model_from_record))
+            .map_err(RepositoryError::from)
+    }
+
+    async fn get_for_update(&self, id: &Id) -> Result<Option<ModelType>, RepositoryError> {
+        DbRecord::find_by_id(DbRecord::id_to_primary_key(id))
+            .lock_exclusive()
+            .one(self.db.connection())
             .await
             .map(|record| record.map(DbRecord::model_from_record))
-            .map_err(|e| RepositoryError::GenericError(e.to_string()))
+            .map_err(RepositoryError::from)
     }
 
     async fn delete(&self, id: &Id) -> Result<(), RepositoryError> {
         DbRecord::delete_by_id(DbRecord::id_to_primary_key(id))
-            .exec(&self.db)
+            .exec(self.db.connection())
             .await?;
 
         Ok(())
     }
+
+    async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let result = DbRecord::delete_many()
+            .filter(DbRecord::deleted_at_column().lt(deleted_before))
+            .exec(self.db.connection())
+            .await?;
+
+        Ok(result.rows_affected)
+    }
+
+    fn within(
+        self: Arc<Self>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<Id, ModelType> + Send + Sync> {
+        match transaction.database() {
+            Some(database) => Arc::new(RdbmsRepository::<DbRecord, _>::new(database.clone())),
+            None => StagedRepository::scoped(self, transaction),
+        }
+    }
+}
+
+impl<DbRecord, C> RdbmsRepository<DbRecord, C>
+where
+    C: Executor,
+    DbRecord: EntityTrait,
+{
+    /// Ranks and highlights on Postgres, using the index kept on `SEARCH_DOCUMENT_COLUMN`
+    #[allow(clippy::cast_possible_wrap)]
+    async fn search_document<Id, ModelType>(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit<ModelType>>, RepositoryError>
+    where
+        ModelType: Searchable,
+        DbRecord: ModelDatabaseInterface<DbRecord, ModelType, Id>,
+    {
+        let table = DbRecord::default().table_name().to_string();
+        let deleted_at = DbRecord::deleted_at_column().as_str().to_string();
+        let body = ModelType::SEARCHED_FIELDS.last().ok_or_else(|| {
+            RepositoryError::GenericError(format!("Nothing in '{table}' is searchable"))
+        })?;
+        let headline_options = format!(
+            "StartSel={HIGHLIGHT_START}, StopSel={HIGHLIGHT_STOP}, MaxWords={SNIPPET_WORDS}, MinWords={}",
+            SNIPPET_WORDS / 2
+        );
+        let statement = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            format!(
+                "SELECT {table}.*, \
+                    ts_rank({SEARCH_DOCUMENT_COLUMN}, query) AS search_rank, \
+                    ts_headline('english', {body}, query, $3) AS search_snippet \
+                FROM {table}, websearch_to_tsquery('english', $1) AS query \
+                WHERE {SEARCH_DOCUMENT_COLUMN} @@ query AND {deleted_at} IS NULL \
+                ORDER BY search_rank DESC \
+                LIMIT $2"
+            ),
+            [query.into(), (limit as i64).into(), headline_options.into()],
+        );
+
+        self.db
+            .reader()
+            .query_all(statement)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(SearchHit {
+                    entity: DbRecord::model_from_record(DbRecord::Model::from_query_result(
+                        row, "",
+                    )?),
+                    rank: f64::from(row.try_get::<f32>("", "search_rank")?),
+                    snippet: parse_highlighted(&row.try_get::<String>("", "search_snippet")?),
+                })
+            })
+            .collect()
+    }
+
+    /// Elsewhere, narrows the rows down with `LIKE` and ranks them in memory
+    async fn search_by_scanning<Id, ModelType>(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit<ModelType>>, RepositoryError>
+    where
+        ModelType: Searchable,
+        DbRecord: ModelDatabaseInterface<DbRecord, ModelType, Id>,
+    {
+        let terms = search_terms(query);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+        // Case-insensitive, unlike LIKE filters, as the in-memory ranking ignores case too
+        let mut containing_every_term = Condition::all();
+        for term in &terms {
+            let pattern = format!("%{}%", escape_like(term));
+            let mut containing_term = Condition::any();
+            for field in ModelType::SEARCHED_FIELDS {
+                let column = DbRecord::Column::from_str(field).map_err(|_| {
+                    RepositoryError::GenericError(format!("No column backs the '{field}' field"))
+                })?;
+                containing_term = containing_term.add(
+                    Expr::expr(Func::lower(Expr::col(column)))
+                        .like(LikeExpr::new(&pattern).escape(LIKE_ESCAPE)),
+                );
+            }
+            containing_every_term = containing_every_term.add(containing_term);
+        }
+        let candidates = DbRecord::find()
+            .filter(DbRecord::deleted_at_column().is_null())
+            .filter(containing_every_term)
+            .all(self.db.reader())
+            .await?
+            .into_iter()
+            .map(DbRecord::model_from_record);
+
+        Ok(search_entities(candidates, query, limit))
+    }
+}
+
+#[async_trait]
+impl<DbRecord, Id, ModelType, C> SearchIndex<Id, ModelType> for RdbmsRepository<DbRecord, C>
+where
+    C: Executor,
+    Id: Send + Sync + 'static,
+    ModelType: Send + Sync + Searchable + 'static,
+    DbRecord: Send + Sync + 'static + EntityTrait + ModelDatabaseInterface<DbRecord, ModelType, Id>,
+    <DbRecord as EntityTrait>::Model: Send + Sync,
+{
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit<ModelType>>, RepositoryError> {
+        if self.db.reader().get_database_backend() == DbBackend::Postgres {
+            self.search_document(query, limit).await
+        } else {
+            self.search_by_scanning(query, limit).await
+        }
+    }
 }
 
 pub async fn connect(database_url: &String) -> Result<DatabaseConnection, DbErr> {
     println!("Attempting to connect to the database");
+    if SqlxSqliteConnector::accepts(database_url) {
+        return connect_sqlite(database_url).await;
+    }
+
     let mut connection_options = ConnectOptions::new(database_url);
     connection_options
-        .min_connections(5)
-        .max_connections(20)
+        .min_connections(APP_CONFIG.database_min_connections)
+        .max_connections(APP_CONFIG.database_max_connections)
         .connect_timeout(Duration::from_secs(5))
         .idle_timeout(Duration::from_secs(30))
         .sqlx_logging(false);
 
     Database::connect(connection_options).await
 }
+
+/// Sets up the connection pool without waiting for the database to answer, so the application
+/// can start while it's down and pick it up as soon as it's back
+pub async fn connect_lazily(database_url: &str) -> Result<DatabaseConnection, DbErr> {
+    if SqlxSqliteConnector::accepts(database_url) {
+        return connect_sqlite(database_url).await;
+    }
+
+    let connect_options = PgConnectOptions::from_str(database_url)
+        .map_err(|e| DbErr::Conn(RuntimeErr::SqlxError(e)))?;
+    let pool = PgPoolOptions::new()
+        .max_connections(APP_CONFIG.database_max_connections)
+        .acquire_timeout(Duration::from_secs(5))
+        .idle_timeout(Duration::from_secs(30))
+        .connect_lazy_with(connect_options.disable_statement_logging());
+
+    Ok(SqlxPostgresConnector::from_sqlx_postgres_pool(pool))
+}
+
+/// Opens (or creates) a local database file or an in-memory database, e.g. `sqlite://forum.db` or `sqlite::memory:`.
+/// Its schema is brought up to date by `prepare_schema`, the same as on Postgres.
+async fn connect_sqlite(database_url: &str) -> Result<DatabaseConnection, DbErr> {
+    let sqlx_error = |e| DbErr::Conn(RuntimeErr::SqlxError(e));
+    let connect_options = SqliteConnectOptions::from_str(database_url)
+        .map_err(sqlx_error)?
+        .create_if_missing(true)
+        .foreign_keys(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        // Postgres' LIKE is case-sensitive, SQLite's is not unless asked
+        .pragma("case_sensitive_like", "ON");
+    let is_in_memory = database_url.contains(":memory:") || database_url.contains("mode=memory");
+    // An in-memory database lives only as long as its connections, so one is kept open for good
+    let pool_options = if is_in_memory {
+        SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+    } else {
+        SqlitePoolOptions::new()
+            .max_connections(5)
+            .idle_timeout(Duration::from_secs(30))
+    };
+    let pool = pool_options
+        .acquire_timeout(Duration::from_secs(5))
+        .connect_with(connect_options.disable_statement_logging())
+        .await
+        .map_err(sqlx_error)?;
+    Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(pool))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authn::session::User;
+    use crate::error::AnyError;
+    use crate::persistence::repository_contract::{repository_contract_tests, specimen_topic};
+    use crate::persistence::unit_of_work::UnitOfWork;
+    use crate::petty_matters::comment::{Comment, CommentId};
+    use crate::petty_matters::comment_repository::Entity as CommentDbModel;
+    use crate::petty_matters::topic::{Topic, TopicId};
+    use crate::petty_matters::topic_repository::Entity as TopicDbModel;
+    use crate::testing::migrated_sqlite;
+
+    async fn sqlite_repository() -> Result<RdbmsRepository<TopicDbModel>, AnyError> {
+        let db = migrated_sqlite().await?;
+
+        Ok(RdbmsRepository::<TopicDbModel>::new(db))
+    }
+
+    #[tokio::test]
+    async fn sqlite_records_are_read_back_once_migrated() -> Result<(), AnyError> {
+        let repository = sqlite_repository().await?;
+        let topic = Topic {
+            title: "The neighbour's cat".to_string(),
+            ..Topic::default()
+        };
+        repository.create(topic.clone()).await?;
+
+        let result: Option<Topic> =
+            Repository::<TopicId, Topic>::get_by_id(&repository, &topic.id).await?;
+
+        assert_eq!(result, Some(topic));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_like_is_case_sensitive_as_in_postgres() -> Result<(), AnyError> {
+        let repository = sqlite_repository().await?;
+        for title in ["Cats", "cats"] {
+            repository
+                .create(Topic {
+                    title: title.to_string(),
+                    ..Topic::default()
+                })
+                .await?;
+        }
+
+        let page: Page<Topic> = repository
+            .list(ListParameters {
+                filters: Some(Filter::Like("title".to_string(), "cat%".to_string())),
+                ..ListParameters::default()
+            })
+            .await?;
+
+        assert_eq!(page.total_count, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_transaction_discards_writes_on_rollback() -> Result<(), AnyError> {
+        let db = migrated_sqlite().await?;
+        let repository = Arc::new(RdbmsRepository::<TopicDbModel>::new(db.clone()));
+        let topic = Topic::default();
+        let transaction = UnitOfWork::new(Some(db)).begin().await?;
+        let scoped: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            repository.clone().within(&transaction);
+        scoped.create(topic.clone()).await?;
+        drop(scoped);
+
+        transaction.rollback().await?;
+
+        let result: Option<Topic> =
+            Repository::<TopicId, Topic>::get_by_id(repository.as_ref(), &topic.id).await?;
+        assert_eq!(result, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_reads_records_about_to_be_written_over_within_a_transaction()
+    -> Result<(), AnyError> {
+        let db = migrated_sqlite().await?;
+        let repository = Arc::new(RdbmsRepository::<TopicDbModel>::new(db.clone()));
+        let topic = Topic::default();
+        repository.create(topic.clone()).await?;
+        let transaction = UnitOfWork::new(Some(db)).begin().await?;
+        let scoped: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            repository.within(&transaction);
+
+        let result = scoped.get_for_update(&topic.id).await?;
+        drop(scoped);
+        transaction.commit().await?;
+
+        assert_eq!(result, Some(topic));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_errors_are_told_apart() -> Result<(), AnyError> {
+        let db = migrated_sqlite().await?;
+        let topics = RdbmsRepository::<TopicDbModel>::new(db.clone());
+        let comments = RdbmsRepository::<CommentDbModel>::new(db);
+        let topic = Topic::default();
+        topics.create(topic.clone()).await?;
+
+        let duplicate = topics.create(topic).await;
+        let orphan = comments
+            .create(Comment::new(
+                Topic::default().id,
+                "Nobody's listening".to_string(),
+                User::anonymous(),
+            ))
+            .await;
+
+        assert!(matches!(duplicate, Err(RepositoryError::Duplicate(_))));
+        assert!(matches!(
+            orphan,
+            Err(RepositoryError::ConstraintViolation(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_search_ranks_matching_rows() -> Result<(), AnyError> {
+        let topics = sqlite_repository().await?;
+        let in_title = Topic {
+            title: "Leaves on the lawn".to_string(),
+            ..Topic::default()
+        };
+        let in_content = Topic {
+            title: "Autumn".to_string(),
+            content: "Leaves everywhere, and on my lawn too".to_string(),
+            ..Topic::default()
+        };
+        for topic in [in_content.clone(), in_title.clone(), Topic::default()] {
+            topics.create(topic).await?;
+        }
+
+        let hits = topics.search("lawn leaves", 10).await?;
+
+        assert_eq!(
+            hits.iter().map(|hit| hit.entity.id).collect::<Vec<_>>(),
+            [in_title.id, in_content.id]
+        );
+
+        Ok(())
+    }
+
+    async fn sqlite_topics() -> Result<Arc<dyn Repository<TopicId, Topic> + Send + Sync>, AnyError>
+    {
+        Ok(Arc::new(sqlite_repository().await?))
+    }
+
+    async fn sqlite_comments()
+    -> Result<Arc<dyn Repository<CommentId, Comment> + Send + Sync>, AnyError> {
+        let db = migrated_sqlite().await?;
+        RdbmsRepository::<TopicDbModel>::new(db.clone())
+            .create(specimen_topic())
+            .await?;
+
+        Ok(Arc::new(RdbmsRepository::<CommentDbModel>::new(db)))
+    }
+
+    repository_contract_tests!(sqlite_topic_contract, TopicId, Topic, sqlite_topics());
+    repository_contract_tests!(
+        sqlite_comment_contract,
+        CommentId,
+        Comment,
+        sqlite_comments()
+    );
+}
@@ -0,0 +1,85 @@
+use crate::persistence::rdbms::Executor;
+use sea_orm::DatabaseConnection;
+use std::future::Future;
+
+tokio::task_local! {
+    static READS_FROM_PRIMARY: bool;
+}
+
+/// Runs the future with its reads sent to the primary, for callers who must see their own
+/// writes even though the replica may not have caught up with them yet
+pub async fn reading_from_primary<F: Future>(future: F) -> F::Output {
+    READS_FROM_PRIMARY.scope(true, future).await
+}
+
+/// Whether the current task asked to read from the primary
+pub fn prefers_primary() -> bool {
+    READS_FROM_PRIMARY.try_with(|flag| *flag).unwrap_or(false)
+}
+
+/// Sends writes to the primary and reads to the replica, unless the task prefers the primary
+#[derive(Clone)]
+pub struct ReplicatedConnection {
+    primary: DatabaseConnection,
+    replica: DatabaseConnection,
+}
+
+impl ReplicatedConnection {
+    pub const fn new(primary: DatabaseConnection, replica: DatabaseConnection) -> Self {
+        Self { primary, replica }
+    }
+
+    /// Without a replica, the primary serves the reads as well
+    pub fn primary_only(primary: DatabaseConnection) -> Self {
+        Self {
+            replica: primary.clone(),
+            primary,
+        }
+    }
+}
+
+impl Executor for ReplicatedConnection {
+    type Connection = DatabaseConnection;
+
+    fn connection(&self) -> &DatabaseConnection {
+        &self.primary
+    }
+
+    fn reader(&self) -> &DatabaseConnection {
+        if prefers_primary() {
+            &self.primary
+        } else {
+            &self.replica
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::rdbms::RdbmsRepository;
+    use crate::persistence::repository::Repository;
+    use crate::petty_matters::topic::Topic;
+    use crate::petty_matters::topic_repository::Entity as TopicDbModel;
+    use crate::testing::migrated_sqlite;
+
+    #[tokio::test]
+    async fn own_writes_are_read_from_the_primary() -> Result<(), AnyError> {
+        let repository = RdbmsRepository::<TopicDbModel, _>::new(ReplicatedConnection::new(
+            migrated_sqlite().await?,
+            migrated_sqlite().await?,
+        ));
+        let topic = Topic::default();
+        let id = topic.id;
+        repository.create(topic).await?;
+
+        let from_replica = repository.get_by_id(&id).await?;
+        let from_primary = reading_from_primary(repository.get_by_id(&id)).await?;
+
+        assert!(from_replica.is_none());
+        assert!(from_primary.is_some());
+
+        Ok(())
+    }
+}
@@ -1,11 +1,15 @@
+use crate::persistence::cursor::{Cursor, CursorDirection};
+use crate::persistence::filter::{Filter, FilterError, FilterableAttributes};
+use crate::persistence::unit_of_work::Transaction;
 use crate::views::pagination::{Ordering, PageFilters};
 use async_trait::async_trait;
 use axum::extract::Query;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Display;
 use std::hash::Hash;
+use std::sync::Arc;
 
 #[derive(Clone, Debug, Copy, Default, Deserialize, Eq, PartialEq, Hash)]
 pub struct PageNumber(pub usize);
@@ -13,13 +17,19 @@ pub struct PageNumber(pub usize);
 #[derive(Clone, Debug, Copy, Default, Deserialize, Eq, PartialEq, Hash)]
 pub struct PageSize(pub usize);
 
+static DEFAULT_ORDER_BY: &str = "creation_time";
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct ListParameters {
     pub page_size: PageSize,
     pub page_number: PageNumber,
     pub order_by: Option<String>,
     pub ordering: Option<Ordering>,
-    pub filters: Option<BTreeMap<String, String>>,
+    pub filters: Option<Filter>,
+    /// When present, the listing continues from this position and `page_number` is ignored
+    pub cursor: Option<Cursor>,
+    /// Soft-deleted entities are left out unless asked for
+    pub include_deleted: bool,
 }
 
 impl Default for ListParameters {
@@ -30,6 +40,8 @@ impl Default for ListParameters {
             filters: Option::default(),
             order_by: Option::default(),
             ordering: Option::default(),
+            cursor: Option::default(),
+            include_deleted: false,
         }
     }
 }
@@ -43,67 +55,166 @@ impl ListParameters {
         self.page_size.0
     }
 
-    pub fn from_query_params(page_filters: &Query<PageFilters>) -> Self {
-        Self {
+    pub fn from_query_params<T: FilterableAttributes>(
+        page_filters: &Query<PageFilters>,
+    ) -> Result<Self, FilterError> {
+        if let Some(order_by) = &page_filters.order_by
+            && !is_field_of::<T>(order_by)
+        {
+            return Err(FilterError::UnknownSortField(order_by.clone()));
+        }
+        let cursor = match page_filters.cursor.as_deref() {
+            Some(token) => Some(
+                Cursor::decode(token)
+                    .ok_or_else(|| FilterError::InvalidCursor(token.to_string()))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
             page_size: page_filters.page_size.unwrap_or(PageSize(20)),
             page_number: page_filters.page.unwrap_or(PageNumber(1)),
-            filters: Some(page_filters.filters.clone()),
+            filters: Filter::from_query_params::<T>(&page_filters.filters)?,
             order_by: page_filters.order_by.clone(),
             ordering: page_filters.ordering.clone(),
+            cursor,
+            include_deleted: false,
+        })
+    }
+
+    /// Narrows down the listing further, on top of any filters already present
+    pub fn add_filter(&mut self, filter: Filter) {
+        self.filters = Some(match self.filters.take() {
+            Some(existing) => existing.and(filter),
+            None => filter,
+        });
+    }
+
+    /// The attribute to order by, defaulting to the newest entries first.
+    /// Attributes `T` doesn't have are passed over as if none was asked for.
+    pub fn sort_field<T: FilterableAttributes>(&self) -> &str {
+        self.known_order_by::<T>().unwrap_or(DEFAULT_ORDER_BY)
+    }
+
+    pub fn sort_ordering<T: FilterableAttributes>(&self) -> Ordering {
+        if self.known_order_by::<T>().is_none() {
+            return Ordering::Descending;
         }
+        self.ordering.clone().unwrap_or_default()
     }
+
+    fn known_order_by<T: FilterableAttributes>(&self) -> Option<&str> {
+        self.order_by
+            .as_deref()
+            .filter(|order_by| is_field_of::<T>(order_by))
+    }
+
+    /// Whether rows are walked in ascending order, which is reversed when paging backwards
+    pub fn is_walking_ascending(&self, is_ascending: bool) -> bool {
+        match self.cursor.as_ref().map(|cursor| cursor.direction) {
+            Some(CursorDirection::Before) => !is_ascending,
+            _ => is_ascending,
+        }
+    }
+}
+
+fn is_field_of<T: FilterableAttributes>(field: &str) -> bool {
+    T::FIELDS.iter().any(|(name, _)| *name == field)
 }
 
 #[derive(Clone, Default)]
 pub struct Page<T> {
-    pub current_page_number: PageNumber,
     pub size: PageSize,
     pub total_count: u64,
     pub items: Vec<T>,
+    pub next_cursor: Option<Cursor>,
+    pub previous_cursor: Option<Cursor>,
 }
 
-impl<T> Page<T> {
-    pub const fn is_first_page(&self) -> bool {
-        self.current_page_number.0 == 1
-    }
+impl<T: FilterableAttributes> Page<T> {
+    /// Assembles a page from rows fetched in walking order, including one row past the limit
+    /// which only serves to tell whether there is more to come
+    pub fn from_rows(
+        list_parameters: &ListParameters,
+        sort_field: &str,
+        total_count: u64,
+        mut rows: Vec<T>,
+    ) -> Self {
+        let has_more = rows.len() > list_parameters.calculate_limit();
+        rows.truncate(list_parameters.calculate_limit());
+        let (has_previous, has_next) = match list_parameters.cursor.as_ref() {
+            None => (list_parameters.calculate_offset() > 0, has_more),
+            Some(cursor) if cursor.direction == CursorDirection::After => (true, has_more),
+            Some(_) => {
+                rows.reverse();
+                (has_more, true)
+            }
+        };
 
-    pub const fn has_next_page(&self) -> bool {
-        self.total_count > (self.current_page_number.0 * self.size.0) as u64
+        Self {
+            size: list_parameters.page_size,
+            total_count,
+            next_cursor: rows
+                .last()
+                .filter(|_| has_next)
+                .map(|row| Cursor::after(row, sort_field)),
+            previous_cursor: rows
+                .first()
+                .filter(|_| has_previous)
+                .map(|row| Cursor::before(row, sort_field)),
+            items: rows,
+        }
     }
+}
 
-    pub const fn get_next_page_number(&self) -> usize {
-        if self.has_next_page() {
-            self.current_page_number.0 + 1
-        } else {
-            0
-        }
+impl<T> Page<T> {
+    pub fn get_next_cursor(&self) -> Option<String> {
+        self.next_cursor.as_ref().map(Cursor::encode)
     }
 
-    pub const fn get_previous_page_number(&self) -> usize {
-        self.current_page_number.0.saturating_sub(1)
+    pub fn get_previous_cursor(&self) -> Option<String> {
+        self.previous_cursor.as_ref().map(Cursor::encode)
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum RepositoryError {
     GenericError(String),
+    /// The entity changed since it was read, so the write would have overwritten someone else's
+    Conflict(String),
+    NotFound(String),
+    /// Another entity already holds the same key
+    Duplicate(String),
+    /// The write would leave the data inconsistent, e.g. refer to something that doesn't exist
+    ConstraintViolation(String),
+    /// The storage did not answer in time, but may well do so if asked again later
+    Timeout(String),
+    /// The storage cannot be reached at all
+    Unavailable(String),
+}
+
+impl RepositoryError {
+    /// Whether the same request has a fair chance of succeeding once retried
+    pub const fn is_transient(&self) -> bool {
+        matches!(self, Self::Timeout(_) | Self::Unavailable(_))
+    }
 }
 
 impl Display for RepositoryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::GenericError(msg) => write!(f, "Repository error: {msg}"),
+            Self::Conflict(msg) => write!(f, "Conflicting write: {msg}"),
+            Self::NotFound(msg) => write!(f, "Not found: {msg}"),
+            Self::Duplicate(msg) => write!(f, "Duplicate entry: {msg}"),
+            Self::ConstraintViolation(msg) => write!(f, "Constraint violated: {msg}"),
+            Self::Timeout(msg) => write!(f, "Storage timed out: {msg}"),
+            Self::Unavailable(msg) => write!(f, "Storage unavailable: {msg}"),
         }
     }
 }
 
-impl Error for RepositoryError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Self::GenericError(_) => None,
-        }
-    }
-}
+impl Error for RepositoryError {}
 
 #[async_trait]
 #[allow(dead_code)]
@@ -114,10 +225,59 @@ where
 {
     async fn list(&self, list_parameters: ListParameters) -> Result<Page<Entity>, RepositoryError>;
     async fn create(&self, entity: Entity) -> Result<(), RepositoryError>;
+    /// Stores all the entities or, should one of them fail, possibly only those before it
+    /// unless within a transaction
+    async fn create_many(&self, entities: Vec<Entity>) -> Result<(), RepositoryError>
+    where
+        Entity: 'async_trait,
+    {
+        for entity in entities {
+            self.create(entity).await?;
+        }
+
+        Ok(())
+    }
+    async fn update(&self, entity: Entity) -> Result<(), RepositoryError>;
+    /// Stores the entity as given, neither checking nor bumping its version. Meant for attributes
+    /// the Ministry maintains itself, such as counters, which nobody's amendment should trip over.
+    async fn overwrite(&self, entity: Entity) -> Result<(), RepositoryError>;
     async fn get_by_id(&self, id: &ID) -> Result<Option<Entity>, RepositoryError>;
+    async fn get_by_id_including_deleted(&self, id: &ID)
+    -> Result<Option<Entity>, RepositoryError>;
+    /// Reads an entity, soft-deleted or not, that is about to be written over, keeping other
+    /// transactions from writing it until this one is over. Without a database transaction
+    /// to hold the entity in, it's an ordinary read.
+    async fn get_for_update(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        self.get_by_id_including_deleted(id).await
+    }
+    /// Removes the entity for good; soft deletion is an update of its `deleted_at`
     async fn delete(&self, id: &ID) -> Result<(), RepositoryError>;
+    /// Removes every entity soft-deleted before the cutoff, returning how many there were
+    async fn purge_deleted(&self, deleted_before: DateTime<Utc>) -> Result<u64, RepositoryError>;
+    /// The same repository, writing within the transaction instead of on its own
+    fn within(
+        self: Arc<Self>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<ID, Entity> + Send + Sync>;
 }
 
 pub trait HasId<ID> {
     fn id(&self) -> ID;
 }
+
+/// Entities carrying a row version, used for optimistic concurrency control:
+/// an update only goes through if the stored version still matches the one that was read
+pub trait Versioned {
+    fn version(&self) -> u32;
+    fn increment_version(&mut self);
+}
+
+/// Entities that are hidden rather than removed when deleted, so they can be restored
+/// until the retention period is over and they get purged
+pub trait SoftDeletable {
+    fn deleted_at(&self) -> Option<DateTime<Utc>>;
+
+    fn is_deleted(&self) -> bool {
+        self.deleted_at().is_some()
+    }
+}
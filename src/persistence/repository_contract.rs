@@ -0,0 +1,619 @@
+//! Behaviour every `Repository` backend has to agree on, so the rest of the application
+//! can't tell which one it's talking to.
+//!
+//! Backends sign up in their own tests with [`repository_contract_tests!`], handing over
+//! an expression that yields a fresh, empty repository for every check.
+
+use crate::authn::session::{User, Username};
+use crate::error::AnyError;
+use crate::persistence::cursor::FieldValue;
+use crate::persistence::filter::{Filter, FilterableAttributes};
+use crate::persistence::repository::{
+    HasId, ListParameters, PageNumber, PageSize, Repository, RepositoryError, SoftDeletable,
+    Versioned,
+};
+use crate::petty_matters::comment::{Comment, CommentId};
+use crate::petty_matters::topic::{Topic, TopicId};
+use crate::views::pagination::Ordering;
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+use std::fmt::Debug;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Every specimen comment belongs to this petty matter, which backends enforcing
+/// foreign keys need to have stored up front
+pub static SPECIMEN_TOPIC_ID: TopicId = TopicId(Uuid::from_u128(1));
+
+pub fn specimen_topic() -> Topic {
+    Topic {
+        id: SPECIMEN_TOPIC_ID,
+        ..Topic::default()
+    }
+}
+
+/// Entities the contract knows how to make up
+pub trait Specimen<ID>:
+    Clone
+    + Debug
+    + PartialEq
+    + HasId<ID>
+    + Versioned
+    + SoftDeletable
+    + FilterableAttributes
+    + Send
+    + Sync
+    + 'static
+{
+    /// The text field that [`Specimen::specimen`] fills in and listings get ordered by
+    const TEXT_FIELD: &'static str;
+
+    fn specimen(text: &str, created_minutes_ago: i64) -> Self;
+    fn amend(&mut self, text: &str);
+    fn withdraw(&mut self, at: DateTime<Utc>);
+}
+
+fn minutes_ago(minutes: i64) -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(0) - Duration::minutes(minutes)
+}
+
+fn withdrawer() -> Username {
+    User::anonymous().email
+}
+
+impl Specimen<TopicId> for Topic {
+    const TEXT_FIELD: &'static str = "title";
+
+    fn specimen(text: &str, created_minutes_ago: i64) -> Self {
+        Self {
+            title: text.to_string(),
+            content: text.to_string(),
+            creation_time: minutes_ago(created_minutes_ago),
+            ..Self::default()
+        }
+    }
+
+    fn amend(&mut self, text: &str) {
+        self.title = text.to_string();
+    }
+
+    fn withdraw(&mut self, at: DateTime<Utc>) {
+        self.deleted_at = Some(at);
+        self.deleted_by = Some(withdrawer());
+    }
+}
+
+impl Specimen<CommentId> for Comment {
+    const TEXT_FIELD: &'static str = "content";
+
+    fn specimen(text: &str, created_minutes_ago: i64) -> Self {
+        Self {
+            creation_time: minutes_ago(created_minutes_ago),
+            ..Self::new(SPECIMEN_TOPIC_ID, text.to_string(), User::anonymous())
+        }
+    }
+
+    fn amend(&mut self, text: &str) {
+        self.content = text.to_string();
+    }
+
+    fn withdraw(&mut self, at: DateTime<Utc>) {
+        self.deleted_at = Some(at);
+        self.deleted_by = Some(withdrawer());
+    }
+}
+
+type Backend<ID, E> = Arc<dyn Repository<ID, E> + Send + Sync>;
+
+fn text_of<ID, E: Specimen<ID>>(entity: &E) -> String {
+    match entity.get_field_value(E::TEXT_FIELD) {
+        FieldValue::Text(text) => text,
+        _ => String::new(),
+    }
+}
+
+async fn store<ID, E>(repository: &Backend<ID, E>, entities: &[E]) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    for entity in entities {
+        repository.create(entity.clone()).await?;
+    }
+
+    Ok(())
+}
+
+async fn list_texts<ID, E>(
+    repository: &Backend<ID, E>,
+    list_parameters: ListParameters,
+) -> Result<Vec<String>, AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    Ok(repository
+        .list(list_parameters)
+        .await?
+        .items
+        .iter()
+        .map(text_of)
+        .collect())
+}
+
+pub async fn created_entities_can_be_read_back<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let entity = E::specimen("Noisy neighbours", 0);
+
+    store(&repository, std::slice::from_ref(&entity)).await?;
+
+    assert_eq!(repository.get_by_id(&entity.id()).await?, Some(entity));
+
+    Ok(())
+}
+
+pub async fn missing_entities_are_none<ID, E>(repository: Backend<ID, E>) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let never_stored = E::specimen("Never filed", 0);
+
+    let result = repository.get_by_id(&never_stored.id()).await;
+
+    assert_eq!(result, Ok(None));
+
+    Ok(())
+}
+
+pub async fn creating_an_entity_twice_is_a_duplicate<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let entity = E::specimen("Filed twice", 0);
+    store(&repository, std::slice::from_ref(&entity)).await?;
+
+    let result = repository.create(entity).await;
+
+    assert!(matches!(result, Err(RepositoryError::Duplicate(_))));
+
+    Ok(())
+}
+
+pub async fn entities_can_be_created_together<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let entities = vec![E::specimen("First", 0), E::specimen("Second", 0)];
+
+    repository.create_many(entities.clone()).await?;
+
+    for entity in entities {
+        assert_eq!(repository.get_by_id(&entity.id()).await?, Some(entity));
+    }
+
+    Ok(())
+}
+
+pub async fn updates_are_stored_with_the_next_version<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let mut entity = E::specimen("Draft", 0);
+    store(&repository, std::slice::from_ref(&entity)).await?;
+
+    entity.amend("Amended");
+    repository.update(entity.clone()).await?;
+
+    entity.increment_version();
+    assert_eq!(repository.get_by_id(&entity.id()).await?, Some(entity));
+
+    Ok(())
+}
+
+pub async fn stale_updates_are_conflicts<ID, E>(repository: Backend<ID, E>) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let entity = E::specimen("Draft", 0);
+    store(&repository, std::slice::from_ref(&entity)).await?;
+    let mut first = entity.clone();
+    first.amend("First");
+    repository.update(first).await?;
+
+    let mut second = entity;
+    second.amend("Second");
+    let result = repository.update(second).await;
+
+    assert!(matches!(result, Err(RepositoryError::Conflict(_))));
+
+    Ok(())
+}
+
+pub async fn overwrites_keep_the_version<ID, E>(repository: Backend<ID, E>) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let mut entity = E::specimen("Draft", 0);
+    store(&repository, std::slice::from_ref(&entity)).await?;
+
+    entity.amend("Overwritten");
+    repository.overwrite(entity.clone()).await?;
+    let missing = repository.overwrite(E::specimen("Never filed", 0)).await;
+
+    assert_eq!(repository.get_by_id(&entity.id()).await?, Some(entity));
+    assert!(matches!(missing, Err(RepositoryError::NotFound(_))));
+
+    Ok(())
+}
+
+pub async fn updating_a_missing_entity_is_not_found<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let never_stored = E::specimen("Never filed", 0);
+
+    let result = repository.update(never_stored).await;
+
+    assert!(matches!(result, Err(RepositoryError::NotFound(_))));
+
+    Ok(())
+}
+
+pub async fn deleted_entities_are_gone<ID, E>(repository: Backend<ID, E>) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let entity = E::specimen("Short-lived", 0);
+    store(&repository, std::slice::from_ref(&entity)).await?;
+
+    repository.delete(&entity.id()).await?;
+
+    assert_eq!(
+        repository.get_by_id_including_deleted(&entity.id()).await,
+        Ok(None)
+    );
+
+    Ok(())
+}
+
+pub async fn listings_are_newest_first_by_default<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    store(
+        &repository,
+        &[
+            E::specimen("b", 2),
+            E::specimen("c", 1),
+            E::specimen("a", 3),
+        ],
+    )
+    .await?;
+
+    let texts = list_texts(&repository, ListParameters::default()).await?;
+
+    assert_eq!(texts, ["c", "b", "a"]);
+
+    Ok(())
+}
+
+pub async fn listings_by_unknown_fields_are_newest_first<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    store(
+        &repository,
+        &[
+            E::specimen("b", 2),
+            E::specimen("c", 1),
+            E::specimen("a", 3),
+        ],
+    )
+    .await?;
+
+    let texts = list_texts(
+        &repository,
+        ListParameters {
+            order_by: Some("shoe_size".to_string()),
+            ordering: Some(Ordering::Ascending),
+            ..ListParameters::default()
+        },
+    )
+    .await?;
+
+    assert_eq!(texts, ["c", "b", "a"]);
+
+    Ok(())
+}
+
+pub async fn listings_follow_the_requested_order<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    store(
+        &repository,
+        &[
+            E::specimen("b", 1),
+            E::specimen("c", 2),
+            E::specimen("a", 3),
+        ],
+    )
+    .await?;
+    let ordered = |ordering| ListParameters {
+        order_by: Some(E::TEXT_FIELD.to_string()),
+        ordering: Some(ordering),
+        ..ListParameters::default()
+    };
+
+    let ascending = list_texts(&repository, ordered(Ordering::Ascending)).await?;
+    let descending = list_texts(&repository, ordered(Ordering::Descending)).await?;
+
+    assert_eq!(ascending, ["a", "b", "c"]);
+    assert_eq!(descending, ["c", "b", "a"]);
+
+    Ok(())
+}
+
+pub async fn listings_count_and_return_only_matching_entities<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    store(
+        &repository,
+        &[
+            E::specimen("Parking", 1),
+            E::specimen("Parking again", 2),
+            E::specimen("Hedges", 3),
+        ],
+    )
+    .await?;
+    let filtered = |filter| ListParameters {
+        filters: Some(filter),
+        ..ListParameters::default()
+    };
+
+    let exact = repository
+        .list(filtered(Filter::Eq(
+            E::TEXT_FIELD.to_string(),
+            "Hedges".to_string().into(),
+        )))
+        .await?;
+    let prefixed = repository
+        .list(filtered(Filter::Like(
+            E::TEXT_FIELD.to_string(),
+            "Parking%".to_string(),
+        )))
+        .await?;
+
+    assert_eq!((exact.total_count, exact.items.len()), (1, 1));
+    assert_eq!((prefixed.total_count, prefixed.items.len()), (2, 2));
+
+    Ok(())
+}
+
+pub async fn listings_page_by_number<ID, E>(repository: Backend<ID, E>) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let entities: Vec<E> = (0..5)
+        .map(|age| E::specimen(&age.to_string(), age))
+        .collect();
+    store(&repository, &entities).await?;
+
+    let page = repository
+        .list(ListParameters {
+            page_size: PageSize(2),
+            page_number: PageNumber(2),
+            ..ListParameters::default()
+        })
+        .await?;
+
+    assert_eq!(page.total_count, 5);
+    assert_eq!(
+        page.items.iter().map(text_of).collect::<Vec<_>>(),
+        ["2", "3"]
+    );
+    assert!(page.next_cursor.is_some() && page.previous_cursor.is_some());
+
+    Ok(())
+}
+
+pub async fn cursors_walk_every_entity_once_either_way<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync + PartialEq + Debug,
+    E: Specimen<ID>,
+{
+    // Entities sharing a creation time are told apart by their id
+    let entities: Vec<E> = (0..5)
+        .map(|age| E::specimen(&age.to_string(), age / 2))
+        .collect();
+    store(&repository, &entities).await?;
+    let mut list_parameters = ListParameters {
+        page_size: PageSize(2),
+        ..ListParameters::default()
+    };
+
+    let mut forwards = vec![];
+    let mut last_page = loop {
+        let page = repository.list(list_parameters.clone()).await?;
+        forwards.extend(page.items.iter().map(HasId::id));
+        match page.next_cursor.clone() {
+            Some(cursor) => list_parameters.cursor = Some(cursor),
+            None => break page,
+        }
+    };
+    let mut backwards: Vec<ID> = last_page.items.iter().rev().map(HasId::id).collect();
+    while let Some(cursor) = last_page.previous_cursor.clone() {
+        list_parameters.cursor = Some(cursor);
+        last_page = repository.list(list_parameters.clone()).await?;
+        backwards.extend(last_page.items.iter().rev().map(HasId::id));
+    }
+    backwards.reverse();
+
+    assert_eq!(forwards.len(), entities.len());
+    assert!(
+        entities
+            .iter()
+            .all(|entity| forwards.contains(&entity.id()))
+    );
+    assert_eq!(forwards, backwards);
+
+    Ok(())
+}
+
+pub async fn soft_deleted_entities_are_hidden_unless_asked_for<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let kept = E::specimen("Kept", 1);
+    let mut withdrawn = E::specimen("Withdrawn", 2);
+    store(&repository, &[kept, withdrawn.clone()]).await?;
+    withdrawn.withdraw(minutes_ago(0));
+    repository.update(withdrawn.clone()).await?;
+
+    let visible = list_texts(&repository, ListParameters::default()).await?;
+    let everything = list_texts(
+        &repository,
+        ListParameters {
+            include_deleted: true,
+            ..ListParameters::default()
+        },
+    )
+    .await?;
+
+    assert_eq!(visible, ["Kept"]);
+    assert_eq!(everything, ["Kept", "Withdrawn"]);
+    assert_eq!(repository.get_by_id(&withdrawn.id()).await, Ok(None));
+    assert!(
+        repository
+            .get_by_id_including_deleted(&withdrawn.id())
+            .await
+            .is_ok_and(|result| result.is_some_and(|entity| entity.is_deleted()))
+    );
+
+    Ok(())
+}
+
+pub async fn purging_removes_entities_deleted_before_the_cutoff<ID, E>(
+    repository: Backend<ID, E>,
+) -> Result<(), AnyError>
+where
+    ID: Send + Sync,
+    E: Specimen<ID>,
+{
+    let mut long_gone = E::specimen("Long gone", 3);
+    let mut recently_withdrawn = E::specimen("Recently withdrawn", 2);
+    let kept = E::specimen("Kept", 1);
+    store(
+        &repository,
+        &[long_gone.clone(), recently_withdrawn.clone(), kept.clone()],
+    )
+    .await?;
+    long_gone.withdraw(minutes_ago(60));
+    recently_withdrawn.withdraw(minutes_ago(1));
+    for entity in [long_gone.clone(), recently_withdrawn.clone()] {
+        repository.update(entity).await?;
+    }
+
+    let purged = repository.purge_deleted(minutes_ago(30)).await;
+
+    assert_eq!(purged, Ok(1));
+    assert_eq!(
+        repository
+            .get_by_id_including_deleted(&long_gone.id())
+            .await,
+        Ok(None)
+    );
+    for entity in [recently_withdrawn, kept] {
+        assert!(
+            repository
+                .get_by_id_including_deleted(&entity.id())
+                .await
+                .is_ok_and(|result| result.is_some())
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs every check of the contract against the backend, each time on a repository freshly
+/// produced by `$repository` for entities of type `$entity`
+macro_rules! repository_contract_tests {
+    ($suite:ident, $id:ty, $entity:ty, $repository:expr) => {
+        mod $suite {
+            #[allow(clippy::wildcard_imports)]
+            use super::*;
+            use crate::error::AnyError;
+            use crate::persistence::repository_contract as contract;
+
+            repository_contract_tests!(
+                @checks $id, $entity, $repository,
+                created_entities_can_be_read_back,
+                missing_entities_are_none,
+                creating_an_entity_twice_is_a_duplicate,
+                entities_can_be_created_together,
+                updates_are_stored_with_the_next_version,
+                stale_updates_are_conflicts,
+                overwrites_keep_the_version,
+                updating_a_missing_entity_is_not_found,
+                deleted_entities_are_gone,
+                listings_are_newest_first_by_default,
+                listings_by_unknown_fields_are_newest_first,
+                listings_follow_the_requested_order,
+                listings_count_and_return_only_matching_entities,
+                listings_page_by_number,
+                cursors_walk_every_entity_once_either_way,
+                soft_deleted_entities_are_hidden_unless_asked_for,
+                purging_removes_entities_deleted_before_the_cutoff
+            );
+        }
+    };
+    (@checks $id:ty, $entity:ty, $repository:expr, $($check:ident),+) => {
+        $(
+            #[tokio::test]
+            async fn $check() -> Result<(), AnyError> {
+                contract::$check::<$id, $entity>($repository.await?).await
+            }
+        )+
+    };
+}
+
+pub(crate) use repository_contract_tests;
@@ -0,0 +1,96 @@
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, DbErr, Statement, TransactionTrait};
+
+/// Shared by every instance of the forum, so only one of them migrates at a time
+static MIGRATION_LOCK_KEY: i64 = 0x4d6f_504d_5363_6831;
+
+/// Brings the schema up to date when asked to, then makes sure nothing is left pending,
+/// as serving requests against an outdated schema would only fail in more confusing ways
+pub async fn prepare_schema(db: &DatabaseConnection, apply_pending: bool) -> Result<(), DbErr> {
+    if apply_pending {
+        apply_pending_migrations(db).await?;
+    }
+
+    let pending: Vec<String> = Migrator::get_pending_migrations(db)
+        .await?
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect();
+    if !pending.is_empty() {
+        return Err(DbErr::Migration(format!(
+            "The schema is behind, pending migrations: {}. \
+            Set RUN_MIGRATIONS_ON_STARTUP=true to apply them on startup.",
+            pending.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// On Postgres, the migrations run in a transaction holding an advisory lock,
+/// so instances starting at the same time wait for the first one to finish
+/// and then find nothing left to do
+async fn apply_pending_migrations(db: &DatabaseConnection) -> Result<(), DbErr> {
+    if db.get_database_backend() != DbBackend::Postgres {
+        return Migrator::up(db, None).await;
+    }
+
+    let transaction = db.begin().await?;
+    transaction
+        .execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "SELECT pg_advisory_xact_lock($1)",
+            [MIGRATION_LOCK_KEY.into()],
+        ))
+        .await?;
+    println!("Applying pending migrations");
+    Migrator::up(&transaction, None).await?;
+
+    transaction.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::rdbms::connect;
+    use crate::testing::migrated_sqlite;
+
+    #[tokio::test]
+    async fn an_up_to_date_schema_is_ready_to_serve() -> Result<(), AnyError> {
+        let db = migrated_sqlite().await?;
+
+        let result = prepare_schema(&db, false).await;
+
+        assert!(result.is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_to_serve_while_migrations_are_pending() -> Result<(), AnyError> {
+        let db = migrated_sqlite().await?;
+        Migrator::down(&db, Some(1)).await?;
+
+        let refused = prepare_schema(&db, false).await;
+        let migrated = prepare_schema(&db, true).await;
+
+        assert!(matches!(refused, Err(DbErr::Migration(_))));
+        assert!(migrated.is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_is_only_migrated_when_asked_to() -> Result<(), AnyError> {
+        let db = connect(&"sqlite::memory:".to_string()).await?;
+
+        let refused = prepare_schema(&db, false).await;
+        let migrated = prepare_schema(&db, true).await;
+
+        assert!(matches!(refused, Err(DbErr::Migration(_))));
+        assert!(migrated.is_ok());
+
+        Ok(())
+    }
+}
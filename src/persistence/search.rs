@@ -0,0 +1,283 @@
+use crate::persistence::cursor::FieldValue;
+use crate::persistence::filter::FilterableAttributes;
+use crate::persistence::repository::{RepositoryError, SoftDeletable};
+use async_trait::async_trait;
+use std::cmp::Ordering;
+
+/// Marks where a highlighted word starts and ends in snippets coming from the database,
+/// picked as unlikely to turn up in what people write
+pub static HIGHLIGHT_START: &str = "⟦";
+pub static HIGHLIGHT_STOP: &str = "⟧";
+/// Roughly how many words a snippet shows around the first match
+pub static SNIPPET_WORDS: usize = 24;
+/// How much a match in a later field counts for, relative to one in the first field
+static LESSER_FIELD_WEIGHT: f64 = 0.4;
+
+/// Entities whose text can be searched
+pub trait Searchable: FilterableAttributes + SoftDeletable {
+    /// The text fields searched, from the most to the least significant.
+    /// Snippets are taken from the last one, which is expected to hold the body of the text.
+    const SEARCHED_FIELDS: &'static [&'static str];
+}
+
+/// A run of snippet text, highlighted when it's one of the words searched for
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SnippetFragment {
+    pub text: String,
+    pub is_match: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchHit<Entity> {
+    pub entity: Entity,
+    pub rank: f64,
+    pub snippet: Vec<SnippetFragment>,
+}
+
+#[async_trait]
+pub trait SearchIndex<ID, Entity>: Send + Sync {
+    /// The entities containing every word of the query, best matches first.
+    /// Deleted entities are left out.
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit<Entity>>, RepositoryError>;
+}
+
+/// The words of a query, lowercased, punctuation and SQL wildcards included in what's dropped
+pub fn search_terms(query: &str) -> Vec<String> {
+    words(query).map(|(_, word)| word.to_lowercase()).collect()
+}
+
+/// Words are runs of letters and digits, returned along with where they start
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = None;
+    text.char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+        .filter_map(move |(position, c)| {
+            if c.is_alphanumeric() {
+                start.get_or_insert(position);
+                return None;
+            }
+            let start = start.take()?;
+            text.get(start..position).map(|word| (start, word))
+        })
+}
+
+/// A word matches a term it starts with, which roughly stands in for stemming: `cats` matches `cat`
+fn is_match(word: &str, terms: &[String]) -> bool {
+    let word = word.to_lowercase();
+    terms.iter().any(|term| word.starts_with(term.as_str()))
+}
+
+fn field_text<Entity: Searchable>(entity: &Entity, field: &str) -> String {
+    match entity.get_field_value(field) {
+        FieldValue::Text(text) => text,
+        _ => String::new(),
+    }
+}
+
+/// Scores an entity by how often the terms occur in it, weighing the first field above the rest.
+/// Nothing is returned unless every term occurs somewhere.
+pub fn rank<Entity: Searchable>(entity: &Entity, terms: &[String]) -> Option<f64> {
+    let mut occurrences = vec![0.0; terms.len()];
+    for (position, field) in Entity::SEARCHED_FIELDS.iter().enumerate() {
+        let weight = if position == 0 {
+            1.0
+        } else {
+            LESSER_FIELD_WEIGHT
+        };
+        for (_, word) in words(&field_text(entity, field)) {
+            let word = word.to_lowercase();
+            for (term, count) in terms.iter().zip(occurrences.iter_mut()) {
+                if word.starts_with(term.as_str()) {
+                    *count += weight;
+                }
+            }
+        }
+    }
+
+    if terms.is_empty() || occurrences.contains(&0.0) {
+        return None;
+    }
+    Some(occurrences.iter().sum())
+}
+
+/// Up to `SNIPPET_WORDS` words of the text around its first match, with the matches highlighted
+pub fn snippet(text: &str, terms: &[String]) -> Vec<SnippetFragment> {
+    let words: Vec<(usize, &str)> = words(text).collect();
+    let first_match = words
+        .iter()
+        .position(|(_, word)| is_match(word, terms))
+        .unwrap_or(0);
+    let first = first_match.saturating_sub(SNIPPET_WORDS / 3);
+    let last = (first + SNIPPET_WORDS).min(words.len());
+    let (Some((start, _)), Some((last_start, last_word))) =
+        (words.get(first), words.get(last.saturating_sub(1)))
+    else {
+        return vec![];
+    };
+    let end = last_start + last_word.len();
+
+    let mut fragments = vec![];
+    let mut plain = String::new();
+    if first > 0 {
+        plain.push('…');
+    }
+    let mut cursor = *start;
+    for (offset, word) in words.iter().take(last).skip(first) {
+        if is_match(word, terms) {
+            plain.push_str(text.get(cursor..*offset).unwrap_or_default());
+            push_fragment(&mut fragments, std::mem::take(&mut plain), false);
+            push_fragment(&mut fragments, (*word).to_string(), true);
+            cursor = offset + word.len();
+        }
+    }
+    plain.push_str(text.get(cursor..end).unwrap_or_default());
+    if last < words.len() {
+        plain.push('…');
+    }
+    push_fragment(&mut fragments, plain, false);
+
+    fragments
+}
+
+fn push_fragment(fragments: &mut Vec<SnippetFragment>, text: String, is_match: bool) {
+    if !text.is_empty() {
+        fragments.push(SnippetFragment { text, is_match });
+    }
+}
+
+/// Splits a snippet highlighted by the database with `HIGHLIGHT_START` and `HIGHLIGHT_STOP`
+pub fn parse_highlighted(highlighted: &str) -> Vec<SnippetFragment> {
+    let mut fragments = vec![];
+    let mut rest = highlighted;
+    while let Some((before, after)) = rest.split_once(HIGHLIGHT_START) {
+        push_fragment(&mut fragments, before.to_string(), false);
+        let (matched, after) = after.split_once(HIGHLIGHT_STOP).unwrap_or((after, ""));
+        push_fragment(&mut fragments, matched.to_string(), true);
+        rest = after;
+    }
+    push_fragment(&mut fragments, rest.to_string(), false);
+
+    fragments
+}
+
+/// Searches entities already at hand, for backends without an index of their own
+pub fn search_entities<Entity: Searchable>(
+    entities: impl IntoIterator<Item = Entity>,
+    query: &str,
+    limit: usize,
+) -> Vec<SearchHit<Entity>> {
+    let terms = search_terms(query);
+    let mut hits: Vec<SearchHit<Entity>> = entities
+        .into_iter()
+        .filter(|entity| !entity.is_deleted())
+        .filter_map(|entity| {
+            let rank = rank(&entity, &terms)?;
+            let body = Entity::SEARCHED_FIELDS
+                .last()
+                .map(|field| field_text(&entity, field))
+                .unwrap_or_default();
+            Some(SearchHit {
+                snippet: snippet(&body, &terms),
+                entity,
+                rank,
+            })
+        })
+        .collect();
+    hits.sort_by(|a, b| b.rank.partial_cmp(&a.rank).unwrap_or(Ordering::Equal));
+    hits.truncate(limit);
+
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petty_matters::topic::Topic;
+    use chrono::Utc;
+
+    fn topic(title: &str, content: &str) -> Topic {
+        Topic {
+            title: title.to_string(),
+            content: content.to_string(),
+            ..Topic::default()
+        }
+    }
+
+    fn terms(query: &str) -> Vec<String> {
+        search_terms(query)
+    }
+
+    #[test]
+    fn every_word_of_the_query_has_to_occur() {
+        let topic = topic("Noisy cats", "They sing at dawn");
+
+        assert!(rank(&topic, &terms("cat dawn")).is_some());
+        assert!(rank(&topic, &terms("cat dusk")).is_none());
+        assert!(rank(&topic, &terms("%_")).is_none());
+    }
+
+    #[test]
+    fn matches_in_the_title_count_for_more() {
+        let in_title = topic("The hedge", "It is tall");
+        let in_content = topic("Greenery", "The hedge is tall");
+
+        let hits = search_entities([in_content, in_title.clone()], "hedge", 10);
+
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits.first().map(|hit| hit.entity.id), Some(in_title.id));
+    }
+
+    #[test]
+    fn deleted_entities_are_not_found() {
+        let mut deleted = topic("Bins", "Left out on the wrong day");
+        deleted.deleted_at = Some(Utc::now());
+
+        assert!(search_entities([deleted], "bins", 10).is_empty());
+    }
+
+    #[test]
+    fn snippets_highlight_the_matches_around_the_first_one() {
+        let long_text = format!("{} the parking spot, again. Parking.", "word ".repeat(40));
+
+        let fragments = snippet(&long_text, &terms("parking"));
+
+        let matches: Vec<&str> = fragments
+            .iter()
+            .filter(|fragment| fragment.is_match)
+            .map(|fragment| fragment.text.as_str())
+            .collect();
+        assert_eq!(matches, ["parking", "Parking"]);
+        assert!(fragments.first().is_some_and(|f| f.text.starts_with('…')));
+    }
+
+    #[test]
+    fn highlighted_snippets_from_the_database_are_split_into_fragments() {
+        let fragments = parse_highlighted("a ⟦cat⟧ on the ⟦mat⟧");
+
+        assert_eq!(
+            fragments,
+            [
+                SnippetFragment {
+                    text: "a ".to_string(),
+                    is_match: false
+                },
+                SnippetFragment {
+                    text: "cat".to_string(),
+                    is_match: true
+                },
+                SnippetFragment {
+                    text: " on the ".to_string(),
+                    is_match: false
+                },
+                SnippetFragment {
+                    text: "mat".to_string(),
+                    is_match: true
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use crate::persistence::repository::{HasId, ListParameters, Page, Repository, RepositoryError};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sea_orm::{DatabaseConnection, DatabaseTransaction, TransactionTrait};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+type Undo =
+    Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = Result<(), RepositoryError>> + Send>> + Send>;
+type Step =
+    Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = Result<Undo, RepositoryError>> + Send>> + Send>;
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// Staged writes go to stores without transactions of their own, so two changesets applied
+/// at once could interleave, and undoing one of them put back what the other just wrote
+static STAGED_COMMITS: Mutex<()> = Mutex::const_new(());
+
+/// Writes staged for repositories without transactions of their own, applied in order
+/// on commit and compensated in reverse order if any of them fails
+#[derive(Clone, Default)]
+pub struct Changeset {
+    steps: Arc<Mutex<Vec<Step>>>,
+}
+
+impl Changeset {
+    async fn stage(&self, step: Step) {
+        self.steps.lock().await.push(step);
+    }
+
+    async fn is_empty(&self) -> bool {
+        self.steps.lock().await.is_empty()
+    }
+
+    async fn apply(&self) -> Result<Vec<Undo>, RepositoryError> {
+        let steps = std::mem::take(&mut *self.steps.lock().await);
+        let mut undos = Vec::with_capacity(steps.len());
+        for step in steps {
+            match step().await {
+                Ok(undo) => undos.push(undo),
+                Err(e) => return Err(Self::undo(undos, e).await),
+            }
+        }
+
+        Ok(undos)
+    }
+
+    /// Undoes every write in reverse order, returning the error that called for it
+    /// unless undoing failed as well, leaving the stores with part of the changeset
+    async fn undo(undos: Vec<Undo>, cause: RepositoryError) -> RepositoryError {
+        for undo in undos.into_iter().rev() {
+            if let Err(e) = undo().await {
+                return RepositoryError::GenericError(format!(
+                    "Failed to undo a staged write after {cause}: {e}"
+                ));
+            }
+        }
+
+        cause
+    }
+}
+
+/// Hands out transactions spanning every repository, on the database when there is one
+#[derive(Clone, Default)]
+pub struct UnitOfWork {
+    database: Option<DatabaseConnection>,
+}
+
+impl UnitOfWork {
+    pub const fn new(database: Option<DatabaseConnection>) -> Self {
+        Self { database }
+    }
+
+    pub async fn begin(&self) -> Result<Transaction, RepositoryError> {
+        let database = match &self.database {
+            Some(db) => Some(Arc::new(db.begin().await?)),
+            None => None,
+        };
+
+        Ok(Transaction {
+            database,
+            changeset: Changeset::default(),
+            after_commit: std::sync::Mutex::default(),
+        })
+    }
+}
+
+/// Repositories scoped to a transaction with [`Repository::within`] write through it,
+/// and nothing they write takes effect until [`Transaction::commit`].
+/// The scoped repositories must be dropped before committing.
+pub struct Transaction {
+    database: Option<Arc<DatabaseTransaction>>,
+    changeset: Changeset,
+    after_commit: std::sync::Mutex<Vec<Hook>>,
+}
+
+impl Transaction {
+    pub const fn database(&self) -> Option<&Arc<DatabaseTransaction>> {
+        self.database.as_ref()
+    }
+
+    pub const fn changeset(&self) -> &Changeset {
+        &self.changeset
+    }
+
+    /// Runs once everything written within the transaction has taken effect,
+    /// and never if it is rolled back
+    pub fn after_commit(&self, hook: impl FnOnce() + Send + 'static) {
+        if let Ok(mut hooks) = self.after_commit.lock() {
+            hooks.push(Box::new(hook));
+        }
+    }
+
+    pub async fn commit(self) -> Result<(), RepositoryError> {
+        let staged_commit = if self.changeset.is_empty().await {
+            None
+        } else {
+            Some(STAGED_COMMITS.lock().await)
+        };
+        let undos = self.changeset.apply().await?;
+        let outcome = match self.database.map(Arc::try_unwrap) {
+            Some(Ok(database)) => database.commit().await.map_err(RepositoryError::from),
+            Some(Err(_)) => Err(RepositoryError::GenericError(
+                "The transaction is still in use by a repository".to_string(),
+            )),
+            None => Ok(()),
+        };
+        if let Err(e) = outcome {
+            return Err(Changeset::undo(undos, e).await);
+        }
+        drop(staged_commit);
+
+        let hooks = self.after_commit.into_inner().unwrap_or_default();
+        for hook in hooks {
+            hook();
+        }
+
+        Ok(())
+    }
+
+    /// Discards everything written within the transaction
+    pub async fn rollback(self) -> Result<(), RepositoryError> {
+        match self.database.map(Arc::try_unwrap) {
+            Some(Ok(database)) => Ok(database.rollback().await?),
+            // Transactions left behind roll back once the last reference is dropped
+            Some(Err(_)) | None => Ok(()),
+        }
+    }
+}
+
+/// Defers writes to a repository until the transaction's changeset is applied.
+/// Reads are not affected by the staged writes and return what has been committed so far.
+pub struct StagedRepository<ID, Entity> {
+    repository: Arc<dyn Repository<ID, Entity> + Send + Sync>,
+    changeset: Changeset,
+}
+
+impl<ID, Entity> StagedRepository<ID, Entity>
+where
+    ID: Send + Sync + Clone + 'static,
+    Entity: Send + Sync + Clone + HasId<ID> + 'static,
+{
+    pub fn scoped(
+        repository: Arc<dyn Repository<ID, Entity> + Send + Sync>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<ID, Entity> + Send + Sync> {
+        Arc::new(Self {
+            repository,
+            changeset: transaction.changeset().clone(),
+        })
+    }
+
+    /// Puts back an entity as it was before a staged write replaced or removed it
+    fn reinstate(
+        repository: Arc<dyn Repository<ID, Entity> + Send + Sync>,
+        id: ID,
+        previous: Option<Entity>,
+    ) -> Undo {
+        Box::new(move || {
+            Box::pin(async move {
+                match (repository.delete(&id).await, previous) {
+                    (Ok(()), Some(previous)) => repository.create(previous).await,
+                    (outcome, _) => outcome,
+                }
+            })
+        })
+    }
+}
+
+#[async_trait]
+impl<ID, Entity> Repository<ID, Entity> for StagedRepository<ID, Entity>
+where
+    ID: Send + Sync + Clone + 'static,
+    Entity: Send + Sync + Clone + HasId<ID> + 'static,
+{
+    async fn list(&self, list_parameters: ListParameters) -> Result<Page<Entity>, RepositoryError> {
+        self.repository.list(list_parameters).await
+    }
+
+    async fn create(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let repository = self.repository.clone();
+        let undo = Self::reinstate(repository.clone(), entity.id(), None);
+        self.changeset
+            .stage(Box::new(move || {
+                Box::pin(async move {
+                    repository.create(entity).await?;
+                    Ok(undo)
+                })
+            }))
+            .await;
+
+        Ok(())
+    }
+
+    async fn update(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let repository = self.repository.clone();
+        self.changeset
+            .stage(Box::new(move || {
+                Box::pin(async move {
+                    let id = entity.id();
+                    let previous = repository.get_by_id_including_deleted(&id).await?;
+                    repository.update(entity).await?;
+                    Ok(Self::reinstate(repository, id, previous))
+                })
+            }))
+            .await;
+
+        Ok(())
+    }
+
+    async fn overwrite(&self, entity: Entity) -> Result<(), RepositoryError> {
+        let repository = self.repository.clone();
+        self.changeset
+            .stage(Box::new(move || {
+                Box::pin(async move {
+                    let id = entity.id();
+                    let previous = repository.get_by_id_including_deleted(&id).await?;
+                    repository.overwrite(entity).await?;
+                    Ok(Self::reinstate(repository, id, previous))
+                })
+            }))
+            .await;
+
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: &ID) -> Result<Option<Entity>, RepositoryError> {
+        self.repository.get_by_id(id).await
+    }
+
+    async fn get_by_id_including_deleted(
+        &self,
+        id: &ID,
+    ) -> Result<Option<Entity>, RepositoryError> {
+        self.repository.get_by_id_including_deleted(id).await
+    }
+
+    async fn delete(&self, id: &ID) -> Result<(), RepositoryError> {
+        let repository = self.repository.clone();
+        let id = id.clone();
+        self.changeset
+            .stage(Box::new(move || {
+                Box::pin(async move {
+                    let previous = repository.get_by_id_including_deleted(&id).await?;
+                    repository.delete(&id).await?;
+                    Ok(Self::reinstate(repository, id, previous))
+                })
+            }))
+            .await;
+
+        Ok(())
+    }
+
+    async fn purge_deleted(&self, _deleted_before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        Err(RepositoryError::GenericError(
+            "Purging cannot be staged in a unit of work".to_string(),
+        ))
+    }
+
+    fn within(
+        self: Arc<Self>,
+        transaction: &Transaction,
+    ) -> Arc<dyn Repository<ID, Entity> + Send + Sync> {
+        Self::scoped(self.repository.clone(), transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::in_memory_repository::InMemoryRepository;
+    use crate::petty_matters::topic::{Topic, TopicId};
+
+    fn repository() -> Arc<dyn Repository<TopicId, Topic> + Send + Sync> {
+        Arc::new(InMemoryRepository::<TopicId, Topic>::new())
+    }
+
+    #[tokio::test]
+    async fn staged_writes_only_take_effect_on_commit() -> Result<(), AnyError> {
+        let repository = repository();
+        let topic = Topic::default();
+        let transaction = UnitOfWork::default().begin().await?;
+        let scoped = repository.clone().within(&transaction);
+
+        scoped.create(topic.clone()).await?;
+        let before_commit = repository.get_by_id(&topic.id).await?;
+        drop(scoped);
+        transaction.commit().await?;
+
+        assert_eq!(before_commit, None);
+        assert!(
+            repository
+                .get_by_id(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_some())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_failing_write_undoes_the_ones_before_it() -> Result<(), AnyError> {
+        let repository = repository();
+        let created = Topic::default();
+        let never_stored = Topic::default();
+        let transaction = UnitOfWork::default().begin().await?;
+        let scoped = repository.clone().within(&transaction);
+        scoped.create(created.clone()).await?;
+        scoped.update(never_stored).await?;
+        drop(scoped);
+
+        let result = transaction.commit().await;
+
+        assert!(result.is_err());
+        assert!(
+            repository
+                .get_by_id(&created.id)
+                .await
+                .is_ok_and(|result| result.is_none())
+        );
+
+        Ok(())
+    }
+}
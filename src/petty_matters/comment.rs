@@ -1,13 +1,15 @@
 use crate::authn::session::{User, Username};
-use crate::persistence::in_memory_repository::FilterableAttributes;
-use crate::persistence::repository::HasId;
-use crate::petty_matters::topic::TopicId;
+use crate::persistence::cursor::FieldValue;
+use crate::persistence::filter::{FieldType, FilterableAttributes};
+use crate::persistence::repository::{HasId, SoftDeletable, Versioned};
+use crate::persistence::search::Searchable;
+use crate::petty_matters::topic::{Topic, TopicId};
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 use uuid::Uuid;
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub struct CommentId(pub Uuid);
 
 impl Display for CommentId {
@@ -16,7 +18,7 @@ impl Display for CommentId {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Comment {
     pub id: CommentId,
     pub topic_id: TopicId,
@@ -26,6 +28,12 @@ pub struct Comment {
     pub created_by: Username,
     pub creation_time: DateTime<Utc>,
     pub last_updated_time: Option<DateTime<Utc>>,
+    pub version: u32,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub deleted_by: Option<Username>,
+    /// Withdrawn as part of withdrawing its petty matter, so restoring that restores it too
+    #[serde(default)]
+    pub withdrawn_with_topic: bool,
 }
 
 impl Comment {
@@ -39,8 +47,40 @@ impl Comment {
             created_by: author.email,
             creation_time: Utc::now(),
             last_updated_time: None,
+            version: 0,
+            deleted_at: None,
+            deleted_by: None,
+            withdrawn_with_topic: false,
         }
     }
+
+    pub fn is_authored_by(&self, user: &User) -> bool {
+        !user.is_anonymous && self.created_by == user.email
+    }
+
+    pub(crate) fn edit(&mut self, content: String) {
+        self.content = content;
+        self.last_updated_time = Some(Utc::now());
+    }
+
+    pub(crate) fn mark_deleted(&mut self, deleted_by: &User) {
+        self.deleted_at = Some(Utc::now());
+        self.deleted_by = Some(deleted_by.email.clone());
+        self.withdrawn_with_topic = false;
+    }
+
+    /// Shares the withdrawal of its petty matter, so restoring one restores the other
+    pub(crate) fn mark_deleted_along_with(&mut self, topic: &Topic) {
+        self.deleted_at = topic.deleted_at;
+        self.deleted_by.clone_from(&topic.deleted_by);
+        self.withdrawn_with_topic = true;
+    }
+
+    pub(crate) fn restore(&mut self) {
+        self.deleted_at = None;
+        self.deleted_by = None;
+        self.withdrawn_with_topic = false;
+    }
 }
 
 impl HasId<CommentId> for Comment {
@@ -49,14 +89,53 @@ impl HasId<CommentId> for Comment {
     }
 }
 
+impl Versioned for Comment {
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn increment_version(&mut self) {
+        self.version += 1;
+    }
+}
+
+impl SoftDeletable for Comment {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        self.deleted_at
+    }
+}
+
 impl FilterableAttributes for Comment {
-    type Output = Option<String>;
+    const FIELDS: &'static [(&'static str, FieldType)] = &[
+        ("id", FieldType::Uuid),
+        ("topic_id", FieldType::Uuid),
+        ("content", FieldType::Text),
+        ("upvotes_count", FieldType::Integer),
+        ("downvotes_count", FieldType::Integer),
+        ("created_by", FieldType::Text),
+        ("creation_time", FieldType::Timestamp),
+        ("last_updated_time", FieldType::Timestamp),
+        ("deleted_at", FieldType::Timestamp),
+        ("deleted_by", FieldType::Text),
+    ];
 
-    fn get_field_value(&self, field: &str) -> Self::Output {
+    fn get_field_value(&self, field: &str) -> FieldValue {
         match field {
-            "id" => Some(self.id.to_string()),
-            "topic_id" => Some(self.topic_id.to_string()),
-            _ => None,
+            "id" => self.id.0.into(),
+            "topic_id" => self.topic_id.0.into(),
+            "content" => self.content.clone().into(),
+            "upvotes_count" => self.upvotes_count.into(),
+            "downvotes_count" => self.downvotes_count.into(),
+            "created_by" => self.created_by.0.clone().into(),
+            "creation_time" => self.creation_time.into(),
+            "last_updated_time" => self.last_updated_time.into(),
+            "deleted_at" => self.deleted_at.into(),
+            "deleted_by" => self.deleted_by.as_ref().map(|u| u.0.clone()).into(),
+            _ => FieldValue::Null,
         }
     }
 }
+
+impl Searchable for Comment {
+    const SEARCHED_FIELDS: &'static [&'static str] = &["content"];
+}
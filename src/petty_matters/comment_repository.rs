@@ -2,11 +2,11 @@ use crate::authn::session::Username;
 use crate::persistence::rdbms::ModelDatabaseInterface;
 use crate::persistence::repository::{HasId, ListParameters};
 use crate::petty_matters::comment::{Comment, CommentId};
-use crate::petty_matters::topic::TopicId;
 use chrono::Utc;
 use sea_orm::entity::prelude::*;
-use sea_orm::{Condition, DeriveEntityModel, Order, Set};
+use sea_orm::{DeriveEntityModel, Order, Set};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "comments")]
@@ -20,6 +20,10 @@ pub struct Model {
     pub created_by: String,
     pub creation_time: chrono::DateTime<Utc>,
     pub last_updated_time: Option<chrono::DateTime<Utc>>,
+    pub version: i32,
+    pub deleted_at: Option<chrono::DateTime<Utc>>,
+    pub deleted_by: Option<String>,
+    pub withdrawn_with_topic: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -53,40 +57,12 @@ impl HasId<Uuid> for Model {
 }
 
 impl ModelDatabaseInterface<Self, Comment, CommentId> for Entity {
-    fn filter_from_params(list_parameters: &ListParameters) -> Condition {
-        let mut condition = Condition::all();
-        if let Some(filters) = &list_parameters.filters {
-            for (key, val) in filters {
-                match key.as_str() {
-                    "topic_id" => {
-                        condition = condition.add(Column::TopicId.eq(TopicId::from(val).0));
-                    }
-                    "created_by" => condition = condition.add(Column::CreatedBy.eq(val)),
-                    "creation_time" => condition = condition.add(Column::CreationTime.gte(val)),
-                    _ => {}
-                }
-            }
-        }
-
-        condition
-    }
-
     fn order_by_from_params(
         list_parameters: &ListParameters,
     ) -> (<Self as EntityTrait>::Column, Order) {
-        list_parameters
-            .order_by
-            .as_ref()
-            .map_or((Column::CreationTime, Order::Desc), |order_by| {
-                let column = match order_by.as_str() {
-                    "created_by" => Column::CreatedBy,
-                    _ => Column::CreationTime,
-                };
-                (
-                    column,
-                    list_parameters.ordering.clone().unwrap_or_default().into(),
-                )
-            })
+        let column = Column::from_str(list_parameters.sort_field::<Comment>())
+            .unwrap_or(Column::CreationTime);
+        (column, list_parameters.sort_ordering::<Comment>().into())
     }
 
     #[allow(clippy::cast_sign_loss)]
@@ -100,6 +76,10 @@ impl ModelDatabaseInterface<Self, Comment, CommentId> for Entity {
             created_by: Username(record.created_by),
             creation_time: record.creation_time,
             last_updated_time: record.last_updated_time,
+            version: record.version as u32,
+            deleted_at: record.deleted_at,
+            deleted_by: record.deleted_by.map(Username),
+            withdrawn_with_topic: record.withdrawn_with_topic,
         }
     }
 
@@ -113,7 +93,11 @@ impl ModelDatabaseInterface<Self, Comment, CommentId> for Entity {
             downvotes_count: Set(model.downvotes_count as i32),
             created_by: Set(model.created_by.to_string()),
             creation_time: Set(model.creation_time),
-            last_updated_time: Set(Option::from(Utc::now())),
+            last_updated_time: Set(model.last_updated_time),
+            version: Set(model.version as i32),
+            deleted_at: Set(model.deleted_at),
+            deleted_by: Set(model.deleted_by.map(|username| username.0)),
+            withdrawn_with_topic: Set(model.withdrawn_with_topic),
         }
     }
 
@@ -122,4 +106,16 @@ impl ModelDatabaseInterface<Self, Comment, CommentId> for Entity {
     ) -> <<Self as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType {
         id.0
     }
+
+    fn id_column() -> <Self as EntityTrait>::Column {
+        Column::Id
+    }
+
+    fn version_column() -> <Self as EntityTrait>::Column {
+        Column::Version
+    }
+
+    fn deleted_at_column() -> <Self as EntityTrait>::Column {
+        Column::DeletedAt
+    }
 }
@@ -1,31 +1,56 @@
 use crate::authn::session::User;
+use crate::config::APP_CONFIG;
 use crate::error::AnyError;
 use crate::feature_flags::FEATURE_FLAGS;
+use crate::persistence::cached_repository::CachedRepository;
+use crate::persistence::file_repository::{CompactionJob, FileRepository};
+use crate::persistence::filter::Filter;
+use crate::persistence::health::{
+    DATABASE_HEALTH, DatabaseHealth, DatabaseStatus, SchemaState, start_health_check,
+};
 use crate::persistence::in_memory_repository::InMemoryRepository;
-use crate::persistence::rdbms::RdbmsRepository;
-use crate::persistence::repository::{ListParameters, Page, Repository, RepositoryError};
+use crate::persistence::rdbms::{RdbmsRepository, connect, connect_lazily};
+use crate::persistence::replication::ReplicatedConnection;
+use crate::persistence::repository::{
+    ListParameters, Page, Repository, RepositoryError, SoftDeletable,
+};
+use crate::persistence::schema::prepare_schema;
+use crate::persistence::search::{SearchHit, SearchIndex};
+use crate::persistence::unit_of_work::UnitOfWork;
 use crate::petty_matters::comment::{Comment, CommentId};
 use crate::petty_matters::comment_repository::Entity as CommentDbModel;
 use crate::petty_matters::topic::{Topic, TopicId};
 use crate::petty_matters::topic_repository::Entity as TopicDbModel;
-use crate::queue::base::{Queue, QueueError, WriteOperation};
-use crate::queue::in_memory_queue::WriteQueue;
-use crate::queue::worker::start_write_worker;
-use moka::future::Cache;
-use moka::policy::EvictionPolicy;
-use sea_orm::{DatabaseConnection, DbErr};
-use std::collections::BTreeMap;
-use std::sync::{Arc, LazyLock};
+use crate::queue::base::{Completion, Queue, QueueError, WriteOperation};
+use crate::queue::events::EventBus;
+use crate::queue::idempotency::{IdempotencyKeys, InMemoryIdempotencyKeys, idempotency_key};
+use crate::queue::journal_outbox::JournalOutbox;
+use crate::queue::outbox::{Outbox, OutboxQueue};
+use crate::queue::rdbms_idempotency::RdbmsIdempotencyKeys;
+use crate::queue::rdbms_outbox::RdbmsOutbox;
+use crate::queue::worker::{start_outbox_worker, supervise};
+use crate::scheduler::base::{InMemoryJobRuns, Job, JobRuns, Schedule};
+use crate::scheduler::locks::{AdvisoryJobLocks, InProcessJobLocks, JobLocks};
+use crate::scheduler::rdbms_job_runs::RdbmsJobRuns;
+use crate::scheduler::runner::Scheduler;
+use crate::time::{Days, Hours, Seconds};
+use async_trait::async_trait;
+use chrono::Utc;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, DbErr};
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::channel;
 
-static CACHE: LazyLock<Cache<ListParameters, Page<Topic>>> = LazyLock::new(|| {
-    Cache::builder()
-        .eviction_policy(EvictionPolicy::tiny_lfu())
-        .time_to_live(Duration::from_secs(30))
-        .max_capacity(10_000)
-        .build()
-});
+/// How long the purge job waits for its write to be applied before leaving it to the worker
+static PURGE_WAIT: Duration = Duration::from_mins(1);
+static SEARCH_RESULTS_LIMIT: usize = 20;
+
+/// What a search turned up, petty matters and comments apart
+pub struct SearchResults {
+    pub topics: Vec<SearchHit<Topic>>,
+    /// Each comment along with the petty matter it was made on
+    pub comments: Vec<(SearchHit<Comment>, Topic)>,
+}
 
 pub struct PettyMattersService<Q>
 where
@@ -33,50 +58,161 @@ where
 {
     pub topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
     pub comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+    pub topic_search: Arc<dyn SearchIndex<TopicId, Topic> + Send + Sync>,
+    pub comment_search: Arc<dyn SearchIndex<CommentId, Comment> + Send + Sync>,
     pub write_queue: Arc<Q>,
+    pub database_health: Arc<DatabaseHealth>,
+    /// Where what comes of the writes is published, once they're applied
+    pub events: EventBus,
 }
 
 impl<Q> PettyMattersService<Q>
 where
     Q: Queue + Send + Sync,
 {
-    pub const fn new(
+    pub fn new(
         topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
         comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+        topic_search: Arc<dyn SearchIndex<TopicId, Topic> + Send + Sync>,
+        comment_search: Arc<dyn SearchIndex<CommentId, Comment> + Send + Sync>,
         write_queue: Arc<Q>,
     ) -> Self {
         Self {
             topic_repository,
             comment_repository,
+            topic_search,
+            comment_search,
             write_queue,
+            database_health: Arc::new(DatabaseHealth::default()),
+            events: EventBus::default(),
         }
     }
 
-    pub async fn create_topic(&self, topic: Topic) -> Result<(), QueueError> {
-        self.write_queue
-            .enqueue(WriteOperation::CreateTopic(topic))
-            .await
+    /// Refuses writes for as long as the database is reported unavailable
+    pub fn with_database_health(mut self, database_health: Arc<DatabaseHealth>) -> Self {
+        self.database_health = database_health;
+        self
+    }
+
+    /// The bus the write queue publishes to, for anything to subscribe to
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    async fn enqueue(&self, operation: WriteOperation) -> Result<Completion, QueueError> {
+        if self.database_health.is_degraded() {
+            return Err(QueueError::Unavailable(
+                "The Ministry's records are read-only for the time being".to_string(),
+            ));
+        }
+
+        let operation = match idempotency_key() {
+            Some(key) => WriteOperation::Idempotent(key, Box::new(operation)),
+            None => operation,
+        };
+        self.write_queue.enqueue(operation).await
+    }
+
+    pub async fn create_topic(&self, topic: Topic) -> Result<Completion, QueueError> {
+        self.enqueue(WriteOperation::CreateTopic(topic)).await
     }
 
     pub async fn get_topic(&self, topic_id: &TopicId) -> Result<Option<Topic>, RepositoryError> {
         self.topic_repository.get_by_id(topic_id).await
     }
 
-    pub async fn list_topics(
+    pub async fn edit_topic(
         &self,
-        list_parameters: ListParameters,
-    ) -> Result<Page<Topic>, RepositoryError> {
-        if let Some(cached) = CACHE.get(&list_parameters).await {
-            return Ok(cached);
+        mut topic: Topic,
+        title: String,
+        content: String,
+        expected_version: u32,
+        user: &User,
+    ) -> Result<Completion, QueueError> {
+        if !topic.is_authored_by(user) {
+            return Err(QueueError::PermissionDenied(
+                "Only the author can edit a petty matter".to_string(),
+            ));
+        }
+        if topic.version != expected_version {
+            return Err(QueueError::Conflict(
+                "The petty matter was amended since it was opened for editing".to_string(),
+            ));
+        }
+        if title.is_empty() || content.is_empty() {
+            return Err(QueueError::InvalidInput(
+                "Petty matters need both a name and a description".to_string(),
+            ));
         }
 
-        match self.topic_repository.list(list_parameters.clone()).await {
-            Ok(page) => {
-                CACHE.insert(list_parameters, page.clone()).await;
-                Ok(page)
-            }
-            Err(e) => Err(e),
+        topic.edit(title, content);
+        self.enqueue(WriteOperation::UpdateTopic(topic)).await
+    }
+
+    pub async fn get_topic_including_deleted(
+        &self,
+        topic_id: &TopicId,
+    ) -> Result<Option<Topic>, RepositoryError> {
+        self.topic_repository
+            .get_by_id_including_deleted(topic_id)
+            .await
+    }
+
+    pub async fn delete_topic(
+        &self,
+        mut topic: Topic,
+        expected_version: u32,
+        user: &User,
+    ) -> Result<Completion, QueueError> {
+        if !topic.is_authored_by(user) {
+            return Err(QueueError::PermissionDenied(
+                "Only the author can withdraw a petty matter".to_string(),
+            ));
+        }
+        if topic.version != expected_version {
+            return Err(QueueError::Conflict(
+                "The petty matter was amended since it was opened".to_string(),
+            ));
+        }
+
+        // Its comments are withdrawn along with it as the write is applied
+        topic.mark_deleted(user);
+        self.enqueue(WriteOperation::UpdateTopic(topic)).await
+    }
+
+    pub async fn restore_topic(
+        &self,
+        mut topic: Topic,
+        expected_version: u32,
+        user: &User,
+    ) -> Result<Completion, QueueError> {
+        if !topic.is_authored_by(user) {
+            return Err(QueueError::PermissionDenied(
+                "Only the author can restore a petty matter".to_string(),
+            ));
+        }
+        if topic.version != expected_version {
+            return Err(QueueError::Conflict(
+                "The petty matter was amended since it was opened".to_string(),
+            ));
+        }
+        if !topic.is_deleted() {
+            return Err(QueueError::InvalidInput(
+                "The petty matter has not been withdrawn".to_string(),
+            ));
         }
+
+        // Comments withdrawn along with the petty matter come back with it as the write is applied
+        topic.restore();
+        self.enqueue(WriteOperation::UpdateTopic(topic)).await
+    }
+
+    pub async fn list_topics(
+        &self,
+        list_parameters: ListParameters,
+    ) -> Result<Page<Topic>, RepositoryError> {
+        self.topic_repository.list(list_parameters).await
     }
 
     pub async fn reply_to_topic(
@@ -84,7 +220,7 @@ where
         topic_id: &TopicId,
         message: String,
         user: User,
-    ) -> Result<(), QueueError> {
+    ) -> Result<Completion, QueueError> {
         if message.is_empty() {
             return Err(QueueError::InvalidInput(
                 "Comment body cannot be empty".to_string(),
@@ -92,80 +228,404 @@ where
         }
 
         let comment = Comment::new(*topic_id, message, user);
-        self.write_queue
-            .enqueue(WriteOperation::AddComment(comment))
+        self.enqueue(WriteOperation::AddComment(comment)).await
+    }
+
+    pub async fn get_comment(
+        &self,
+        comment_id: &CommentId,
+    ) -> Result<Option<Comment>, RepositoryError> {
+        self.comment_repository.get_by_id(comment_id).await
+    }
+
+    pub async fn edit_comment(
+        &self,
+        mut comment: Comment,
+        message: String,
+        expected_version: u32,
+        user: &User,
+    ) -> Result<Completion, QueueError> {
+        if !comment.is_authored_by(user) {
+            return Err(QueueError::PermissionDenied(
+                "Only the author can edit a comment".to_string(),
+            ));
+        }
+        if comment.version != expected_version {
+            return Err(QueueError::Conflict(
+                "The comment was amended since it was opened for editing".to_string(),
+            ));
+        }
+        if message.is_empty() {
+            return Err(QueueError::InvalidInput(
+                "Comment body cannot be empty".to_string(),
+            ));
+        }
+
+        comment.edit(message);
+        self.enqueue(WriteOperation::UpdateComment(comment)).await
+    }
+
+    pub async fn get_comment_including_deleted(
+        &self,
+        comment_id: &CommentId,
+    ) -> Result<Option<Comment>, RepositoryError> {
+        self.comment_repository
+            .get_by_id_including_deleted(comment_id)
+            .await
+    }
+
+    pub async fn delete_comment(
+        &self,
+        mut comment: Comment,
+        expected_version: u32,
+        user: &User,
+    ) -> Result<Completion, QueueError> {
+        if !comment.is_authored_by(user) {
+            return Err(QueueError::PermissionDenied(
+                "Only the author can withdraw a comment".to_string(),
+            ));
+        }
+        if comment.version != expected_version {
+            return Err(QueueError::Conflict(
+                "The comment was amended since it was opened".to_string(),
+            ));
+        }
+
+        comment.mark_deleted(user);
+        self.enqueue(WriteOperation::UpdateComment(comment)).await
+    }
+
+    pub async fn restore_comment(
+        &self,
+        mut comment: Comment,
+        expected_version: u32,
+        user: &User,
+    ) -> Result<Completion, QueueError> {
+        if !comment.is_authored_by(user) {
+            return Err(QueueError::PermissionDenied(
+                "Only the author can restore a comment".to_string(),
+            ));
+        }
+        if comment.version != expected_version {
+            return Err(QueueError::Conflict(
+                "The comment was amended since it was opened".to_string(),
+            ));
+        }
+        if !comment.is_deleted() {
+            return Err(QueueError::InvalidInput(
+                "The comment has not been withdrawn".to_string(),
+            ));
+        }
+
+        comment.restore();
+        self.enqueue(WriteOperation::UpdateComment(comment)).await
+    }
+
+    /// Hard-deletes whatever was soft-deleted longer ago than the retention period
+    pub async fn purge_deleted(&self, retention: Days) -> Result<Completion, QueueError> {
+        let retention = chrono::Duration::seconds(i64::from(Seconds::from(retention).0));
+        self.enqueue(WriteOperation::PurgeDeleted(Utc::now() - retention))
             .await
     }
 
+    /// Every comment on the petty matter matching the listing, page after page
     pub async fn list_comments(
         &self,
         for_topic: &TopicId,
         mut list_parameters: ListParameters,
     ) -> Result<Page<Comment>, RepositoryError> {
-        list_parameters.filters = Some(BTreeMap::from([(
-            "topic_id".to_string(),
-            for_topic.to_string(),
-        )]));
+        list_parameters.add_filter(Filter::Eq("topic_id".to_string(), for_topic.0.into()));
         self.comment_repository.list(list_parameters).await
     }
+
+    /// Comments are only shown while the petty matter they were made on is still around
+    pub async fn search(&self, query: &str) -> Result<SearchResults, RepositoryError> {
+        let topics = self
+            .topic_search
+            .search(query, SEARCH_RESULTS_LIMIT)
+            .await?;
+        let mut comments = vec![];
+        for hit in self
+            .comment_search
+            .search(query, SEARCH_RESULTS_LIMIT)
+            .await?
+        {
+            if let Some(topic) = self
+                .topic_repository
+                .get_by_id(&hit.entity.topic_id)
+                .await?
+            {
+                comments.push((hit, topic));
+            }
+        }
+
+        Ok(SearchResults { topics, comments })
+    }
 }
 
-pub fn petty_matters_service_factory(
+pub async fn petty_matters_service_factory(
     db_connection: Result<DatabaseConnection, DbErr>,
-) -> Result<Arc<PettyMattersService<WriteQueue>>, AnyError> {
+) -> Result<Arc<PettyMattersService<OutboxQueue>>, AnyError> {
     println!("Instantiating Petty Matters service");
 
+    let (database, schema) = establish_database(db_connection).await?;
+
     let topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync>;
     let comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync>;
-    match db_connection {
-        Ok(db) => {
-            println!("Connection established");
-            topic_repository = Arc::new(RdbmsRepository::<TopicDbModel>::new(db.clone()));
-            comment_repository = Arc::new(RdbmsRepository::<CommentDbModel>::new(db));
-        }
-        Err(e) => {
-            if FEATURE_FLAGS.is_ephemeral_db_allowed {
-                eprintln!(
-                    "Database connection failed: {e},
-                    using in-memory repositories as fallback.
-                    If you'd like to disallow the fallback behavior,
-                    set the EPHEMERAL_DB_ALLOWED environment variable to false."
-                );
-                topic_repository = Arc::new(InMemoryRepository::<TopicId, Topic>::new());
-                comment_repository = Arc::new(InMemoryRepository::<CommentId, Comment>::new());
-            } else {
-                eprintln!(
-                    "Database connection failed: {e}
-                    and ephemeral DB is not allowed, exiting.
-                    To allow the application to run with an in-memory database,
-                    set the EPHEMERAL_DB_ALLOWED environment variable to true."
-                );
-                return Err(e.into());
-            }
-        }
+    let topic_search: Arc<dyn SearchIndex<TopicId, Topic> + Send + Sync>;
+    let comment_search: Arc<dyn SearchIndex<CommentId, Comment> + Send + Sync>;
+    let outbox: Arc<dyn Outbox>;
+    let idempotency_window = Duration::from_secs(u64::from(APP_CONFIG.idempotency_window.0));
+    let idempotency_keys: Arc<dyn IdempotencyKeys>;
+    let mut unit_of_work = UnitOfWork::default();
+    let mut scheduler = scheduler_for(database.as_ref());
+    if let Some(db) = database {
+        tokio::spawn(start_health_check(
+            db.clone(),
+            DATABASE_HEALTH.clone(),
+            APP_CONFIG.database_health_check_interval.clone(),
+            schema,
+        ));
+        unit_of_work = UnitOfWork::new(Some(db.clone()));
+        outbox = Arc::new(RdbmsOutbox::new(db.clone()));
+        idempotency_keys = Arc::new(RdbmsIdempotencyKeys::new(db.clone(), idempotency_window));
+        let connection = connect_replica(db).await;
+        let topics = Arc::new(RdbmsRepository::<TopicDbModel, _>::new(connection.clone()));
+        let comments = Arc::new(RdbmsRepository::<CommentDbModel, _>::new(connection));
+        (topic_repository, topic_search) = (topics.clone(), topics);
+        (comment_repository, comment_search) = (comments.clone(), comments);
+    } else if let Some(directory) = &APP_CONFIG.ephemeral_db_directory {
+        println!("Keeping records in {directory}");
+        let directory = Path::new(directory);
+        let topics = Arc::new(FileRepository::<TopicId, Topic>::open(directory, "topics").await?);
+        let comments =
+            Arc::new(FileRepository::<CommentId, Comment>::open(directory, "comments").await?);
+        outbox = Arc::new(JournalOutbox::open(directory).await?);
+        scheduler = with_compaction(scheduler, topics.clone(), comments.clone());
+        idempotency_keys = Arc::new(InMemoryIdempotencyKeys::new(idempotency_window));
+        (topic_repository, topic_search) = (topics.clone(), topics);
+        (comment_repository, comment_search) = (comments.clone(), comments);
+    } else {
+        println!(
+            "Records will be lost on restart,
+            set EPHEMERAL_DB_DIRECTORY to keep them on disk."
+        );
+        let topics = Arc::new(InMemoryRepository::<TopicId, Topic>::new());
+        let comments = Arc::new(InMemoryRepository::<CommentId, Comment>::new());
+        outbox = Arc::new(JournalOutbox::in_memory());
+        idempotency_keys = Arc::new(InMemoryIdempotencyKeys::new(idempotency_window));
+        (topic_repository, topic_search) = (topics.clone(), topics);
+        (comment_repository, comment_search) = (comments.clone(), comments);
     }
 
-    let (tx, rx) = channel(100);
-    tokio::spawn(start_write_worker(
-        rx,
-        topic_repository.clone(),
-        comment_repository.clone(),
-    ));
-    let topic_service = Arc::new(PettyMattersService::new(
+    let topic_repository = Arc::new(CachedRepository::new(
         topic_repository,
+        APP_CONFIG.cache_capacity,
+        &APP_CONFIG.cache_ttl,
+    ));
+    let comment_repository = Arc::new(CachedRepository::new(
         comment_repository,
-        Arc::new(WriteQueue::new(tx)),
+        APP_CONFIG.cache_capacity,
+        &APP_CONFIG.cache_ttl,
     ));
+
+    let write_queue = OutboxQueue::new(outbox).with_limits(
+        APP_CONFIG.write_queue_capacity,
+        Duration::from_secs(u64::from(APP_CONFIG.write_enqueue_deadline.0)),
+    );
+    let worker_queue = write_queue.clone();
+    let events = EventBus::default();
+    let worker_events = events.clone();
+    let (topics, comments): (
+        Arc<dyn Repository<_, _> + Send + Sync>,
+        Arc<dyn Repository<_, _> + Send + Sync>,
+    ) = (topic_repository.clone(), comment_repository.clone());
+    tokio::spawn(supervise("write worker", move || {
+        start_outbox_worker(
+            worker_queue.clone(),
+            topics.clone(),
+            comments.clone(),
+            idempotency_keys.clone(),
+            worker_events.clone(),
+            unit_of_work.clone(),
+        )
+    }));
+    let topic_service = Arc::new(
+        PettyMattersService::new(
+            topic_repository,
+            comment_repository,
+            topic_search,
+            comment_search,
+            Arc::new(write_queue),
+        )
+        .with_database_health(DATABASE_HEALTH.clone())
+        .with_events(events),
+    );
+    start_jobs(scheduler, &topic_service);
     println!("Service configuration done");
 
     Ok(topic_service)
 }
 
+/// Falls back to local repositories when ephemeral databases are allowed and no database was
+/// configured, or to a read-only mode until the database is back otherwise. A configured
+/// database is always waited for, since the local repositories never hand over to it.
+/// In read-only mode the schema is only prepared once the database answers.
+async fn establish_database(
+    db_connection: Result<DatabaseConnection, DbErr>,
+) -> Result<(Option<DatabaseConnection>, SchemaState), AnyError> {
+    let database = match db_connection {
+        Ok(db) => {
+            println!("Connection established");
+            prepare_schema(&db, APP_CONFIG.run_migrations_on_startup).await?;
+            (Some(db), SchemaState::Prepared)
+        }
+        Err(e)
+            if FEATURE_FLAGS.is_ephemeral_db_allowed && !APP_CONFIG.is_database_url_configured =>
+        {
+            eprintln!(
+                "Database connection failed: {e},
+                using local repositories as fallback until the next restart.
+                If you'd like to disallow the fallback behavior,
+                set the EPHEMERAL_DB_ALLOWED environment variable to false."
+            );
+            (None, SchemaState::Prepared)
+        }
+        Err(e) => {
+            eprintln!(
+                "Database connection failed: {e}
+                and the ephemeral DB is either not allowed or stands in only for
+                the default database, starting in read-only mode
+                until the database becomes available.
+                To allow the application to run with an in-memory database,
+                set the EPHEMERAL_DB_ALLOWED environment variable to true
+                and leave DATABASE_URL unset."
+            );
+            DATABASE_HEALTH.record(DatabaseStatus::Unavailable);
+            let schema = SchemaState::Pending {
+                apply_migrations: APP_CONFIG.run_migrations_on_startup,
+            };
+            (
+                Some(connect_lazily(&APP_CONFIG.database_url).await?),
+                schema,
+            )
+        }
+    };
+
+    Ok(database)
+}
+
+/// Reads go to the replica when one is configured and reachable, otherwise to the primary
+async fn connect_replica(primary: DatabaseConnection) -> ReplicatedConnection {
+    let Some(replica_database_url) = &APP_CONFIG.replica_database_url else {
+        return ReplicatedConnection::primary_only(primary);
+    };
+
+    match connect(replica_database_url).await {
+        Ok(replica) => {
+            println!("Reading from the replica");
+            ReplicatedConnection::new(primary, replica)
+        }
+        Err(e) => {
+            eprintln!("Replica connection failed: {e}, reading from the primary instead");
+            ReplicatedConnection::primary_only(primary)
+        }
+    }
+}
+
+/// Job runs are kept with the records, and on Postgres an advisory lock keeps
+/// instances from running a job twice over. Without a database there's only this instance.
+fn scheduler_for(database: Option<&DatabaseConnection>) -> Scheduler {
+    let (runs, locks): (Arc<dyn JobRuns>, Arc<dyn JobLocks>) = match database {
+        Some(db) if db.get_database_backend() == DbBackend::Postgres => (
+            Arc::new(RdbmsJobRuns::new(db.clone())),
+            Arc::new(AdvisoryJobLocks::new(db.clone())),
+        ),
+        Some(db) => (
+            Arc::new(RdbmsJobRuns::new(db.clone())),
+            Arc::new(InProcessJobLocks::default()),
+        ),
+        None => (
+            Arc::new(InMemoryJobRuns::default()),
+            Arc::new(InProcessJobLocks::default()),
+        ),
+    };
+
+    Scheduler::new(runs, locks)
+}
+
+/// The files in the ephemeral directory only get their logs folded in on a schedule
+fn with_compaction(
+    scheduler: Scheduler,
+    topics: Arc<FileRepository<TopicId, Topic>>,
+    comments: Arc<FileRepository<CommentId, Comment>>,
+) -> Scheduler {
+    let every = || Schedule::every(APP_CONFIG.ephemeral_db_compaction_interval.clone());
+    scheduler
+        .with_job(
+            "compact_topics",
+            every(),
+            Arc::new(CompactionJob { repository: topics }),
+        )
+        .with_job(
+            "compact_comments",
+            every(),
+            Arc::new(CompactionJob {
+                repository: comments,
+            }),
+        )
+}
+
+fn start_jobs<Q>(scheduler: Scheduler, service: &Arc<PettyMattersService<Q>>)
+where
+    Q: Queue + Send + Sync + 'static,
+{
+    scheduler
+        .with_job(
+            "purge_deleted",
+            Schedule::every(Hours(1)),
+            Arc::new(PurgeDeletedJob {
+                service: service.clone(),
+                retention: APP_CONFIG.deleted_retention.clone(),
+            }),
+        )
+        .start();
+}
+
+/// Hard-deletes what was soft-deleted longer ago than the retention period
+pub struct PurgeDeletedJob<Q>
+where
+    Q: Queue + Send + Sync,
+{
+    pub service: Arc<PettyMattersService<Q>>,
+    pub retention: Days,
+}
+
+#[async_trait]
+impl<Q> Job for PurgeDeletedJob<Q>
+where
+    Q: Queue + Send + Sync,
+{
+    async fn run(&self) -> Result<(), AnyError> {
+        self.service
+            .purge_deleted(self.retention.clone())
+            .await?
+            .wait(PURGE_WAIT)
+            .await?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::authn::session::Username;
+    use crate::error::AnyError;
     use crate::persistence::in_memory_repository::InMemoryRepository;
     use crate::petty_matters::topic::Topic;
+    use crate::queue::base::IdempotencyKey;
+    use crate::queue::idempotency::with_idempotency_key;
     use crate::queue::stub_queue::StubQueue;
 
     fn setup_service() -> PettyMattersService<StubQueue> {
@@ -173,18 +633,21 @@ mod tests {
         let comment_repository = Arc::new(InMemoryRepository::new());
         let queue = StubQueue::new(topic_repository.clone(), comment_repository.clone());
 
-        PettyMattersService::new(topic_repository, comment_repository, Arc::new(queue))
+        PettyMattersService::new(
+            topic_repository.clone(),
+            comment_repository.clone(),
+            topic_repository,
+            comment_repository,
+            Arc::new(queue),
+        )
     }
 
     #[tokio::test]
-    async fn test_start_topic_should_persist_a_topic() {
+    async fn test_start_topic_should_persist_a_topic() -> Result<(), AnyError> {
         let service = setup_service();
         let topic = Topic::default();
 
-        service
-            .create_topic(topic.clone())
-            .await
-            .expect("Failed to start topic");
+        service.create_topic(topic.clone()).await?;
 
         assert!(
             service
@@ -192,16 +655,15 @@ mod tests {
                 .await
                 .is_ok_and(|result| result.is_some_and(|entity| entity == topic))
         );
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn test_should_add_comment_to_a_topic() {
+    async fn test_should_add_comment_to_a_topic() -> Result<(), AnyError> {
         let service = setup_service();
         let topic = Topic::default();
-        service
-            .create_topic(topic.clone())
-            .await
-            .expect("Failed to start topic");
+        service.create_topic(topic.clone()).await?;
 
         service
             .reply_to_topic(
@@ -209,8 +671,7 @@ mod tests {
                 "This is a comment".to_string(),
                 User::anonymous(),
             )
-            .await
-            .expect("Failed to add comment");
+            .await?;
 
         assert!(
             service
@@ -221,16 +682,40 @@ mod tests {
                     .first()
                     .is_some_and(|c| c.content == "This is a comment"))
         );
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn test_should_refuse_to_add_comments_without_a_body() {
+    async fn test_should_add_a_comment_submitted_twice_only_once() -> Result<(), AnyError> {
         let service = setup_service();
         let topic = Topic::default();
-        service
-            .create_topic(topic.clone())
-            .await
-            .expect("Failed to start topic");
+        service.create_topic(topic.clone()).await?;
+        let key = IdempotencyKey::new();
+
+        for _ in 0..2 {
+            with_idempotency_key(
+                Some(key),
+                service.reply_to_topic(&topic.id, "Me too".to_string(), User::anonymous()),
+            )
+            .await?;
+        }
+
+        assert!(
+            service
+                .list_comments(&topic.id, ListParameters::default())
+                .await
+                .is_ok_and(|topic_comments| topic_comments.items.len() == 1)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_should_refuse_to_add_comments_without_a_body() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::default();
+        service.create_topic(topic.clone()).await?;
 
         let result = service
             .reply_to_topic(&topic.id, String::new(), User::anonymous())
@@ -241,23 +726,19 @@ mod tests {
             service
                 .list_comments(&topic.id, ListParameters::default())
                 .await
-                .is_ok_and(|topic_comments| topic_comments.items.len() == 0)
+                .is_ok_and(|topic_comments| topic_comments.items.is_empty())
         );
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn test_should_only_return_comments_relevant_for_the_topic() {
+    async fn test_should_only_return_comments_relevant_for_the_topic() -> Result<(), AnyError> {
         let service = setup_service();
         let unrelated_topic = Topic::default();
-        service
-            .create_topic(unrelated_topic.clone())
-            .await
-            .expect("Failed to start topic");
+        service.create_topic(unrelated_topic.clone()).await?;
         let topic = Topic::default();
-        service
-            .create_topic(topic.clone())
-            .await
-            .expect("Failed to start topic");
+        service.create_topic(topic.clone()).await?;
 
         service
             .reply_to_topic(
@@ -265,8 +746,7 @@ mod tests {
                 "This is a comment".to_string(),
                 User::anonymous(),
             )
-            .await
-            .expect("Failed to add comment");
+            .await?;
 
         assert!(
             service
@@ -274,5 +754,349 @@ mod tests {
                 .await
                 .is_ok_and(|result| result.items.is_empty())
         );
+
+        Ok(())
+    }
+
+    fn author() -> User {
+        User::new(Username("author@localhost".to_string()), 0)
+    }
+
+    #[tokio::test]
+    async fn test_author_should_be_able_to_edit_their_topic() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+
+        service
+            .edit_topic(
+                topic.clone(),
+                "Fixed title".to_string(),
+                "Fixed content".to_string(),
+                topic.version,
+                &author(),
+            )
+            .await?;
+
+        let edited = service
+            .get_topic(&topic.id)
+            .await?
+            .ok_or("Topic should exist")?;
+        assert_eq!(edited.title, "Fixed title");
+        assert_eq!(edited.content, "Fixed content");
+        assert!(edited.last_updated_time.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_should_refuse_edits_from_someone_other_than_the_author() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+
+        let result = service
+            .edit_topic(
+                topic.clone(),
+                "Hijacked".to_string(),
+                "Hijacked".to_string(),
+                topic.version,
+                &User::anonymous(),
+            )
+            .await;
+
+        assert!(matches!(result, Err(QueueError::PermissionDenied(_))));
+        assert!(
+            service
+                .get_topic(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_some_and(|entity| entity.title == "Title"))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_author_should_be_able_to_edit_their_comment() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::default();
+        service.create_topic(topic.clone()).await?;
+        service
+            .reply_to_topic(&topic.id, "Typo".to_string(), author())
+            .await?;
+        let comment = service
+            .list_comments(&topic.id, ListParameters::default())
+            .await?
+            .items
+            .first()
+            .cloned()
+            .ok_or("Comment should exist")?;
+
+        service
+            .edit_comment(
+                comment.clone(),
+                "Fixed".to_string(),
+                comment.version,
+                &author(),
+            )
+            .await?;
+
+        assert!(service.get_comment(&comment.id).await.is_ok_and(|result| {
+            result.is_some_and(|c| c.content == "Fixed" && c.last_updated_time.is_some())
+        }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_should_refuse_edits_based_on_a_stale_version() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+        service
+            .edit_topic(
+                topic.clone(),
+                "First".to_string(),
+                "First".to_string(),
+                topic.version,
+                &author(),
+            )
+            .await?;
+
+        let result = service
+            .edit_topic(
+                topic.clone(),
+                "Second".to_string(),
+                "Second".to_string(),
+                topic.version,
+                &author(),
+            )
+            .await;
+
+        assert!(matches!(result, Err(QueueError::Conflict(_))));
+        assert!(
+            service
+                .get_topic(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_some_and(|entity| entity.title == "First"))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_withdrawn_topic_is_hidden_until_restored() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+
+        service
+            .delete_topic(topic.clone(), topic.version, &author())
+            .await?;
+        assert!(
+            service
+                .get_topic(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_none())
+        );
+
+        let withdrawn = service
+            .get_topic_including_deleted(&topic.id)
+            .await?
+            .ok_or("Withdrawn topic should still exist")?;
+        assert_eq!(withdrawn.deleted_by, Some(author().email));
+        service
+            .restore_topic(withdrawn.clone(), withdrawn.version, &author())
+            .await?;
+
+        assert!(
+            service
+                .get_topic(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_some_and(|entity| !entity.is_deleted()))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_purge_removes_withdrawn_topics_past_retention() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+        service
+            .delete_topic(topic.clone(), topic.version, &author())
+            .await?;
+
+        service.purge_deleted(Days(0)).await?;
+
+        assert!(
+            service
+                .get_topic_including_deleted(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_none())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_withdrawing_a_topic_withdraws_and_restores_its_comments() -> Result<(), AnyError>
+    {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+        service
+            .reply_to_topic(&topic.id, "A reply".to_string(), User::anonymous())
+            .await?;
+
+        service
+            .delete_topic(topic.clone(), topic.version, &author())
+            .await?;
+        let while_withdrawn = service
+            .list_comments(&topic.id, ListParameters::default())
+            .await?;
+        let withdrawn = service
+            .get_topic_including_deleted(&topic.id)
+            .await?
+            .ok_or("Withdrawn topic should still exist")?;
+        service
+            .restore_topic(withdrawn.clone(), withdrawn.version, &author())
+            .await?;
+
+        assert!(while_withdrawn.items.is_empty());
+        assert!(
+            service
+                .list_comments(&topic.id, ListParameters::default())
+                .await
+                .is_ok_and(|result| result.items.len() == 1)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_comments_withdrawn_on_their_own_stay_withdrawn_when_the_topic_is_restored()
+    -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+        service
+            .reply_to_topic(&topic.id, "Second thoughts".to_string(), author())
+            .await?;
+        service
+            .reply_to_topic(&topic.id, "A reply".to_string(), User::anonymous())
+            .await?;
+        let mut filed = service
+            .list_comments(&topic.id, ListParameters::default())
+            .await?
+            .items;
+        filed.retain(|comment| comment.is_authored_by(&author()));
+        let own = filed.pop().ok_or("The author's comment should be filed")?;
+        service.delete_comment(own, 0, &author()).await?;
+
+        service
+            .delete_topic(topic.clone(), topic.version, &author())
+            .await?;
+        let withdrawn = service
+            .get_topic_including_deleted(&topic.id)
+            .await?
+            .ok_or("Withdrawn topic should still exist")?;
+        service
+            .restore_topic(withdrawn.clone(), withdrawn.version, &author())
+            .await?;
+
+        assert!(
+            service
+                .list_comments(&topic.id, ListParameters::default())
+                .await
+                .is_ok_and(|result| result
+                    .items
+                    .iter()
+                    .map(|c| c.content.as_str())
+                    .eq(["A reply"]))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_comments_are_counted_without_getting_in_the_authors_way() -> Result<(), AnyError>
+    {
+        let service = setup_service();
+        let topic = Topic::new("Title".to_string(), "Content".to_string(), author());
+        service.create_topic(topic.clone()).await?;
+
+        service
+            .reply_to_topic(&topic.id, "A reply".to_string(), User::anonymous())
+            .await?;
+        service
+            .edit_topic(
+                topic.clone(),
+                "Fixed title".to_string(),
+                "Content".to_string(),
+                topic.version,
+                &author(),
+            )
+            .await?;
+
+        let edited = service
+            .get_topic(&topic.id)
+            .await?
+            .ok_or("Topic should exist")?;
+        assert_eq!(edited.title, "Fixed title");
+        assert_eq!(edited.comment_count, 1);
+        assert!(edited.last_activity_time > topic.last_activity_time);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_refused_while_the_database_is_unavailable() {
+        let database_health = Arc::new(DatabaseHealth::default());
+        let service = setup_service().with_database_health(database_health.clone());
+        let topic = Topic::default();
+
+        database_health.record(DatabaseStatus::Unavailable);
+        let result = service.create_topic(topic.clone()).await;
+
+        assert!(matches!(result, Err(QueueError::Unavailable(_))));
+        assert!(
+            service
+                .get_topic(&topic.id)
+                .await
+                .is_ok_and(|result| result.is_none())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_finds_comments_along_with_their_topic() -> Result<(), AnyError> {
+        let service = setup_service();
+        let topic = Topic {
+            title: "The hedge".to_string(),
+            ..Topic::default()
+        };
+        service.create_topic(topic.clone()).await?;
+        service
+            .reply_to_topic(
+                &topic.id,
+                "My hedge is taller".to_string(),
+                User::anonymous(),
+            )
+            .await?;
+
+        let results = service.search("taller").await?;
+
+        assert_eq!(results.topics.len(), 0);
+        assert_eq!(
+            results
+                .comments
+                .iter()
+                .map(|(_, commented_on)| commented_on.id)
+                .collect::<Vec<_>>(),
+            [topic.id]
+        );
+
+        Ok(())
     }
 }
@@ -1,12 +1,14 @@
 use crate::authn::session::{User, Username};
-use crate::persistence::in_memory_repository::FilterableAttributes;
-use crate::persistence::repository::HasId;
+use crate::persistence::cursor::FieldValue;
+use crate::persistence::filter::{FieldType, FilterableAttributes};
+use crate::persistence::repository::{HasId, SoftDeletable, Versioned};
+use crate::persistence::search::Searchable;
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 use uuid::Uuid;
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub struct TopicId(pub Uuid);
 
 impl From<&String> for TopicId {
@@ -22,46 +24,92 @@ impl Display for TopicId {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Topic {
     pub id: TopicId,
     pub title: String,
     pub content: String,
     pub upvotes_count: u32,
     pub downvotes_count: u32,
+    /// Every comment made on it, withdrawn ones included as they still take up a spot
+    #[serde(default)]
+    pub comment_count: u32,
     pub created_by: Username,
     pub creation_time: DateTime<Utc>,
     pub last_updated_time: Option<DateTime<Utc>>,
+    /// When it was filed or last commented on, whichever is later
+    #[serde(default)]
+    pub last_activity_time: DateTime<Utc>,
+    pub version: u32,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub deleted_by: Option<Username>,
 }
 
 impl Default for Topic {
     fn default() -> Self {
+        let now = Utc::now();
         Self {
             id: TopicId(Uuid::new_v4()),
             title: String::new(),
             content: String::new(),
             upvotes_count: 0,
             downvotes_count: 0,
+            comment_count: 0,
             created_by: Username::default(),
-            creation_time: Utc::now(),
+            creation_time: now,
             last_updated_time: None,
+            last_activity_time: now,
+            version: 0,
+            deleted_at: None,
+            deleted_by: None,
         }
     }
 }
 
 impl Topic {
     pub(crate) fn new(title: String, content: String, author: User) -> Self {
+        let now = Utc::now();
         Self {
             id: TopicId(Uuid::new_v4()),
             title,
             content,
             upvotes_count: 0,
             downvotes_count: 0,
+            comment_count: 0,
             created_by: author.email,
-            creation_time: Utc::now(),
+            creation_time: now,
             last_updated_time: None,
+            last_activity_time: now,
+            version: 0,
+            deleted_at: None,
+            deleted_by: None,
         }
     }
+
+    pub fn is_authored_by(&self, user: &User) -> bool {
+        !user.is_anonymous && self.created_by == user.email
+    }
+
+    pub(crate) fn edit(&mut self, title: String, content: String) {
+        self.title = title;
+        self.content = content;
+        self.last_updated_time = Some(Utc::now());
+    }
+
+    pub(crate) fn record_comment(&mut self, commented_at: DateTime<Utc>) {
+        self.comment_count += 1;
+        self.last_activity_time = self.last_activity_time.max(commented_at);
+    }
+
+    pub(crate) fn mark_deleted(&mut self, deleted_by: &User) {
+        self.deleted_at = Some(Utc::now());
+        self.deleted_by = Some(deleted_by.email.clone());
+    }
+
+    pub(crate) fn restore(&mut self) {
+        self.deleted_at = None;
+        self.deleted_by = None;
+    }
 }
 
 impl HasId<TopicId> for Topic {
@@ -70,17 +118,61 @@ impl HasId<TopicId> for Topic {
     }
 }
 
+impl Versioned for Topic {
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn increment_version(&mut self) {
+        self.version += 1;
+    }
+}
+
+impl SoftDeletable for Topic {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        self.deleted_at
+    }
+}
+
 impl FilterableAttributes for Topic {
-    type Output = Option<String>;
+    const FIELDS: &'static [(&'static str, FieldType)] = &[
+        ("id", FieldType::Uuid),
+        ("title", FieldType::Text),
+        ("content", FieldType::Text),
+        ("upvotes_count", FieldType::Integer),
+        ("downvotes_count", FieldType::Integer),
+        ("comment_count", FieldType::Integer),
+        ("created_by", FieldType::Text),
+        ("creation_time", FieldType::Timestamp),
+        ("last_updated_time", FieldType::Timestamp),
+        ("last_activity_time", FieldType::Timestamp),
+        ("deleted_at", FieldType::Timestamp),
+        ("deleted_by", FieldType::Text),
+    ];
 
-    fn get_field_value(&self, field: &str) -> Self::Output {
+    fn get_field_value(&self, field: &str) -> FieldValue {
         match field {
-            "id" => Some(self.id.to_string()),
-            _ => None,
+            "id" => self.id.0.into(),
+            "title" => self.title.clone().into(),
+            "content" => self.content.clone().into(),
+            "upvotes_count" => self.upvotes_count.into(),
+            "downvotes_count" => self.downvotes_count.into(),
+            "comment_count" => self.comment_count.into(),
+            "created_by" => self.created_by.0.clone().into(),
+            "creation_time" => self.creation_time.into(),
+            "last_updated_time" => self.last_updated_time.into(),
+            "last_activity_time" => self.last_activity_time.into(),
+            "deleted_at" => self.deleted_at.into(),
+            "deleted_by" => self.deleted_by.as_ref().map(|u| u.0.clone()).into(),
+            _ => FieldValue::Null,
         }
     }
 }
 
+impl Searchable for Topic {
+    const SEARCHED_FIELDS: &'static [&'static str] = &["title", "content"];
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,4 +192,34 @@ mod tests {
         assert_eq!(topic.upvotes_count, 0);
         assert_eq!(topic.downvotes_count, 0);
     }
+
+    #[test]
+    fn test_edit_stamps_last_updated_time() {
+        let mut topic = Topic::default();
+
+        topic.edit("New title".to_string(), "New content".to_string());
+
+        assert_eq!(topic.title, "New title");
+        assert_eq!(topic.content, "New content");
+        assert!(topic.last_updated_time.is_some());
+    }
+
+    #[test]
+    fn test_comments_count_towards_activity() {
+        let mut topic = Topic::default();
+        let commented_at = topic.creation_time + chrono::Duration::minutes(5);
+
+        topic.record_comment(commented_at);
+        topic.record_comment(topic.creation_time);
+
+        assert_eq!(topic.comment_count, 2);
+        assert_eq!(topic.last_activity_time, commented_at);
+    }
+
+    #[test]
+    fn test_anonymous_users_are_never_authors() {
+        let topic = Topic::default();
+
+        assert!(!topic.is_authored_by(&User::anonymous()));
+    }
 }
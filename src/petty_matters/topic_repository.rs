@@ -4,8 +4,9 @@ use crate::persistence::repository::{HasId, ListParameters};
 use crate::petty_matters::topic::{Topic, TopicId};
 use chrono::Utc;
 use sea_orm::entity::prelude::*;
-use sea_orm::{Condition, DeriveEntityModel, Order, Set};
+use sea_orm::{DeriveEntityModel, Order, Set};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "topics")]
@@ -16,9 +17,14 @@ pub struct Model {
     pub content: String,
     pub upvotes_count: i32,
     pub downvotes_count: i32,
+    pub comment_count: i32,
     pub created_by: String,
     pub creation_time: chrono::DateTime<Utc>,
     pub last_updated_time: Option<chrono::DateTime<Utc>>,
+    pub last_activity_time: chrono::DateTime<Utc>,
+    pub version: i32,
+    pub deleted_at: Option<chrono::DateTime<Utc>>,
+    pub deleted_by: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -33,41 +39,13 @@ impl HasId<Uuid> for Model {
 }
 
 impl ModelDatabaseInterface<Self, Topic, TopicId> for Entity {
-    fn filter_from_params(list_parameters: &ListParameters) -> Condition {
-        let mut condition = Condition::all();
-        if let Some(filters) = &list_parameters.filters {
-            for (key, val) in filters {
-                match key.as_str() {
-                    "title" => condition = condition.add(Column::Title.like(val)),
-                    "content" => condition = condition.add(Column::Content.eq(val)),
-                    "created_by" => condition = condition.add(Column::CreatedBy.eq(val)),
-                    _ => {}
-                }
-            }
-        }
-
-        condition
-    }
-
     #[allow(clippy::match_same_arms)]
     fn order_by_from_params(
         list_parameters: &ListParameters,
     ) -> (<Self as EntityTrait>::Column, Order) {
-        list_parameters
-            .order_by
-            .as_ref()
-            .map_or((Column::CreationTime, Order::Desc), |order_by| {
-                let column = match order_by.as_str() {
-                    "created_by" => Column::CreatedBy,
-                    "creation_time" => Column::CreationTime,
-                    "last_updated_time" => Column::LastUpdatedTime,
-                    _ => Column::CreationTime,
-                };
-                (
-                    column,
-                    list_parameters.ordering.clone().unwrap_or_default().into(),
-                )
-            })
+        let column =
+            Column::from_str(list_parameters.sort_field::<Topic>()).unwrap_or(Column::CreationTime);
+        (column, list_parameters.sort_ordering::<Topic>().into())
     }
 
     #[allow(clippy::cast_sign_loss)]
@@ -78,9 +56,14 @@ impl ModelDatabaseInterface<Self, Topic, TopicId> for Entity {
             content: record.content,
             upvotes_count: record.upvotes_count as u32,
             downvotes_count: record.downvotes_count as u32,
+            comment_count: record.comment_count as u32,
             created_by: Username(record.created_by),
             creation_time: record.creation_time,
             last_updated_time: record.last_updated_time,
+            last_activity_time: record.last_activity_time,
+            version: record.version as u32,
+            deleted_at: record.deleted_at,
+            deleted_by: record.deleted_by.map(Username),
         }
     }
 
@@ -92,9 +75,14 @@ impl ModelDatabaseInterface<Self, Topic, TopicId> for Entity {
             content: Set(model.content),
             upvotes_count: Set(model.upvotes_count as i32),
             downvotes_count: Set(model.downvotes_count as i32),
+            comment_count: Set(model.comment_count as i32),
             created_by: Set(model.created_by.0),
             creation_time: Set(model.creation_time),
             last_updated_time: Set(model.last_updated_time),
+            last_activity_time: Set(model.last_activity_time),
+            version: Set(model.version as i32),
+            deleted_at: Set(model.deleted_at),
+            deleted_by: Set(model.deleted_by.map(|username| username.0)),
         }
     }
 
@@ -103,4 +91,16 @@ impl ModelDatabaseInterface<Self, Topic, TopicId> for Entity {
     ) -> <<Self as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType {
         id.0
     }
+
+    fn id_column() -> <Self as EntityTrait>::Column {
+        Column::Id
+    }
+
+    fn version_column() -> <Self as EntityTrait>::Column {
+        Column::Version
+    }
+
+    fn deleted_at_column() -> <Self as EntityTrait>::Column {
+        Column::DeletedAt
+    }
 }
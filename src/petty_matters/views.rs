@@ -1,14 +1,19 @@
 use crate::authn::session::User;
-use crate::persistence::repository::{ListParameters, Page, PageNumber, PageSize};
-use crate::petty_matters::comment::Comment;
-use crate::petty_matters::service::PettyMattersService;
+use crate::config::APP_CONFIG;
+use crate::persistence::filter::FilterableAttributes;
+use crate::persistence::repository::{ListParameters, Page, PageNumber, PageSize, SoftDeletable};
+use crate::petty_matters::comment::{Comment, CommentId};
+use crate::petty_matters::service::{PettyMattersService, SearchResults};
 use crate::petty_matters::topic::{Topic, TopicId};
-use crate::queue::base::Queue;
+use crate::queue::base::{Completion, IdempotencyKey, Queue, QueueError};
+use crate::queue::idempotency::with_idempotency_key;
 use crate::render_template;
 use crate::templates::{Nonce, filters};
-use crate::time::Seconds;
 use crate::views::pagination::PageFilters;
-use crate::views::templates::{HtmlResponse, show_error_page, show_not_found_page};
+use crate::views::templates::{
+    HtmlResponse, show_bad_request_page, show_forbidden_page, show_not_found_page,
+    show_queue_error_page, show_repository_error_page,
+};
 use askama::Template;
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
@@ -16,8 +21,8 @@ use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
 use axum::{Form, Router};
 use serde::Deserialize;
-use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Template)]
 #[template(path = "petty_matters/list.html")]
@@ -25,6 +30,28 @@ pub struct PettyMattersList {
     user: User,
     nonce: Nonce,
     pub topics: Page<Topic>,
+    /// The sorting and filters, carried over to the pagination links
+    /// so the next page lists the same petty matters in the same order
+    pub carried_over: String,
+}
+
+/// The ways of sorting the listing offered above it, along with their query parameters
+static SORT_OPTIONS: [(&str, &str); 3] = [
+    ("Newest", ""),
+    ("Most active", "order_by=comment_count&ordering=descending"),
+    (
+        "Recently active",
+        "order_by=last_activity_time&ordering=descending",
+    ),
+];
+
+#[derive(Template)]
+#[template(path = "petty_matters/search.html")]
+pub struct PettyMattersSearch {
+    nonce: Nonce,
+    pub query: String,
+    /// Left out until something is searched for
+    pub results: Option<SearchResults>,
 }
 
 #[derive(Template)]
@@ -32,25 +59,73 @@ pub struct PettyMattersList {
 pub struct PettyMattersRegistration {
     user: User,
     nonce: Nonce,
+    idempotency_key: IdempotencyKey,
 }
 
 #[derive(Template)]
 #[template(path = "petty_matters/view.html")]
+/// Every form on the page is rendered with a key of its own, so submitting one twice has no
+/// further effect while the same submission through another form still goes through
 pub struct PettyMatter {
+    user: User,
     nonce: Nonce,
     pub topic: Topic,
     pub comments: Vec<Comment>,
 }
 
+#[derive(Template)]
+#[template(path = "petty_matters/edit.html")]
+pub struct PettyMatterAmendment {
+    nonce: Nonce,
+    idempotency_key: IdempotencyKey,
+    pub topic: Topic,
+}
+
+#[derive(Template)]
+#[template(path = "petty_matters/edit_comment.html")]
+pub struct CommentAmendment {
+    nonce: Nonce,
+    idempotency_key: IdempotencyKey,
+    pub comment: Comment,
+}
+
 #[derive(Deserialize)]
 struct PettyMattersRegistrationForm {
     subject: String,
     content: String,
+    idempotency_key: Option<IdempotencyKey>,
 }
 
 #[derive(Deserialize)]
 struct CommentForm {
     content: String,
+    idempotency_key: Option<IdempotencyKey>,
+}
+
+#[derive(Deserialize)]
+struct PettyMatterAmendmentForm {
+    subject: String,
+    content: String,
+    version: u32,
+    idempotency_key: Option<IdempotencyKey>,
+}
+
+#[derive(Deserialize)]
+struct CommentAmendmentForm {
+    content: String,
+    version: u32,
+    idempotency_key: Option<IdempotencyKey>,
+}
+
+#[derive(Deserialize)]
+struct SearchParameters {
+    q: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WithdrawalForm {
+    version: u32,
+    idempotency_key: Option<IdempotencyKey>,
 }
 
 async fn list_petty_matters<Q>(
@@ -62,21 +137,101 @@ async fn list_petty_matters<Q>(
 where
     Q: Queue + Send + Sync,
 {
-    let list_parameters = ListParameters::from_query_params(&page_filters);
+    let list_parameters = match ListParameters::from_query_params::<Topic>(&page_filters) {
+        Ok(list_parameters) => list_parameters,
+        Err(e) => return show_bad_request_page(&e),
+    };
+    let carried_over = carried_over_query(&page_filters);
     let topics = match service.list_topics(list_parameters).await {
         Ok(topics) => topics,
-        Err(e) => return show_error_page(e),
+        Err(e) => return show_repository_error_page(e),
     };
     let template = render_template!(PettyMattersList {
         user,
         nonce,
-        topics
+        topics,
+        carried_over
     });
     Ok(HtmlResponse::from_string(template))
 }
 
+/// Everything but the position in the listing, as query parameters to append to a link
+fn carried_over_query(page_filters: &PageFilters) -> String {
+    let sorting = page_filters
+        .order_by
+        .as_ref()
+        .filter(|order_by| Topic::FIELDS.iter().any(|(field, _)| field == order_by))
+        .map(|order_by| {
+            vec![
+                ("order_by".to_string(), order_by.clone()),
+                (
+                    "ordering".to_string(),
+                    page_filters
+                        .ordering
+                        .clone()
+                        .unwrap_or_default()
+                        .to_string(),
+                ),
+            ]
+        })
+        .unwrap_or_default();
+    let parameters = sorting
+        .into_iter()
+        .chain(page_filters.filters.clone())
+        .collect::<Vec<_>>();
+
+    match serde_urlencoded::to_string(parameters) {
+        Ok(query) if !query.is_empty() => format!("&{query}"),
+        _ => String::new(),
+    }
+}
+
+async fn search_petty_matters<Q>(
+    nonce: Nonce,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+    Query(parameters): Query<SearchParameters>,
+) -> Result<HtmlResponse, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let query = parameters.q.unwrap_or_default();
+    let results = if query.trim().is_empty() {
+        None
+    } else {
+        match service.search(&query).await {
+            Ok(results) => Some(results),
+            Err(e) => return show_repository_error_page(e),
+        }
+    };
+    let template = render_template!(PettyMattersSearch {
+        nonce,
+        query,
+        results
+    });
+    Ok(HtmlResponse::from_string(template))
+}
+
+/// Gives the write a moment to go through, so the page redirected to already shows it,
+/// and a write that can't go through is reported to its author rather than only logged
+async fn redirect_once_written(written: Result<Completion, QueueError>, to: &str) -> Response {
+    let confirmation_timeout =
+        Duration::from_secs(u64::from(APP_CONFIG.write_confirmation_timeout.0));
+    let outcome = match written {
+        Ok(completion) => completion.wait(confirmation_timeout).await,
+        Err(e) => Err(e),
+    };
+    match outcome {
+        Ok(_) => Redirect::to(to).into_response(),
+        Err(e) => show_queue_error_page(e).into_response(),
+    }
+}
+
 async fn render_registration_form(nonce: Nonce, user: User) -> Result<HtmlResponse, StatusCode> {
-    let template = render_template!(PettyMattersRegistration { user, nonce });
+    let template = render_template!(PettyMattersRegistration {
+        user,
+        nonce,
+        idempotency_key: IdempotencyKey::new()
+    });
     Ok(HtmlResponse::from_string(template))
 }
 
@@ -89,14 +244,12 @@ where
     Q: Queue + Send + Sync,
 {
     let topic = Topic::new(form.subject.clone(), form.content.clone(), user);
-    match service.create_topic(topic).await {
-        Ok(t) => t,
-        Err(e) => return Ok(show_error_page(e).into_response()),
-    }
-    Ok(Redirect::to("/petty-matters").into_response())
+    let written = with_idempotency_key(form.idempotency_key, service.create_topic(topic)).await;
+    Ok(redirect_once_written(written, "/petty-matters").await)
 }
 
 async fn view_petty_matter<Q>(
+    user: User,
     nonce: Nonce,
     Path(topic_id): Path<TopicId>,
     State(service): State<Arc<PettyMattersService<Q>>>,
@@ -104,32 +257,33 @@ async fn view_petty_matter<Q>(
 where
     Q: Queue + Send + Sync,
 {
-    let topic = match service.get_topic(&topic_id).await {
-        Ok(Some(t)) => t,
-        Ok(None) => return show_not_found_page(),
-        Err(e) => return show_error_page(e),
+    // Withdrawn petty matters stay visible to their author, who may still restore them
+    let topic = match service.get_topic_including_deleted(&topic_id).await {
+        Ok(Some(t)) if !t.is_deleted() || t.is_authored_by(&user) => t,
+        Ok(_) => return show_not_found_page(),
+        Err(e) => return show_repository_error_page(e),
     };
     let comment_filters = ListParameters {
         page_size: PageSize(1000),
         page_number: PageNumber(1),
         order_by: None,
         ordering: None,
-        filters: Some(BTreeMap::from([(
-            "topic_id".to_string(),
-            topic_id.to_string(),
-        )])),
+        filters: None,
+        cursor: None,
+        include_deleted: true,
     };
     let comments = match service.list_comments(&topic_id, comment_filters).await {
         Ok(c) => c,
-        Err(e) => return show_error_page(e),
+        Err(e) => return show_repository_error_page(e),
     };
     let template = render_template!(PettyMatter {
+        user,
         nonce,
         topic,
         comments: comments.items,
     });
 
-    Ok(HtmlResponse::cached(template, Seconds(60)))
+    Ok(HtmlResponse::from_string(template))
 }
 
 async fn add_comment<Q>(
@@ -137,15 +291,204 @@ async fn add_comment<Q>(
     Path(topic_id): Path<TopicId>,
     State(service): State<Arc<PettyMattersService<Q>>>,
     form: Form<CommentForm>,
-) -> Result<impl IntoResponse, StatusCode>
+) -> Result<Response, StatusCode>
 where
     Q: Queue + Send + Sync,
 {
-    service
-        .reply_to_topic(&topic_id, form.content.clone(), user)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Redirect::to(&format!("/petty-matters/{topic_id}")))
+    let written = with_idempotency_key(
+        form.idempotency_key,
+        service.reply_to_topic(&topic_id, form.content.clone(), user),
+    )
+    .await;
+    Ok(redirect_once_written(written, &format!("/petty-matters/{topic_id}")).await)
+}
+
+async fn render_amendment_form<Q>(
+    user: User,
+    nonce: Nonce,
+    Path(topic_id): Path<TopicId>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+) -> Result<HtmlResponse, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let topic = match service.get_topic(&topic_id).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return show_not_found_page(),
+        Err(e) => return show_repository_error_page(e),
+    };
+    if !topic.is_authored_by(&user) {
+        return show_forbidden_page();
+    }
+    let template = render_template!(PettyMatterAmendment {
+        nonce,
+        idempotency_key: IdempotencyKey::new(),
+        topic
+    });
+    Ok(HtmlResponse::from_string(template))
+}
+
+async fn amend_petty_matter<Q>(
+    user: User,
+    Path(topic_id): Path<TopicId>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+    form: Form<PettyMatterAmendmentForm>,
+) -> Result<Response, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let topic = match service.get_topic(&topic_id).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return Ok(show_not_found_page().into_response()),
+        Err(e) => return Ok(show_repository_error_page(e).into_response()),
+    };
+    let written = with_idempotency_key(
+        form.idempotency_key,
+        service.edit_topic(
+            topic,
+            form.subject.clone(),
+            form.content.clone(),
+            form.version,
+            &user,
+        ),
+    )
+    .await;
+    Ok(redirect_once_written(written, &format!("/petty-matters/{topic_id}")).await)
+}
+
+async fn render_comment_amendment_form<Q>(
+    user: User,
+    nonce: Nonce,
+    Path((topic_id, comment_id)): Path<(TopicId, CommentId)>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+) -> Result<HtmlResponse, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let comment = match service.get_comment(&comment_id).await {
+        Ok(Some(c)) if c.topic_id == topic_id => c,
+        Ok(_) => return show_not_found_page(),
+        Err(e) => return show_repository_error_page(e),
+    };
+    if !comment.is_authored_by(&user) {
+        return show_forbidden_page();
+    }
+    let template = render_template!(CommentAmendment {
+        nonce,
+        idempotency_key: IdempotencyKey::new(),
+        comment
+    });
+    Ok(HtmlResponse::from_string(template))
+}
+
+async fn amend_comment<Q>(
+    user: User,
+    Path((topic_id, comment_id)): Path<(TopicId, CommentId)>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+    form: Form<CommentAmendmentForm>,
+) -> Result<Response, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let comment = match service.get_comment(&comment_id).await {
+        Ok(Some(c)) if c.topic_id == topic_id => c,
+        Ok(_) => return Ok(show_not_found_page().into_response()),
+        Err(e) => return Ok(show_repository_error_page(e).into_response()),
+    };
+    let written = with_idempotency_key(
+        form.idempotency_key,
+        service.edit_comment(comment, form.content.clone(), form.version, &user),
+    )
+    .await;
+    Ok(redirect_once_written(written, &format!("/petty-matters/{topic_id}")).await)
+}
+
+async fn withdraw_petty_matter<Q>(
+    user: User,
+    Path(topic_id): Path<TopicId>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+    form: Form<WithdrawalForm>,
+) -> Result<Response, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let topic = match service.get_topic(&topic_id).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return Ok(show_not_found_page().into_response()),
+        Err(e) => return Ok(show_repository_error_page(e).into_response()),
+    };
+    let written = with_idempotency_key(
+        form.idempotency_key,
+        service.delete_topic(topic, form.version, &user),
+    )
+    .await;
+    Ok(redirect_once_written(written, &format!("/petty-matters/{topic_id}")).await)
+}
+
+async fn restore_petty_matter<Q>(
+    user: User,
+    Path(topic_id): Path<TopicId>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+    form: Form<WithdrawalForm>,
+) -> Result<Response, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let topic = match service.get_topic_including_deleted(&topic_id).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return Ok(show_not_found_page().into_response()),
+        Err(e) => return Ok(show_repository_error_page(e).into_response()),
+    };
+    let written = with_idempotency_key(
+        form.idempotency_key,
+        service.restore_topic(topic, form.version, &user),
+    )
+    .await;
+    Ok(redirect_once_written(written, &format!("/petty-matters/{topic_id}")).await)
+}
+
+async fn withdraw_comment<Q>(
+    user: User,
+    Path((topic_id, comment_id)): Path<(TopicId, CommentId)>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+    form: Form<WithdrawalForm>,
+) -> Result<Response, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let comment = match service.get_comment(&comment_id).await {
+        Ok(Some(c)) if c.topic_id == topic_id => c,
+        Ok(_) => return Ok(show_not_found_page().into_response()),
+        Err(e) => return Ok(show_repository_error_page(e).into_response()),
+    };
+    let written = with_idempotency_key(
+        form.idempotency_key,
+        service.delete_comment(comment, form.version, &user),
+    )
+    .await;
+    Ok(redirect_once_written(written, &format!("/petty-matters/{topic_id}")).await)
+}
+
+async fn restore_comment<Q>(
+    user: User,
+    Path((topic_id, comment_id)): Path<(TopicId, CommentId)>,
+    State(service): State<Arc<PettyMattersService<Q>>>,
+    form: Form<WithdrawalForm>,
+) -> Result<Response, StatusCode>
+where
+    Q: Queue + Send + Sync,
+{
+    let comment = match service.get_comment_including_deleted(&comment_id).await {
+        Ok(Some(c)) if c.topic_id == topic_id => c,
+        Ok(_) => return Ok(show_not_found_page().into_response()),
+        Err(e) => return Ok(show_repository_error_page(e).into_response()),
+    };
+    let written = with_idempotency_key(
+        form.idempotency_key,
+        service.restore_comment(comment, form.version, &user),
+    )
+    .await;
+    Ok(redirect_once_written(written, &format!("/petty-matters/{topic_id}")).await)
 }
 
 pub fn petty_matters_router<Q>(service: Arc<PettyMattersService<Q>>) -> Router
@@ -155,7 +498,79 @@ where
     Router::new()
         .route("/", get(list_petty_matters).post(register_petty_matter))
         .route("/register", get(render_registration_form))
+        .route("/search", get(search_petty_matters))
         .route("/{topic_id}", get(view_petty_matter))
+        .route(
+            "/{topic_id}/edit",
+            get(render_amendment_form).post(amend_petty_matter),
+        )
+        .route("/{topic_id}/delete", post(withdraw_petty_matter))
+        .route("/{topic_id}/restore", post(restore_petty_matter))
         .route("/{topic_id}/comments", post(add_comment))
+        .route(
+            "/{topic_id}/comments/{comment_id}/edit",
+            get(render_comment_amendment_form).post(amend_comment),
+        )
+        .route(
+            "/{topic_id}/comments/{comment_id}/delete",
+            post(withdraw_comment),
+        )
+        .route(
+            "/{topic_id}/comments/{comment_id}/restore",
+            post(restore_comment),
+        )
         .with_state(service)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::cursor::Cursor;
+    use crate::persistence::filter::FilterError;
+    use crate::views::pagination::Ordering;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn pagination_links_keep_the_sorting_and_filters() {
+        let page_filters = PageFilters {
+            page: None,
+            page_size: None,
+            order_by: Some("comment_count".to_string()),
+            ordering: Some(Ordering::Descending),
+            cursor: Some("abc".to_string()),
+            filters: BTreeMap::from([("title__like".to_string(), "%cat & dog%".to_string())]),
+        };
+
+        let carried_over = carried_over_query(&page_filters);
+
+        assert_eq!(
+            carried_over,
+            "&order_by=comment_count&ordering=descending&title__like=%25cat+%26+dog%25"
+        );
+    }
+
+    #[test]
+    fn listings_by_unknown_fields_or_from_garbled_cursors_are_bad_requests() {
+        let listing = |order_by: &str, cursor: &str| {
+            ListParameters::from_query_params::<Topic>(&Query(PageFilters {
+                page: None,
+                page_size: None,
+                order_by: Some(order_by.to_string()),
+                ordering: None,
+                cursor: Some(cursor.to_string()),
+                filters: BTreeMap::new(),
+            }))
+        };
+        let cursor = Cursor::after(&Topic::default(), "title").encode();
+
+        assert!(listing("title", &cursor).is_ok());
+        assert_eq!(
+            listing("shoe_size", &cursor).err(),
+            Some(FilterError::UnknownSortField("shoe_size".to_string()))
+        );
+        assert_eq!(
+            listing("title", "garbled").err(),
+            Some(FilterError::InvalidCursor("garbled".to_string()))
+        );
+    }
+}
@@ -1,12 +1,64 @@
+use crate::persistence::repository::RepositoryError;
 use crate::petty_matters::comment::Comment;
-use crate::petty_matters::topic::Topic;
+use crate::petty_matters::topic::{Topic, TopicId};
 use async_trait::async_trait;
-use std::fmt::Display;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use uuid::Uuid;
 
-#[derive(Debug)]
+/// Tells a write apart from its repeats, such as a form submitted twice
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct IdempotencyKey(pub Uuid);
+
+impl IdempotencyKey {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Display for IdempotencyKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum WriteOperation {
     CreateTopic(Topic),
+    UpdateTopic(Topic),
     AddComment(Comment),
+    UpdateComment(Comment),
+    /// Hard-deletes topics and comments that were soft-deleted before the given time
+    PurgeDeleted(DateTime<Utc>),
+    /// Applied in a single unit of work: either every operation succeeds or none does
+    Batch(Vec<Self>),
+    /// Ignored if a write with the same key was applied within the idempotency window
+    Idempotent(IdempotencyKey, Box<Self>),
+}
+
+impl WriteOperation {
+    /// The petty matter the write is about, if it's about a single one.
+    /// Writes about the same petty matter have to be applied in the order they came in.
+    pub fn topic_id(&self) -> Option<TopicId> {
+        match self {
+            Self::CreateTopic(topic) | Self::UpdateTopic(topic) => Some(topic.id),
+            Self::AddComment(comment) | Self::UpdateComment(comment) => Some(comment.topic_id),
+            Self::PurgeDeleted(_) => None,
+            Self::Idempotent(_, operation) => operation.topic_id(),
+            Self::Batch(operations) => {
+                let mut topic_ids = operations.iter().map(Self::topic_id);
+                let first = topic_ids.next().flatten();
+                topic_ids
+                    .all(|topic_id| topic_id == first)
+                    .then_some(first)
+                    .flatten()
+            }
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -14,6 +66,12 @@ pub enum QueueError {
     SendError(String),
     OperationFailed(String),
     InvalidInput(String),
+    PermissionDenied(String),
+    Conflict(String),
+    /// The write could not be carried out for now, but may be retried later
+    Unavailable(String),
+    /// Too many writes are already waiting, so this one wasn't taken
+    Overwhelmed(String),
 }
 
 impl Display for QueueError {
@@ -22,13 +80,78 @@ impl Display for QueueError {
             Self::SendError(msg) => write!(f, "Send error: {msg}"),
             Self::OperationFailed(msg) => write!(f, "Operation failed: {msg}"),
             Self::InvalidInput(msg) => write!(f, "Invalid data provided: {msg}"),
+            Self::PermissionDenied(msg) => write!(f, "Permission denied: {msg}"),
+            Self::Conflict(msg) => write!(f, "Conflicting write: {msg}"),
+            Self::Unavailable(msg) => write!(f, "Temporarily unavailable: {msg}"),
+            Self::Overwhelmed(msg) => write!(f, "Too busy: {msg}"),
         }
     }
 }
 
 impl std::error::Error for QueueError {}
 
+impl QueueError {
+    /// Whether trying the same write again later may succeed, rather than fail the same way
+    pub const fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Unavailable(_) | Self::SendError(_) | Self::Overwhelmed(_)
+        )
+    }
+}
+
+impl From<RepositoryError> for QueueError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::Conflict(msg)
+            | RepositoryError::NotFound(msg)
+            | RepositoryError::Duplicate(msg) => Self::Conflict(msg),
+            RepositoryError::ConstraintViolation(msg) => Self::InvalidInput(msg),
+            e if e.is_transient() => Self::Unavailable(e.to_string()),
+            e => Self::OperationFailed(e.to_string()),
+        }
+    }
+}
+
+/// What became of a write by the time its author stopped waiting
+#[derive(Debug, Eq, PartialEq)]
+pub enum WriteStatus {
+    Applied,
+    /// Still queued, and going to be applied as soon as the worker gets to it
+    Pending,
+}
+
+/// Resolves once the write it was handed out for has been applied or given up on.
+/// Dropping it leaves the write to go through on its own.
+pub struct Completion {
+    outcome: Option<oneshot::Receiver<Result<(), QueueError>>>,
+}
+
+impl Completion {
+    pub const fn new(outcome: oneshot::Receiver<Result<(), QueueError>>) -> Self {
+        Self {
+            outcome: Some(outcome),
+        }
+    }
+
+    /// For writes that were applied on the spot
+    pub const fn applied() -> Self {
+        Self { outcome: None }
+    }
+
+    pub async fn wait(self, within: Duration) -> Result<WriteStatus, QueueError> {
+        let Some(outcome) = self.outcome else {
+            return Ok(WriteStatus::Applied);
+        };
+        match timeout(within, outcome).await {
+            Ok(Ok(outcome)) => outcome.map(|()| WriteStatus::Applied),
+            // Whoever would have reported back is gone, but the write itself is still queued
+            Ok(Err(_)) | Err(_) => Ok(WriteStatus::Pending),
+        }
+    }
+}
+
 #[async_trait]
 pub trait Queue {
-    async fn enqueue(&self, op: WriteOperation) -> Result<(), QueueError>;
+    async fn enqueue(&self, op: WriteOperation) -> Result<Completion, QueueError>;
 }
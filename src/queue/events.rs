@@ -0,0 +1,150 @@
+use crate::petty_matters::comment::Comment;
+use crate::petty_matters::topic::Topic;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::task::JoinSet;
+
+/// What happened to the Ministry's records, told once the write behind it has been applied.
+/// Carries the records as they were written, for subscribers to make of them what they need.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub enum DomainEvent {
+    TopicCreated(Topic),
+    TopicUpdated(Topic),
+    TopicDeleted(Topic),
+    TopicRestored(Topic),
+    CommentAdded(Comment),
+    CommentUpdated(Comment),
+    CommentDeleted(Comment),
+    CommentRestored(Comment),
+    /// Those soft-deleted before `deleted_before` are gone for good
+    DeletedPurged {
+        deleted_before: DateTime<Utc>,
+        topics: u64,
+        comments: u64,
+    },
+}
+
+impl DomainEvent {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::TopicCreated(_) => "topic_created",
+            Self::TopicUpdated(_) => "topic_updated",
+            Self::TopicDeleted(_) => "topic_deleted",
+            Self::TopicRestored(_) => "topic_restored",
+            Self::CommentAdded(_) => "comment_added",
+            Self::CommentUpdated(_) => "comment_updated",
+            Self::CommentDeleted(_) => "comment_deleted",
+            Self::CommentRestored(_) => "comment_restored",
+            Self::DeletedPurged { .. } => "deleted_purged",
+        }
+    }
+}
+
+/// Reacts to what happens to the records, e.g. by notifying someone or updating a feed
+#[async_trait]
+pub trait EventSubscriber: Send + Sync {
+    /// Holds up the next write for as long as it runs, so anything slow is best spawned
+    async fn handle(&self, event: &DomainEvent);
+}
+
+/// Hands each event to every subscriber, in the order the writes were applied
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<RwLock<Vec<Arc<dyn EventSubscriber>>>>,
+}
+
+impl EventBus {
+    pub async fn subscribe(&self, subscriber: Arc<dyn EventSubscriber>) {
+        self.subscribers.write().await.push(subscriber);
+    }
+
+    /// Each subscriber takes the events on its own, so one that fails doesn't keep them
+    /// from the others, nor take the write down with it
+    pub async fn publish(&self, events: Vec<DomainEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        let events = Arc::new(events);
+        let mut deliveries = JoinSet::new();
+        for subscriber in self.subscribers.read().await.iter() {
+            let (subscriber, events) = (subscriber.clone(), events.clone());
+            deliveries.spawn(async move {
+                for event in events.iter() {
+                    subscriber.handle(event).await;
+                }
+            });
+        }
+        while let Some(delivered) = deliveries.join_next().await {
+            if let Err(e) = delivered {
+                eprintln!("A subscriber failed to handle the events: {e}");
+            }
+        }
+    }
+}
+
+/// Counts the events published, by name, for the metrics
+#[derive(Default)]
+pub struct EventCounts {
+    counts: std::sync::Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl EventCounts {
+    pub fn counts(&self) -> Vec<(&'static str, u64)> {
+        self.counts
+            .lock()
+            .map(|counts| counts.iter().map(|(name, count)| (*name, *count)).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for EventCounts {
+    async fn handle(&self, event: &DomainEvent) {
+        if let Ok(mut counts) = self.counts.lock() {
+            *counts.entry(event.name()).or_default() += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Failing;
+
+    #[async_trait]
+    impl EventSubscriber for Failing {
+        #[allow(clippy::panic)]
+        async fn handle(&self, _event: &DomainEvent) {
+            panic!("Out to lunch");
+        }
+    }
+
+    #[tokio::test]
+    async fn every_subscriber_is_told_of_every_event() {
+        let events = EventBus::default();
+        let (first, second) = (
+            Arc::new(EventCounts::default()),
+            Arc::new(EventCounts::default()),
+        );
+        events.subscribe(first.clone()).await;
+        events.subscribe(Arc::new(Failing)).await;
+        events.subscribe(second.clone()).await;
+
+        events
+            .publish(vec![
+                DomainEvent::TopicCreated(Topic::default()),
+                DomainEvent::TopicUpdated(Topic::default()),
+                DomainEvent::TopicCreated(Topic::default()),
+            ])
+            .await;
+
+        let expected = vec![("topic_created", 2), ("topic_updated", 1)];
+        assert_eq!(first.counts(), expected);
+        assert_eq!(second.counts(), expected);
+    }
+}
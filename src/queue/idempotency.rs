@@ -0,0 +1,127 @@
+use crate::persistence::unit_of_work::Transaction;
+use crate::queue::base::{IdempotencyKey, QueueError};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    static IDEMPOTENCY_KEY: Option<IdempotencyKey>;
+}
+
+/// Runs the future with the writes it enqueues keyed, so they're applied only once
+/// however many times it's run within the idempotency window
+pub async fn with_idempotency_key<F: Future>(key: Option<IdempotencyKey>, future: F) -> F::Output {
+    IDEMPOTENCY_KEY.scope(key, future).await
+}
+
+/// The key the current task's writes are enqueued with, if any
+pub fn idempotency_key() -> Option<IdempotencyKey> {
+    IDEMPOTENCY_KEY.try_with(|key| *key).ok().flatten()
+}
+
+/// The keys of the writes applied lately, so their repeats can be ignored
+#[async_trait]
+pub trait IdempotencyKeys: Send + Sync {
+    /// Takes note of the key within the transaction applying its write, so it's only
+    /// remembered if the write goes through. Returns whether it was new rather than a repeat.
+    async fn remember(
+        &self,
+        key: IdempotencyKey,
+        transaction: &Transaction,
+    ) -> Result<bool, QueueError>;
+}
+
+/// Keys remembered by this instance only, and only until it restarts.
+/// A key is added once its transaction is committed.
+pub struct InMemoryIdempotencyKeys {
+    window: Duration,
+    remembered: Arc<Mutex<HashMap<IdempotencyKey, Instant>>>,
+}
+
+impl InMemoryIdempotencyKeys {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            remembered: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl IdempotencyKeys for InMemoryIdempotencyKeys {
+    async fn remember(
+        &self,
+        key: IdempotencyKey,
+        transaction: &Transaction,
+    ) -> Result<bool, QueueError> {
+        let now = Instant::now();
+        let mut remembered = self
+            .remembered
+            .lock()
+            .map_err(|e| QueueError::OperationFailed(e.to_string()))?;
+        remembered.retain(|_, remembered_at| now.duration_since(*remembered_at) < self.window);
+        let is_new = !remembered.contains_key(&key);
+        drop(remembered);
+        if is_new {
+            let remembered = self.remembered.clone();
+            transaction.after_commit(move || {
+                if let Ok(mut remembered) = remembered.lock() {
+                    remembered.insert(key, Instant::now());
+                }
+            });
+        }
+
+        Ok(is_new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::unit_of_work::UnitOfWork;
+
+    #[tokio::test]
+    async fn keys_are_only_new_once_committed_within_the_window() -> Result<(), AnyError> {
+        let keys = InMemoryIdempotencyKeys::new(Duration::from_millis(50));
+        let key = IdempotencyKey::new();
+        let unit_of_work = UnitOfWork::default();
+        let (keys, unit_of_work) = (&keys, &unit_of_work);
+        let remember = |commits: bool| async move {
+            let transaction = unit_of_work.begin().await?;
+            let is_new = keys.remember(key, &transaction).await?;
+            if commits {
+                transaction.commit().await?;
+            } else {
+                transaction.rollback().await?;
+            }
+            Ok::<_, AnyError>(is_new)
+        };
+
+        let rolled_back = remember(false).await?;
+        let first = remember(true).await?;
+        let repeat = remember(true).await?;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let after_the_window = remember(true).await?;
+
+        assert!(rolled_back);
+        assert!(first);
+        assert!(!repeat);
+        assert!(after_the_window);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keys_are_only_seen_within_their_scope() {
+        let key = IdempotencyKey::new();
+
+        let within = with_idempotency_key(Some(key), async { idempotency_key() }).await;
+
+        assert_eq!(within, Some(key));
+        assert_eq!(idempotency_key(), None);
+    }
+}
@@ -1,13 +1,17 @@
-use crate::queue::base::WriteOperation;
+use crate::queue::base::{Completion, WriteOperation};
 use crate::queue::base::{Queue, QueueError};
 use async_trait::async_trait;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
 
+/// Hands writes over to whoever holds the receiving end, keeping nothing should they be lost.
+/// Nothing reports back through it, so its writes are only ever known to be pending.
 #[derive(Clone)]
 pub struct WriteQueue {
     pub sender: Sender<WriteOperation>,
 }
 
+#[allow(dead_code)]
 impl WriteQueue {
     pub const fn new(sender: Sender<WriteOperation>) -> Self {
         Self { sender }
@@ -16,10 +20,12 @@ impl WriteQueue {
 
 #[async_trait]
 impl Queue for WriteQueue {
-    async fn enqueue(&self, op: WriteOperation) -> Result<(), QueueError> {
+    async fn enqueue(&self, op: WriteOperation) -> Result<Completion, QueueError> {
+        let (_, outcome) = oneshot::channel();
         self.sender
             .send(op)
             .await
+            .map(|()| Completion::new(outcome))
             .map_err(|e| QueueError::SendError(e.to_string()))
     }
 }
@@ -0,0 +1,425 @@
+use crate::persistence::file_repository::read_lines;
+use crate::queue::base::{QueueError, WriteOperation};
+use crate::queue::outbox::{CLAIM_LEASE, DeadLetter, Outbox, OutboxEntry, OutboxEntryId};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+#[derive(Serialize, Deserialize)]
+enum JournalEntry {
+    Appended(OutboxEntryId, WriteOperation),
+    /// Released after this many more attempts
+    Attempted(OutboxEntryId, u32),
+    Done(OutboxEntryId),
+}
+
+struct PendingWrite {
+    operation: WriteOperation,
+    claimed_at: Option<Instant>,
+    attempts: u32,
+}
+
+struct Journal {
+    pending: BTreeMap<OutboxEntryId, PendingWrite>,
+    next_id: i64,
+    file: Option<File>,
+    dead_letters: Vec<DeadLetter>,
+    dead_letter_file: Option<File>,
+}
+
+/// An outbox for running without a database: pending writes are kept in memory and,
+/// when given a directory, journaled to `outbox.journal.jsonl` before being acknowledged.
+///
+/// The journal is started afresh on open and whenever every write in it has been applied.
+/// Writes given up on are kept for good in `outbox.dead_letters.jsonl`.
+pub struct JournalOutbox {
+    journal: Mutex<Journal>,
+}
+
+impl From<std::io::Error> for QueueError {
+    fn from(err: std::io::Error) -> Self {
+        Self::SendError(format!("Could not journal the write: {err}"))
+    }
+}
+
+impl JournalOutbox {
+    /// Pending writes are lost on restart, as are the records they would have been written to
+    pub fn in_memory() -> Self {
+        Self {
+            journal: Mutex::new(Journal {
+                pending: BTreeMap::new(),
+                next_id: 1,
+                file: None,
+                dead_letters: vec![],
+                dead_letter_file: None,
+            }),
+        }
+    }
+
+    /// Replays the writes that weren't applied before the last shutdown
+    pub async fn open(directory: &Path) -> Result<Self, QueueError> {
+        tokio::fs::create_dir_all(directory).await?;
+        let path = directory.join("outbox.journal.jsonl");
+
+        let dead_letter_path = directory.join("outbox.dead_letters.jsonl");
+        let dead_letters: Vec<DeadLetter> = read_entries(&dead_letter_path).await?;
+
+        let mut pending = BTreeMap::new();
+        for entry in read_entries(&path).await? {
+            match entry {
+                JournalEntry::Appended(id, operation) => {
+                    pending.insert(
+                        id,
+                        PendingWrite {
+                            operation,
+                            claimed_at: None,
+                            attempts: 0,
+                        },
+                    );
+                }
+                JournalEntry::Attempted(id, attempts) => {
+                    if let Some(write) = pending.get_mut(&id) {
+                        write.attempts = write.attempts.saturating_add(attempts);
+                    }
+                }
+                JournalEntry::Done(id) => {
+                    pending.remove(&id);
+                }
+            }
+        }
+        if !pending.is_empty() {
+            println!("Replaying {} writes left in the outbox", pending.len());
+        }
+
+        // Only what's still pending is carried over, written aside first so a crash loses nothing
+        let compacted_path = directory.join("outbox.journal.jsonl.tmp");
+        let mut compacted = File::create(&compacted_path).await?;
+        for (id, write) in &pending {
+            let mut entries = vec![JournalEntry::Appended(*id, write.operation.clone())];
+            if write.attempts > 0 {
+                entries.push(JournalEntry::Attempted(*id, write.attempts));
+            }
+            for entry in entries {
+                compacted
+                    .write_all(format!("{}\n", serde_json::to_string(&entry)?).as_bytes())
+                    .await?;
+            }
+        }
+        compacted.sync_all().await?;
+        tokio::fs::rename(&compacted_path, &path).await?;
+
+        let file = OpenOptions::new().append(true).open(&path).await?;
+        let dead_letter_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&dead_letter_path)
+            .await?;
+        let next_id = pending
+            .keys()
+            .chain(dead_letters.iter().map(|dead_letter| &dead_letter.id))
+            .max()
+            .map_or(1, |id| id.0 + 1);
+
+        Ok(Self {
+            journal: Mutex::new(Journal {
+                pending,
+                next_id,
+                file: Some(file),
+                dead_letters,
+                dead_letter_file: Some(dead_letter_file),
+            }),
+        })
+    }
+}
+
+/// Reads every entry of a journal, dropping a last one cut short by a crash,
+/// as it was never acknowledged
+async fn read_entries<Entry: DeserializeOwned>(path: &Path) -> Result<Vec<Entry>, QueueError> {
+    let lines = read_lines(path)
+        .await
+        .map_err(|e| QueueError::OperationFailed(e.to_string()))?;
+    let last_line_number = lines.len();
+    let mut entries = vec![];
+    for (line_number, line) in (1..).zip(lines) {
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) if line_number == last_line_number => {
+                eprintln!(
+                    "Discarding incomplete entry at the end of {}: {e}",
+                    path.display()
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(entries)
+}
+
+async fn append_line(file: &mut File, entry: &(impl Serialize + Sync)) -> Result<(), QueueError> {
+    file.write_all(format!("{}\n", serde_json::to_string(entry)?).as_bytes())
+        .await?;
+
+    Ok(file.sync_data().await?)
+}
+
+impl Journal {
+    async fn record(&mut self, entry: &JournalEntry) -> Result<(), QueueError> {
+        match &mut self.file {
+            Some(file) => append_line(file, entry).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn settle(&mut self, id: OutboxEntryId) -> Result<(), QueueError> {
+        if self.pending.remove(&id).is_none() {
+            return Ok(());
+        }
+        if self.pending.is_empty()
+            && let Some(file) = &mut self.file
+        {
+            file.set_len(0).await?;
+            return Ok(file.sync_data().await?);
+        }
+
+        self.record(&JournalEntry::Done(id)).await
+    }
+}
+
+#[async_trait]
+impl Outbox for JournalOutbox {
+    async fn append(&self, operation: &WriteOperation) -> Result<OutboxEntryId, QueueError> {
+        let mut journal = self.journal.lock().await;
+        let id = OutboxEntryId(journal.next_id);
+        journal
+            .record(&JournalEntry::Appended(id, operation.clone()))
+            .await?;
+        journal.next_id += 1;
+        journal.pending.insert(
+            id,
+            PendingWrite {
+                operation: operation.clone(),
+                claimed_at: None,
+                attempts: 0,
+            },
+        );
+        drop(journal);
+
+        Ok(id)
+    }
+
+    async fn claim(&self, limit: usize) -> Result<Vec<OutboxEntry>, QueueError> {
+        let mut journal = self.journal.lock().await;
+        let now = Instant::now();
+
+        Ok(journal
+            .pending
+            .iter_mut()
+            .filter(|(_, write)| {
+                write
+                    .claimed_at
+                    .is_none_or(|claimed_at| now.duration_since(claimed_at) > CLAIM_LEASE)
+            })
+            .take(limit)
+            .map(|(id, write)| {
+                write.claimed_at = Some(now);
+                OutboxEntry {
+                    id: *id,
+                    operation: write.operation.clone(),
+                    attempts: write.attempts,
+                }
+            })
+            .collect())
+    }
+
+    async fn release(&self, id: OutboxEntryId, attempts: u32) -> Result<(), QueueError> {
+        let mut journal = self.journal.lock().await;
+        let Some(write) = journal.pending.get_mut(&id) else {
+            return Ok(());
+        };
+        write.claimed_at = None;
+        if attempts == 0 {
+            return Ok(());
+        }
+        write.attempts = write.attempts.saturating_add(attempts);
+
+        journal.record(&JournalEntry::Attempted(id, attempts)).await
+    }
+
+    async fn mark_done(&self, id: OutboxEntryId) -> Result<(), QueueError> {
+        self.journal.lock().await.settle(id).await
+    }
+
+    async fn dead_letter(
+        &self,
+        id: OutboxEntryId,
+        error: &QueueError,
+        attempts: u32,
+    ) -> Result<(), QueueError> {
+        let mut journal = self.journal.lock().await;
+        let Some(write) = journal.pending.get(&id) else {
+            return Ok(());
+        };
+        let dead_letter = DeadLetter {
+            id,
+            operation: write.operation.clone(),
+            error: error.to_string(),
+            attempts,
+            failed_at: Utc::now(),
+        };
+        if let Some(file) = &mut journal.dead_letter_file {
+            append_line(file, &dead_letter).await?;
+        }
+        journal.dead_letters.push(dead_letter);
+
+        journal.settle(id).await
+    }
+
+    async fn depth(&self) -> Result<usize, QueueError> {
+        Ok(self.journal.lock().await.pending.len())
+    }
+
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, QueueError> {
+        let journal = self.journal.lock().await;
+
+        Ok(journal
+            .dead_letters
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::petty_matters::topic::Topic;
+    use tempfile::TempDir;
+
+    async fn open(directory: &Path) -> Result<JournalOutbox, QueueError> {
+        JournalOutbox::open(directory).await
+    }
+
+    #[tokio::test]
+    async fn unapplied_writes_are_replayed_after_a_restart() -> Result<(), AnyError> {
+        let directory = TempDir::new()?;
+        let outbox = open(directory.path()).await?;
+        let applied = Topic::default();
+        let unapplied = Topic::default();
+        let applied_id = outbox.append(&WriteOperation::CreateTopic(applied)).await?;
+        outbox
+            .append(&WriteOperation::CreateTopic(unapplied.clone()))
+            .await?;
+        outbox.claim(10).await?;
+        outbox.mark_done(applied_id).await?;
+        drop(outbox);
+
+        let reopened = open(directory.path()).await?;
+        let entries = reopened.claim(10).await?;
+
+        assert_eq!(entries.len(), 1);
+        assert!(matches!(
+            entries.first().map(|entry| &entry.operation),
+            Some(WriteOperation::CreateTopic(topic)) if topic.id == unapplied.id
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_set_aside_are_kept_after_a_restart() -> Result<(), AnyError> {
+        let directory = TempDir::new()?;
+        let outbox = open(directory.path()).await?;
+        let id = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+        outbox
+            .dead_letter(id, &QueueError::Conflict("Already on file".to_string()), 1)
+            .await?;
+        drop(outbox);
+
+        let reopened = open(directory.path()).await?;
+        let next_id = reopened
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+        let dead_letters = reopened.dead_letters(10).await?;
+
+        assert_eq!(
+            dead_letters
+                .iter()
+                .map(|dead_letter| dead_letter.id)
+                .collect::<Vec<_>>(),
+            vec![id]
+        );
+        assert_ne!(next_id, id);
+        assert_eq!(reopened.claim(10).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn claimed_writes_are_not_handed_out_twice() -> Result<(), AnyError> {
+        let outbox = JournalOutbox::in_memory();
+        let ids = |entries: Vec<OutboxEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>()
+        };
+        let first = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+        let second = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+
+        let claimed = outbox.claim(1).await?;
+        let claimed_next = outbox.claim(10).await?;
+        outbox.release(first, 0).await?;
+        let claimed_again = outbox.claim(10).await?;
+
+        assert_eq!(ids(claimed), vec![first]);
+        assert_eq!(ids(claimed_next), vec![second]);
+        assert_eq!(ids(claimed_again), vec![first]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn attempts_are_counted_across_claims_and_restarts() -> Result<(), AnyError> {
+        let directory = TempDir::new()?;
+        let outbox = open(directory.path()).await?;
+        let id = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+        outbox.claim(10).await?;
+        outbox.release(id, 3).await?;
+        outbox.claim(10).await?;
+        outbox.release(id, 2).await?;
+        drop(outbox);
+
+        let reopened = open(directory.path()).await?;
+
+        assert_eq!(
+            reopened
+                .claim(10)
+                .await?
+                .iter()
+                .map(|entry| entry.attempts)
+                .collect::<Vec<_>>(),
+            vec![5]
+        );
+
+        Ok(())
+    }
+}
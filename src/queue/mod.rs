@@ -1,4 +1,10 @@
 pub mod base;
+pub mod events;
+pub mod idempotency;
 pub mod in_memory_queue;
+pub mod journal_outbox;
+pub mod outbox;
+pub mod rdbms_idempotency;
+pub mod rdbms_outbox;
 pub mod stub_queue;
 pub mod worker;
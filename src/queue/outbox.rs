@@ -0,0 +1,299 @@
+use crate::queue::base::{Completion, Queue, QueueError, WriteOperation};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify, oneshot};
+use tokio::time::{Instant, timeout};
+
+/// How long a claimed entry is left alone before it's assumed its worker died with it
+pub static CLAIM_LEASE: Duration = Duration::from_mins(5);
+static DEFAULT_CAPACITY: usize = 1_000;
+static DEFAULT_ENQUEUE_DEADLINE: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct OutboxEntryId(pub i64);
+
+impl Display for OutboxEntryId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct OutboxEntry {
+    pub id: OutboxEntryId,
+    pub operation: WriteOperation,
+    /// Made under earlier claims, so a write can't keep coming back for good
+    pub attempts: u32,
+}
+
+/// A write that was given up on, kept so it can be looked into and replayed by hand
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub id: OutboxEntryId,
+    pub operation: WriteOperation,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Where writes wait until they're applied, surviving restarts in the meantime
+#[async_trait]
+pub trait Outbox: Send + Sync {
+    /// Stores the operation for good, so it's only lost once marked done
+    async fn append(&self, operation: &WriteOperation) -> Result<OutboxEntryId, QueueError>;
+    /// Takes up to `limit` entries nobody else is working on, oldest first
+    async fn claim(&self, limit: usize) -> Result<Vec<OutboxEntry>, QueueError>;
+    /// Gives up a claim, so the entry is picked up again, counting the attempts made under it
+    async fn release(&self, id: OutboxEntryId, attempts: u32) -> Result<(), QueueError>;
+    /// Removes an entry that has been dealt with
+    async fn mark_done(&self, id: OutboxEntryId) -> Result<(), QueueError>;
+    /// Moves an entry that keeps failing out of the way of the writes behind it
+    async fn dead_letter(
+        &self,
+        id: OutboxEntryId,
+        error: &QueueError,
+        attempts: u32,
+    ) -> Result<(), QueueError>;
+    /// The writes given up on, most recent first
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, QueueError>;
+    /// How many writes are waiting to be applied, claimed or not
+    async fn depth(&self) -> Result<usize, QueueError>;
+}
+
+type Outcome = Result<(), QueueError>;
+
+/// Writes whose authors may be waiting on them. Those applied by another instance
+/// are never reported back, so their authors stop waiting once their time is up.
+#[derive(Default)]
+struct Completions {
+    awaiting: HashMap<OutboxEntryId, oneshot::Sender<Outcome>>,
+    /// Writes can be applied before their authors start waiting, in which case the outcome
+    /// is kept for them, though no longer than storing a write is given
+    settled_early: HashMap<OutboxEntryId, (Instant, Outcome)>,
+}
+
+/// Only acknowledges a write once it's safely in the outbox, then nudges the worker
+#[derive(Clone)]
+pub struct OutboxQueue {
+    outbox: Arc<dyn Outbox>,
+    wake_up: Arc<Notify>,
+    completions: Arc<Mutex<Completions>>,
+    /// Set once the application is shutting down, after which no more writes are taken
+    is_closing: Arc<AtomicBool>,
+    drained: Arc<Notify>,
+    /// Last counted by the worker, and kept up with by every write taken since
+    depth: Arc<AtomicUsize>,
+    /// Past this many writes waiting, new ones are turned away
+    capacity: usize,
+    enqueue_deadline: Duration,
+}
+
+impl OutboxQueue {
+    pub fn new(outbox: Arc<dyn Outbox>) -> Self {
+        Self {
+            outbox,
+            wake_up: Arc::new(Notify::new()),
+            completions: Arc::new(Mutex::new(Completions::default())),
+            is_closing: Arc::new(AtomicBool::new(false)),
+            drained: Arc::new(Notify::new()),
+            depth: Arc::new(AtomicUsize::new(0)),
+            capacity: DEFAULT_CAPACITY,
+            enqueue_deadline: DEFAULT_ENQUEUE_DEADLINE,
+        }
+    }
+
+    /// Writes beyond `capacity` waiting, or that can't be stored within `enqueue_deadline`,
+    /// are turned away rather than kept waiting
+    pub const fn with_limits(mut self, capacity: usize, enqueue_deadline: Duration) -> Self {
+        self.capacity = capacity;
+        self.enqueue_deadline = enqueue_deadline;
+        self
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Acquire)
+    }
+
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Counts the writes waiting anew, including those taken by other instances
+    pub async fn refresh_depth(&self) -> Result<(), QueueError> {
+        let depth = self.outbox.depth().await?;
+        self.depth.store(depth, Ordering::Release);
+
+        Ok(())
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.is_closing.load(Ordering::Acquire)
+    }
+
+    /// Turns away new writes and waits for the worker to apply those already taken,
+    /// returning whether it managed to before `within` was up.
+    /// Whatever is left stays in the outbox for the next start.
+    pub async fn drain(&self, within: Duration) -> bool {
+        self.is_closing.store(true, Ordering::Release);
+        self.wake_up.notify_one();
+
+        timeout(within, self.drained.notified()).await.is_ok()
+    }
+
+    /// Called by the worker once the outbox is empty and the queue is closing
+    pub fn mark_drained(&self) {
+        self.drained.notify_one();
+    }
+
+    /// Lets whoever waits on the write know how it went
+    pub async fn complete(&self, id: OutboxEntryId, outcome: Outcome) {
+        let mut completions = self.completions.lock().await;
+        if let Some(awaiting) = completions.awaiting.remove(&id) {
+            let _ = awaiting.send(outcome);
+            return;
+        }
+        let enqueue_deadline = self.enqueue_deadline;
+        completions
+            .settled_early
+            .retain(|_, (settled_at, _)| settled_at.elapsed() < enqueue_deadline);
+        completions
+            .settled_early
+            .insert(id, (Instant::now(), outcome));
+    }
+
+    pub fn outbox(&self) -> Arc<dyn Outbox> {
+        self.outbox.clone()
+    }
+
+    /// Signalled on every write, so the worker needn't wait for its next round
+    pub fn wake_up(&self) -> Arc<Notify> {
+        self.wake_up.clone()
+    }
+}
+
+#[async_trait]
+impl Queue for OutboxQueue {
+    async fn enqueue(&self, op: WriteOperation) -> Result<Completion, QueueError> {
+        if self.is_closing() {
+            return Err(QueueError::Unavailable(
+                "The Ministry is closing for the day".to_string(),
+            ));
+        }
+        let depth = self.depth();
+        if depth >= self.capacity {
+            return Err(QueueError::Overwhelmed(format!(
+                "{depth} writes are already waiting"
+            )));
+        }
+        // Storing the write carries on even if its author stops waiting,
+        // so a write cut short doesn't leave a torn entry behind
+        let outbox = self.outbox.clone();
+        let append = tokio::spawn(async move { outbox.append(&op).await });
+        let id = match timeout(self.enqueue_deadline, append).await {
+            Ok(Ok(appended)) => appended?,
+            Ok(Err(e)) => return Err(QueueError::SendError(e.to_string())),
+            Err(_) => {
+                return Err(QueueError::Overwhelmed(format!(
+                    "The write could not be stored within {} seconds, it may still go through",
+                    self.enqueue_deadline.as_secs()
+                )));
+            }
+        };
+        self.depth.fetch_add(1, Ordering::AcqRel);
+        let (sender, receiver) = oneshot::channel();
+        let mut completions = self.completions.lock().await;
+        // Nobody waits on writes whose completion was dropped, or was never reported back
+        completions
+            .awaiting
+            .retain(|_, awaiting| !awaiting.is_closed());
+        match completions.settled_early.remove(&id) {
+            Some((_, outcome)) => {
+                let _ = sender.send(outcome);
+            }
+            None => {
+                completions.awaiting.insert(id, sender);
+            }
+        }
+        drop(completions);
+        self.wake_up.notify_one();
+
+        Ok(Completion::new(receiver))
+    }
+}
+
+impl From<serde_json::Error> for QueueError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::OperationFailed(format!("Unreadable write operation: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::petty_matters::topic::Topic;
+    use crate::queue::base::WriteStatus;
+    use crate::queue::journal_outbox::JournalOutbox;
+
+    #[tokio::test]
+    async fn writes_not_applied_in_time_are_left_pending() -> Result<(), AnyError> {
+        let outbox = Arc::new(JournalOutbox::in_memory());
+        let queue = OutboxQueue::new(outbox.clone());
+
+        let status = queue
+            .enqueue(WriteOperation::CreateTopic(Topic::default()))
+            .await?
+            .wait(Duration::from_millis(10))
+            .await;
+
+        assert_eq!(status, Ok(WriteStatus::Pending));
+        assert_eq!(outbox.claim(10).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_applied_before_their_authors_wait_are_still_reported() -> Result<(), AnyError> {
+        let outbox = Arc::new(JournalOutbox::in_memory());
+        let queue = OutboxQueue::new(outbox.clone());
+        let next_id = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?
+            .0
+            + 1;
+
+        queue.complete(OutboxEntryId(next_id), Ok(())).await;
+        let status = queue
+            .enqueue(WriteOperation::CreateTopic(Topic::default()))
+            .await?
+            .wait(Duration::from_millis(10))
+            .await;
+
+        assert_eq!(status, Ok(WriteStatus::Applied));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_beyond_capacity_are_turned_away() {
+        let queue = OutboxQueue::new(Arc::new(JournalOutbox::in_memory()))
+            .with_limits(1, Duration::from_secs(1));
+
+        let taken = queue
+            .enqueue(WriteOperation::CreateTopic(Topic::default()))
+            .await;
+        let turned_away = queue
+            .enqueue(WriteOperation::CreateTopic(Topic::default()))
+            .await;
+
+        assert!(taken.is_ok());
+        assert_eq!(queue.depth(), 1);
+        assert!(matches!(turned_away, Err(QueueError::Overwhelmed(_))));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::persistence::repository::RepositoryError;
+use crate::persistence::unit_of_work::Transaction;
+use crate::queue::base::{IdempotencyKey, QueueError};
+use crate::queue::idempotency::IdempotencyKeys;
+use async_trait::async_trait;
+use chrono::Utc;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+    TryInsertResult,
+};
+use std::time::Duration;
+
+mod idempotency_key_record {
+    use chrono::Utc;
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "idempotency_keys")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub key: Uuid,
+        pub remembered_at: chrono::DateTime<Utc>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+use idempotency_key_record::{ActiveModel, Column, Entity};
+
+/// Keeps the keys in the `idempotency_keys` table, so a repeat is recognised
+/// whichever instance it reaches. A key is inserted in the same transaction as its write,
+/// so neither is stored without the other. Keys past the window are cleared as new ones come in.
+pub struct RdbmsIdempotencyKeys {
+    db: DatabaseConnection,
+    window: Duration,
+}
+
+impl RdbmsIdempotencyKeys {
+    pub const fn new(db: DatabaseConnection, window: Duration) -> Self {
+        Self { db, window }
+    }
+
+    async fn remember_on(
+        &self,
+        db: &impl ConnectionTrait,
+        key: IdempotencyKey,
+    ) -> Result<bool, DbErr> {
+        let now = Utc::now();
+        let window = chrono::Duration::from_std(self.window).unwrap_or(chrono::Duration::MAX);
+        Entity::delete_many()
+            .filter(Column::RememberedAt.lt(now - window))
+            .exec(db)
+            .await?;
+        // Whoever inserts the key first applies the write, however many instances got it.
+        // Anyone else inserting it meanwhile waits to see whether that transaction commits.
+        let inserted = Entity::insert(ActiveModel {
+            key: Set(key.0),
+            remembered_at: Set(now),
+        })
+        .on_conflict(OnConflict::column(Column::Key).do_nothing().to_owned())
+        .do_nothing()
+        .exec_without_returning(db)
+        .await?;
+
+        Ok(matches!(inserted, TryInsertResult::Inserted(1..)))
+    }
+}
+
+fn idempotency_error(err: DbErr) -> QueueError {
+    RepositoryError::from(err).into()
+}
+
+#[async_trait]
+impl IdempotencyKeys for RdbmsIdempotencyKeys {
+    async fn remember(
+        &self,
+        key: IdempotencyKey,
+        transaction: &Transaction,
+    ) -> Result<bool, QueueError> {
+        match transaction.database() {
+            Some(database) => self.remember_on(database.as_ref(), key).await,
+            None => self.remember_on(&self.db, key).await,
+        }
+        .map_err(idempotency_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::persistence::unit_of_work::UnitOfWork;
+    use crate::testing::migrated_sqlite;
+
+    #[tokio::test]
+    async fn sqlite_keys_are_only_new_once_committed() -> Result<(), AnyError> {
+        let db = migrated_sqlite().await?;
+        let keys = RdbmsIdempotencyKeys::new(db.clone(), Duration::from_mins(1));
+        let key = IdempotencyKey::new();
+        let unit_of_work = UnitOfWork::new(Some(db));
+        let (keys, unit_of_work) = (&keys, &unit_of_work);
+        let remember = |commits: bool| async move {
+            let transaction = unit_of_work.begin().await?;
+            let is_new = keys.remember(key, &transaction).await?;
+            if commits {
+                transaction.commit().await?;
+            } else {
+                transaction.rollback().await?;
+            }
+            Ok::<_, AnyError>(is_new)
+        };
+
+        let rolled_back = remember(false).await?;
+        let first = remember(true).await?;
+        let repeat = remember(true).await?;
+
+        assert!(rolled_back);
+        assert!(first);
+        assert!(!repeat);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+use crate::persistence::repository::RepositoryError;
+use crate::queue::base::{QueueError, WriteOperation};
+use crate::queue::outbox::{CLAIM_LEASE, DeadLetter, Outbox, OutboxEntry, OutboxEntryId};
+use async_trait::async_trait;
+use chrono::Utc;
+use sea_orm::sea_query::{Expr, Query};
+use sea_orm::{
+    ActiveValue::NotSet, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbBackend,
+    DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, Statement,
+    TransactionTrait,
+};
+
+/// Shared by every instance of the forum, so only one of them claims writes at a time
+static CLAIM_LOCK_KEY: i64 = 0x4d6f_504d_436c_6169;
+
+mod outbox_record {
+    use chrono::Utc;
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "outbox")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i64,
+        pub operation: String,
+        pub enqueued_at: chrono::DateTime<Utc>,
+        pub claimed_at: Option<chrono::DateTime<Utc>>,
+        pub topic_id: Option<Uuid>,
+        /// Made under earlier claims
+        pub attempts: i32,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+mod dead_letter_record {
+    use chrono::Utc;
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "dead_letters")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: i64,
+        pub operation: String,
+        pub error: String,
+        pub attempts: i32,
+        pub enqueued_at: chrono::DateTime<Utc>,
+        pub failed_at: chrono::DateTime<Utc>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+use outbox_record::{ActiveModel, Column, Entity};
+
+/// Keeps pending writes in the `outbox` table, so any instance can pick them up,
+/// and nothing acknowledged is lost when one goes down.
+/// Writes about a petty matter another instance is still working on are left to it,
+/// so they're applied in the order they came in, whichever instance they end up with.
+/// Writes given up on are moved to the `dead_letters` table.
+pub struct RdbmsOutbox {
+    db: DatabaseConnection,
+}
+
+impl RdbmsOutbox {
+    pub const fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+fn outbox_error(err: DbErr) -> QueueError {
+    RepositoryError::from(err).into()
+}
+
+#[async_trait]
+impl Outbox for RdbmsOutbox {
+    async fn append(&self, operation: &WriteOperation) -> Result<OutboxEntryId, QueueError> {
+        let record = ActiveModel {
+            id: NotSet,
+            operation: Set(serde_json::to_string(operation)?),
+            enqueued_at: Set(Utc::now()),
+            claimed_at: Set(None),
+            topic_id: Set(operation.topic_id().map(|topic_id| topic_id.0)),
+            attempts: Set(0),
+        };
+        let inserted = Entity::insert(record)
+            .exec(&self.db)
+            .await
+            .map_err(outbox_error)?;
+
+        Ok(OutboxEntryId(inserted.last_insert_id))
+    }
+
+    async fn claim(&self, limit: usize) -> Result<Vec<OutboxEntry>, QueueError> {
+        let now = Utc::now();
+        let transaction = self.db.begin().await.map_err(outbox_error)?;
+        // Otherwise two instances claiming at once could each take some of a petty matter's writes
+        if self.db.get_database_backend() == DbBackend::Postgres {
+            transaction
+                .execute(Statement::from_sql_and_values(
+                    DbBackend::Postgres,
+                    "SELECT pg_advisory_xact_lock($1)",
+                    [CLAIM_LOCK_KEY.into()],
+                ))
+                .await
+                .map_err(outbox_error)?;
+        }
+        let is_unclaimed = Condition::any()
+            .add(Column::ClaimedAt.is_null())
+            .add(Column::ClaimedAt.lt(now - CLAIM_LEASE));
+        let topics_in_hand = Query::select()
+            .column(Column::TopicId)
+            .from(Entity)
+            .and_where(Column::TopicId.is_not_null())
+            .and_where(Column::ClaimedAt.gte(now - CLAIM_LEASE))
+            .to_owned();
+        let records = Entity::find()
+            .filter(is_unclaimed)
+            .filter(
+                Condition::any()
+                    .add(Column::TopicId.is_null())
+                    .add(Column::TopicId.not_in_subquery(topics_in_hand)),
+            )
+            .order_by_asc(Column::Id)
+            .limit(limit as u64)
+            .all(&transaction)
+            .await
+            .map_err(outbox_error)?;
+        if records.is_empty() {
+            return Ok(vec![]);
+        }
+        Entity::update_many()
+            .col_expr(Column::ClaimedAt, Expr::value(now))
+            .filter(Column::Id.is_in(records.iter().map(|record| record.id)))
+            .exec(&transaction)
+            .await
+            .map_err(outbox_error)?;
+        transaction.commit().await.map_err(outbox_error)?;
+
+        records
+            .into_iter()
+            .map(|record| {
+                Ok(OutboxEntry {
+                    id: OutboxEntryId(record.id),
+                    operation: serde_json::from_str(&record.operation)?,
+                    attempts: u32::try_from(record.attempts).unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    async fn release(&self, id: OutboxEntryId, attempts: u32) -> Result<(), QueueError> {
+        Entity::update_many()
+            .col_expr(
+                Column::ClaimedAt,
+                Expr::value(None::<chrono::DateTime<Utc>>),
+            )
+            .col_expr(
+                Column::Attempts,
+                Expr::col(Column::Attempts).add(i32::try_from(attempts).unwrap_or(i32::MAX)),
+            )
+            .filter(Column::Id.eq(id.0))
+            .exec(&self.db)
+            .await
+            .map_err(outbox_error)?;
+
+        Ok(())
+    }
+
+    async fn mark_done(&self, id: OutboxEntryId) -> Result<(), QueueError> {
+        Entity::delete_by_id(id.0)
+            .exec(&self.db)
+            .await
+            .map_err(outbox_error)?;
+
+        Ok(())
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    async fn dead_letter(
+        &self,
+        id: OutboxEntryId,
+        error: &QueueError,
+        attempts: u32,
+    ) -> Result<(), QueueError> {
+        let transaction = self.db.begin().await.map_err(outbox_error)?;
+        let Some(record) = Entity::find_by_id(id.0)
+            .one(&transaction)
+            .await
+            .map_err(outbox_error)?
+        else {
+            return Ok(());
+        };
+        dead_letter_record::Entity::insert(dead_letter_record::ActiveModel {
+            id: Set(record.id),
+            operation: Set(record.operation),
+            error: Set(error.to_string()),
+            attempts: Set(attempts as i32),
+            enqueued_at: Set(record.enqueued_at),
+            failed_at: Set(Utc::now()),
+        })
+        .exec(&transaction)
+        .await
+        .map_err(outbox_error)?;
+        Entity::delete_by_id(id.0)
+            .exec(&transaction)
+            .await
+            .map_err(outbox_error)?;
+
+        transaction.commit().await.map_err(outbox_error)
+    }
+
+    async fn depth(&self) -> Result<usize, QueueError> {
+        let depth = Entity::find().count(&self.db).await.map_err(outbox_error)?;
+
+        Ok(usize::try_from(depth).unwrap_or(usize::MAX))
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, QueueError> {
+        dead_letter_record::Entity::find()
+            .order_by_desc(dead_letter_record::Column::FailedAt)
+            .limit(limit as u64)
+            .all(&self.db)
+            .await
+            .map_err(outbox_error)?
+            .into_iter()
+            .map(|record| {
+                Ok(DeadLetter {
+                    id: OutboxEntryId(record.id),
+                    operation: serde_json::from_str(&record.operation)?,
+                    error: record.error,
+                    attempts: record.attempts as u32,
+                    failed_at: record.failed_at,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::petty_matters::topic::Topic;
+    use crate::testing::migrated_sqlite;
+
+    async fn sqlite_outbox() -> Result<RdbmsOutbox, AnyError> {
+        let db = migrated_sqlite().await?;
+
+        Ok(RdbmsOutbox::new(db))
+    }
+
+    #[tokio::test]
+    async fn sqlite_outbox_hands_out_each_write_until_it_is_done() -> Result<(), AnyError> {
+        let outbox = sqlite_outbox().await?;
+        let topic = Topic::default();
+        let id = outbox
+            .append(&WriteOperation::CreateTopic(topic.clone()))
+            .await?;
+
+        let claimed = outbox.claim(10).await?;
+        let claimed_while_busy = outbox.claim(10).await?;
+        outbox.release(id, 0).await?;
+        let claimed_after_release = outbox.claim(10).await?;
+        outbox.mark_done(id).await?;
+        outbox.release(id, 0).await?;
+        let claimed_when_done = outbox.claim(10).await?;
+
+        assert!(matches!(
+            claimed.first(),
+            Some(OutboxEntry { id: claimed_id, operation: WriteOperation::CreateTopic(claimed_topic), .. })
+                if *claimed_id == id && claimed_topic.id == topic.id
+        ));
+        assert!(claimed_while_busy.is_empty());
+        assert_eq!(claimed_after_release.len(), 1);
+        assert!(claimed_when_done.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_outbox_holds_back_writes_about_a_petty_matter_in_hand() -> Result<(), AnyError>
+    {
+        let outbox = sqlite_outbox().await?;
+        let topic = Topic::default();
+        let first = outbox
+            .append(&WriteOperation::CreateTopic(topic.clone()))
+            .await?;
+        let second = outbox
+            .append(&WriteOperation::UpdateTopic(topic.clone()))
+            .await?;
+        let unrelated = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+        let ids = |entries: Vec<OutboxEntry>| -> Vec<OutboxEntryId> {
+            entries.into_iter().map(|entry| entry.id).collect()
+        };
+
+        let claimed_first = ids(outbox.claim(1).await?);
+        let claimed_meanwhile = ids(outbox.claim(10).await?);
+        outbox.mark_done(first).await?;
+        let claimed_once_done = ids(outbox.claim(10).await?);
+
+        assert_eq!(claimed_first, vec![first]);
+        assert_eq!(claimed_meanwhile, vec![unrelated]);
+        assert_eq!(claimed_once_done, vec![second]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_outbox_counts_attempts_across_claims() -> Result<(), AnyError> {
+        let outbox = sqlite_outbox().await?;
+        let id = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+
+        let first_claim = outbox.claim(10).await?;
+        outbox.release(id, 3).await?;
+        outbox.claim(10).await?;
+        outbox.release(id, 2).await?;
+        let last_claim = outbox.claim(10).await?;
+
+        let attempts =
+            |entries: Vec<OutboxEntry>| entries.iter().map(|e| e.attempts).collect::<Vec<_>>();
+        assert_eq!(attempts(first_claim), vec![0]);
+        assert_eq!(attempts(last_claim), vec![5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_outbox_sets_aside_writes_given_up_on() -> Result<(), AnyError> {
+        let outbox = sqlite_outbox().await?;
+        let id = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+        let error = QueueError::InvalidInput("Not a petty matter".to_string());
+
+        outbox.dead_letter(id, &error, 1).await?;
+
+        let dead_letters = outbox.dead_letters(10).await?;
+        assert!(outbox.claim(10).await?.is_empty());
+        assert_eq!(
+            dead_letters
+                .iter()
+                .map(|dead_letter| (dead_letter.id, dead_letter.error.clone()))
+                .collect::<Vec<_>>(),
+            vec![(id, error.to_string())]
+        );
+
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
+use crate::config::APP_CONFIG;
 use crate::persistence::repository::Repository;
+use crate::persistence::unit_of_work::UnitOfWork;
 use crate::petty_matters::comment::{Comment, CommentId};
 use crate::petty_matters::topic::{Topic, TopicId};
-use crate::queue::base::{Queue, QueueError, WriteOperation};
+use crate::queue::base::{Completion, Queue, QueueError, WriteOperation};
+use crate::queue::events::EventBus;
+use crate::queue::idempotency::{IdempotencyKeys, InMemoryIdempotencyKeys};
+use crate::queue::worker::apply_write_operation;
 use async_trait::async_trait;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct StubQueue {
     pub topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
     pub comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+    pub idempotency_keys: Arc<dyn IdempotencyKeys>,
+    pub events: EventBus,
+    pub unit_of_work: UnitOfWork,
 }
 
 #[allow(dead_code)]
@@ -20,24 +29,28 @@ impl StubQueue {
         Self {
             topic_repository,
             comment_repository,
+            idempotency_keys: Arc::new(InMemoryIdempotencyKeys::new(Duration::from_secs(
+                u64::from(APP_CONFIG.idempotency_window.0),
+            ))),
+            events: EventBus::default(),
+            unit_of_work: UnitOfWork::default(),
         }
     }
 }
 
 #[async_trait]
 impl Queue for StubQueue {
-    async fn enqueue(&self, op: WriteOperation) -> Result<(), QueueError> {
-        match op {
-            WriteOperation::CreateTopic(topic) => self
-                .topic_repository
-                .create(topic)
-                .await
-                .map_err(|e| QueueError::OperationFailed(e.to_string())),
-            WriteOperation::AddComment(comment) => self
-                .comment_repository
-                .create(comment)
-                .await
-                .map_err(|e| QueueError::OperationFailed(e.to_string())),
-        }
+    async fn enqueue(&self, op: WriteOperation) -> Result<Completion, QueueError> {
+        apply_write_operation(
+            op,
+            &self.topic_repository,
+            &self.comment_repository,
+            &self.idempotency_keys,
+            &self.events,
+            &self.unit_of_work,
+        )
+        .await?;
+
+        Ok(Completion::applied())
     }
 }
@@ -1,31 +1,1046 @@
-use crate::persistence::repository::Repository;
+use crate::config::APP_CONFIG;
+use crate::feature_flags::FEATURE_FLAGS;
+use crate::persistence::filter::Filter;
+use crate::persistence::replication::reading_from_primary;
+use crate::persistence::repository::{ListParameters, Repository, SoftDeletable};
+use crate::persistence::unit_of_work::{Transaction, UnitOfWork};
 use crate::petty_matters::comment::{Comment, CommentId};
 use crate::petty_matters::topic::{Topic, TopicId};
 use crate::queue::base::{QueueError, WriteOperation};
+use crate::queue::events::{DomainEvent, EventBus};
+use crate::queue::idempotency::IdempotencyKeys;
+use crate::queue::outbox::{Outbox, OutboxEntry, OutboxEntryId, OutboxQueue};
+use crate::time::Backoff;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, sleep, timeout};
 
-pub async fn start_write_worker(
-    mut receiver: Receiver<WriteOperation>,
+/// How often the outbox is checked when nothing has announced a write,
+/// e.g. for writes left by another instance or a previous run
+pub static OUTBOX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+static OUTBOX_BATCH_SIZE: usize = 50;
+static DEAD_LETTERS_REPORTED: usize = 10;
+static RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+static RETRY_MAX_BACKOFF: Duration = Duration::from_secs(30);
+static RESTART_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+static RESTART_MAX_BACKOFF: Duration = Duration::from_mins(1);
+
+/// Keeps a background task running, starting it over with backoff whenever it panics.
+/// A task that returns is done, and is left at that.
+pub async fn supervise<Task, Run>(name: &str, start: Task)
+where
+    Task: Fn() -> Run,
+    Run: Future<Output = ()> + Send + 'static,
+{
+    let mut backoff = Backoff::new(RESTART_INITIAL_BACKOFF, RESTART_MAX_BACKOFF);
+    loop {
+        let started_at = Instant::now();
+        match tokio::spawn(start()).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("The {name} crashed: {e}, restarting it"),
+        }
+        // Only a task that keeps falling over right away is given more and more time to recover
+        if started_at.elapsed() > RESTART_MAX_BACKOFF {
+            backoff.reset();
+        }
+        sleep(backoff.next()).await;
+    }
+}
+
+/// What the workers write with
+#[derive(Clone)]
+struct Writer {
     topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
     comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
-) -> Result<(), QueueError> {
-    while let Some(op) = receiver.recv().await {
-        match op {
-            WriteOperation::CreateTopic(topic) => {
-                topic_repository
-                    .create(topic)
-                    .await
-                    .map_err(|e| QueueError::OperationFailed(e.to_string()))?;
+    idempotency_keys: Arc<dyn IdempotencyKeys>,
+    events: EventBus,
+    unit_of_work: UnitOfWork,
+}
+
+/// Applies the writes waiting in the outbox with a pool of `WRITE_WORKERS` workers,
+/// each taking the writes about its share of the petty matters in the order they came in.
+/// Each write is only removed once it has been applied or set aside as a dead letter,
+/// and whoever waits on it is let know how it went.
+/// Once the queue is closing, it returns as soon as the outbox has been emptied.
+pub async fn start_outbox_worker(
+    queue: OutboxQueue,
+    topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
+    comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+    idempotency_keys: Arc<dyn IdempotencyKeys>,
+    events: EventBus,
+    unit_of_work: UnitOfWork,
+) {
+    let writer = Writer {
+        topic_repository,
+        comment_repository,
+        idempotency_keys,
+        events,
+        unit_of_work,
+    };
+    let (outbox, wake_up) = (queue.outbox(), queue.wake_up());
+    report_dead_letters(outbox.as_ref()).await;
+    loop {
+        if let Err(e) = queue.refresh_depth().await {
+            eprintln!("Could not count the writes waiting: {e}");
+        }
+        let entries = match outbox.claim(OUTBOX_BATCH_SIZE).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Could not read the outbox: {e}");
+                sleep(OUTBOX_POLL_INTERVAL).await;
+                continue;
             }
-            WriteOperation::AddComment(comment) => {
-                comment_repository
-                    .create(comment)
+        };
+        if entries.is_empty() {
+            if queue.is_closing() {
+                queue.mark_drained();
+                return;
+            }
+            let _ = timeout(OUTBOX_POLL_INTERVAL, wake_up.notified()).await;
+            continue;
+        }
+
+        for round in into_rounds(entries, APP_CONFIG.write_workers) {
+            let mut workers = JoinSet::new();
+            for shard in round {
+                workers.spawn(apply_in_order(queue.clone(), shard, writer.clone()));
+            }
+            while let Some(finished) = workers.join_next().await {
+                // Its writes stay claimed until the lease runs out, then they're taken up again
+                if let Err(e) = finished {
+                    eprintln!("A write worker crashed: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// Splits the writes into rounds applied one after the other, each made of shards applied
+/// side by side. Writes about the same petty matter end up in the same shard, in the order
+/// they came in, while those about no petty matter in particular get a round of their own.
+fn into_rounds(entries: Vec<OutboxEntry>, workers: usize) -> Vec<Vec<Vec<OutboxEntry>>> {
+    let workers = workers.max(1);
+    let new_round = || {
+        (0..workers)
+            .map(|_| vec![])
+            .collect::<Vec<Vec<OutboxEntry>>>()
+    };
+    let mut rounds = vec![];
+    let mut shards = new_round();
+    for entry in entries {
+        if let Some(topic_id) = entry.operation.topic_id() {
+            let mut hasher = DefaultHasher::new();
+            topic_id.hash(&mut hasher);
+            // Narrower targets only keep the low bits, which spread petty matters out just as well
+            #[allow(clippy::cast_possible_truncation)]
+            let hash = hasher.finish() as usize;
+            let shard = hash % workers;
+            if let Some(shard) = shards.get_mut(shard) {
+                shard.push(entry);
+            }
+        } else {
+            rounds.push(std::mem::replace(&mut shards, new_round()));
+            rounds.push(vec![vec![entry]]);
+        }
+    }
+    rounds.push(shards);
+
+    rounds
+        .into_iter()
+        .map(|round| {
+            round
+                .into_iter()
+                .filter(|shard| !shard.is_empty())
+                .collect()
+        })
+        .filter(|round: &Vec<Vec<OutboxEntry>>| !round.is_empty())
+        .collect()
+}
+
+/// Applies a shard's writes one after the other. Should one keep failing for a passing reason,
+/// or the outbox fail to take note of it, it and the rest are released,
+/// to be tried again in the same order. One tried too often in all is given up on instead.
+async fn apply_in_order(queue: OutboxQueue, entries: Vec<OutboxEntry>, writer: Writer) {
+    let outbox = queue.outbox();
+    let mut entries = entries.into_iter().peekable();
+    while let Some(entry) = entries.next() {
+        let mut group = vec![];
+        if FEATURE_FLAGS.is_comment_batching_enabled
+            && let Some(comment) = comment_added(&entry.operation)
+        {
+            let topic_id = comment.topic_id;
+            group.push(entry);
+            while let Some(next) = entries.next_if(|next| {
+                comment_added(&next.operation).is_some_and(|c| c.topic_id == topic_id)
+            }) {
+                group.push(next);
+            }
+        } else {
+            group.push(entry);
+        }
+
+        let attempted_before: HashMap<OutboxEntryId, u32> = group
+            .iter()
+            .map(|entry| (entry.id, entry.attempts))
+            .collect();
+        let mut outcomes = apply_group(group, &writer).await.into_iter();
+        while let Some((id, outcome)) = outcomes.next() {
+            let settled = match outcome {
+                Ok(()) => outbox
+                    .mark_done(id)
                     .await
-                    .map_err(|e| QueueError::OperationFailed(e.to_string()))?;
+                    .map(|()| Ok(()))
+                    .map_err(|e| (e, 0)),
+                // Someone else's edit got there first, which is for the author to sort out
+                Err((e @ QueueError::Conflict(_), _)) => {
+                    eprintln!("Dropping write {id}, it conflicts with another: {e}");
+                    outbox
+                        .mark_done(id)
+                        .await
+                        .map(|()| Err(e))
+                        .map_err(|e| (e, 0))
+                }
+                Err((e, attempts)) => {
+                    let total_attempts = attempted_before
+                        .get(&id)
+                        .map_or(attempts, |before| before.saturating_add(attempts));
+                    // Whatever stands in its way may well clear up, so it waits in the outbox
+                    // for that, though only for so long
+                    if e.is_transient() && total_attempts < APP_CONFIG.write_max_total_attempts {
+                        Err((e, attempts))
+                    } else {
+                        eprintln!("Setting write {id} aside after {total_attempts} attempts: {e}");
+                        outbox
+                            .dead_letter(id, &e, total_attempts)
+                            .await
+                            .map(|()| Err(e))
+                            .map_err(|e| (e, attempts))
+                    }
+                }
+            };
+            let outcome = match settled {
+                Ok(outcome) => outcome,
+                Err((e, attempts)) => {
+                    eprintln!("Write {id} will be tried again: {e}");
+                    // Later writes may depend on this one, so they wait their turn too
+                    let remaining = std::iter::once((id, attempts))
+                        .chain(outcomes.map(|(id, _)| (id, 0)))
+                        .chain(entries.map(|entry| (entry.id, 0)));
+                    for (id, attempts) in remaining {
+                        if let Err(e) = outbox.release(id, attempts).await {
+                            eprintln!("Could not release write {id}: {e}");
+                        }
+                    }
+                    sleep(OUTBOX_POLL_INTERVAL).await;
+                    return;
+                }
+            };
+            queue.complete(id, outcome).await;
+        }
+    }
+}
+
+/// The comment the write adds, keyed or not
+fn comment_added(operation: &WriteOperation) -> Option<&Comment> {
+    match operation {
+        WriteOperation::AddComment(comment) => Some(comment),
+        WriteOperation::Idempotent(_, operation) => comment_added(operation),
+        _ => None,
+    }
+}
+
+/// Applies comments on the same petty matter together, in a single insert, falling back
+/// to one at a time if that fails, so a single comment can't hold up the others
+async fn apply_group(
+    group: Vec<OutboxEntry>,
+    writer: &Writer,
+) -> Vec<(OutboxEntryId, Result<(), (QueueError, u32)>)> {
+    if group.len() > 1 {
+        let ids: Vec<OutboxEntryId> = group.iter().map(|entry| entry.id).collect();
+        let together =
+            WriteOperation::Batch(group.iter().map(|entry| entry.operation.clone()).collect());
+        match apply_with_retries(together, 1, writer).await {
+            Ok(()) => return ids.into_iter().map(|id| (id, Ok(()))).collect(),
+            Err((e, _)) => {
+                eprintln!(
+                    "Could not add {} comments at once, adding them one by one: {e}",
+                    ids.len()
+                );
             }
         }
     }
 
+    let mut outcomes = vec![];
+    for OutboxEntry { id, operation, .. } in group {
+        let outcome = apply_with_retries(operation, APP_CONFIG.write_max_attempts, writer).await;
+        outcomes.push((id, outcome));
+    }
+
+    outcomes
+}
+
+/// Reminds whoever reads the logs of the writes set aside so far, as nothing else will
+async fn report_dead_letters(outbox: &dyn Outbox) {
+    match outbox.dead_letters(DEAD_LETTERS_REPORTED).await {
+        Ok(dead_letters) => {
+            for dead_letter in dead_letters {
+                eprintln!(
+                    "Write {} was set aside on {} after {} attempts: {}",
+                    dead_letter.id, dead_letter.failed_at, dead_letter.attempts, dead_letter.error
+                );
+            }
+        }
+        Err(e) => eprintln!("Could not read the dead letters: {e}"),
+    }
+}
+
+/// Tries a write again with backoff for as long as it fails for a passing reason,
+/// up to `max_attempts` at a time. Gives back the last error along with the number of attempts made.
+async fn apply_with_retries(
+    operation: WriteOperation,
+    max_attempts: u32,
+    writer: &Writer,
+) -> Result<(), (QueueError, u32)> {
+    let mut backoff = Backoff::new(RETRY_INITIAL_BACKOFF, RETRY_MAX_BACKOFF);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        // What gets written over is read first, which a lagging replica mustn't answer
+        let outcome = reading_from_primary(apply_write_operation(
+            operation.clone(),
+            &writer.topic_repository,
+            &writer.comment_repository,
+            &writer.idempotency_keys,
+            &writer.events,
+            &writer.unit_of_work,
+        ))
+        .await;
+        match outcome {
+            Err(e) if e.is_transient() && attempts < max_attempts => {
+                eprintln!("Write failed on attempt {attempts}, retrying: {e}");
+                sleep(backoff.next()).await;
+            }
+            outcome => return outcome.map_err(|e| (e, attempts)),
+        }
+    }
+}
+
+pub async fn apply_write_operation(
+    op: WriteOperation,
+    topic_repository: &Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
+    comment_repository: &Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+    idempotency_keys: &Arc<dyn IdempotencyKeys>,
+    events: &EventBus,
+    unit_of_work: &UnitOfWork,
+) -> Result<(), QueueError> {
+    let operations = match op {
+        WriteOperation::Batch(operations) => operations,
+        // A comment also bumps its petty matter's activity, and the two have to go through together.
+        // Edits go through a transaction as well, holding the petty matter while its activity
+        // is carried over, so a comment added meanwhile isn't counted out.
+        op @ (WriteOperation::AddComment(_)
+        | WriteOperation::UpdateTopic(_)
+        | WriteOperation::Idempotent(..)) => vec![op],
+        op => {
+            let applied = apply(op, topic_repository, comment_repository).await?;
+            events.publish(applied).await;
+            return Ok(());
+        }
+    };
+
+    let applied = apply_together(
+        operations,
+        topic_repository,
+        comment_repository,
+        idempotency_keys.as_ref(),
+        unit_of_work,
+    )
+    .await?;
+    events.publish(applied).await;
+
     Ok(())
 }
+
+/// Unwraps the keyed writes, leaving out those already applied.
+/// The keys of the others are taken note of within the transaction applying them.
+async fn skip_repeats(
+    operations: Vec<WriteOperation>,
+    idempotency_keys: &dyn IdempotencyKeys,
+    transaction: &Transaction,
+) -> Result<Vec<WriteOperation>, QueueError> {
+    let mut fresh = vec![];
+    for op in operations {
+        let WriteOperation::Idempotent(key, op) = op else {
+            fresh.push(op);
+            continue;
+        };
+        if !idempotency_keys.remember(key, transaction).await? {
+            println!("Ignoring write {key}, it was already applied");
+            continue;
+        }
+        match *op {
+            WriteOperation::Batch(operations) => fresh.extend(operations),
+            op => fresh.push(op),
+        }
+    }
+
+    Ok(fresh)
+}
+
+/// Applies the writes in a single transaction, remembering the keys of keyed ones with them
+async fn apply_together(
+    operations: Vec<WriteOperation>,
+    topic_repository: &Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
+    comment_repository: &Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+    idempotency_keys: &dyn IdempotencyKeys,
+    unit_of_work: &UnitOfWork,
+) -> Result<Vec<DomainEvent>, QueueError> {
+    if operations.is_empty() {
+        return Ok(vec![]);
+    }
+    let transaction = unit_of_work.begin().await?;
+    let operations = match skip_repeats(operations, idempotency_keys, &transaction).await {
+        Ok(operations) => operations,
+        Err(e) => {
+            transaction.rollback().await?;
+            return Err(e);
+        }
+    };
+    let topic_repository = topic_repository.clone().within(&transaction);
+    let comment_repository = comment_repository.clone().within(&transaction);
+    let mut applied = vec![];
+    let mut operations = operations.into_iter().peekable();
+    while let Some(op) = operations.next() {
+        let outcome = match op {
+            // Consecutive comments on the same petty matter are inserted in one go
+            WriteOperation::AddComment(comment) => {
+                let topic_id = comment.topic_id;
+                let mut comments = vec![comment];
+                while let Some(WriteOperation::AddComment(comment)) = operations.next_if(
+                    |next| matches!(next, WriteOperation::AddComment(c) if c.topic_id == topic_id),
+                ) {
+                    comments.push(comment);
+                }
+                add_comments(comments, &topic_repository, &comment_repository).await
+            }
+            op => apply(op, &topic_repository, &comment_repository).await,
+        };
+        match outcome {
+            Ok(events) => applied.extend(events),
+            Err(e) => {
+                drop((topic_repository, comment_repository));
+                transaction.rollback().await?;
+                return Err(e);
+            }
+        }
+    }
+    drop((topic_repository, comment_repository));
+    transaction.commit().await?;
+
+    Ok(applied)
+}
+
+/// Applies a single write, telling what came of it. Creations found on file already
+/// were applied before the outbox could take note of it, and aren't told of again.
+async fn apply(
+    op: WriteOperation,
+    topic_repository: &Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
+    comment_repository: &Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+) -> Result<Vec<DomainEvent>, QueueError> {
+    let event = match op {
+        WriteOperation::CreateTopic(topic) => {
+            if topic_repository
+                .get_by_id_including_deleted(&topic.id)
+                .await
+                .map_err(QueueError::from)?
+                .is_some()
+            {
+                return Ok(vec![]);
+            }
+            topic_repository
+                .create(topic.clone())
+                .await
+                .map_err(QueueError::from)?;
+            DomainEvent::TopicCreated(topic)
+        }
+        WriteOperation::UpdateTopic(topic) => {
+            return update_topic(topic, topic_repository, comment_repository).await;
+        }
+        WriteOperation::AddComment(comment) => {
+            return add_comments(vec![comment], topic_repository, comment_repository).await;
+        }
+        WriteOperation::UpdateComment(comment) => {
+            let was_deleted = comment_repository
+                .get_by_id_including_deleted(&comment.id)
+                .await
+                .map_err(QueueError::from)?
+                .is_some_and(|stored| stored.is_deleted());
+            comment_repository
+                .update(comment.clone())
+                .await
+                .map_err(QueueError::from)?;
+            match (was_deleted, comment.is_deleted()) {
+                (false, true) => DomainEvent::CommentDeleted(comment),
+                (true, false) => DomainEvent::CommentRestored(comment),
+                _ => DomainEvent::CommentUpdated(comment),
+            }
+        }
+        WriteOperation::PurgeDeleted(deleted_before) => {
+            let purged_comments = comment_repository
+                .purge_deleted(deleted_before)
+                .await
+                .map_err(QueueError::from)?;
+            let purged_topics = topic_repository
+                .purge_deleted(deleted_before)
+                .await
+                .map_err(QueueError::from)?;
+            println!("Purged {purged_topics} petty matters and {purged_comments} comments");
+            DomainEvent::DeletedPurged {
+                deleted_before,
+                topics: purged_topics,
+                comments: purged_comments,
+            }
+        }
+        WriteOperation::Batch(_) | WriteOperation::Idempotent(..) => {
+            return Err(QueueError::InvalidInput(
+                "Batches and keyed writes cannot be nested".to_string(),
+            ));
+        }
+    };
+
+    Ok(vec![event])
+}
+
+/// Withdrawing or restoring a petty matter does the same to its comments, in the same go,
+/// so none filed in the meantime is left out. Comments withdrawn on their own stay withdrawn.
+async fn update_topic(
+    mut topic: Topic,
+    topic_repository: &Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
+    comment_repository: &Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+) -> Result<Vec<DomainEvent>, QueueError> {
+    let stored = topic_repository
+        .get_for_update(&topic.id)
+        .await
+        .map_err(QueueError::from)?;
+    let was_deleted = stored.as_ref().is_some_and(SoftDeletable::is_deleted);
+    // The activity may have moved on since the author read the petty matter
+    if let Some(stored) = stored {
+        topic.comment_count = stored.comment_count;
+        topic.last_activity_time = stored.last_activity_time;
+    }
+    topic_repository
+        .update(topic.clone())
+        .await
+        .map_err(QueueError::from)?;
+    let is_deleted = topic.is_deleted();
+    let mut events = vec![match (was_deleted, is_deleted) {
+        (false, true) => DomainEvent::TopicDeleted(topic.clone()),
+        (true, false) => DomainEvent::TopicRestored(topic.clone()),
+        _ => DomainEvent::TopicUpdated(topic.clone()),
+    }];
+    if was_deleted == is_deleted {
+        return Ok(events);
+    }
+    for mut comment in comments_on(&topic.id, comment_repository).await? {
+        if is_deleted && !comment.is_deleted() {
+            comment.mark_deleted_along_with(&topic);
+            events.push(DomainEvent::CommentDeleted(comment.clone()));
+        } else if !is_deleted && comment.withdrawn_with_topic {
+            comment.restore();
+            events.push(DomainEvent::CommentRestored(comment.clone()));
+        } else {
+            continue;
+        }
+        comment_repository
+            .update(comment)
+            .await
+            .map_err(QueueError::from)?;
+    }
+
+    Ok(events)
+}
+
+/// Every comment on the petty matter, withdrawn ones included
+async fn comments_on(
+    topic_id: &TopicId,
+    comment_repository: &Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+) -> Result<Vec<Comment>, QueueError> {
+    let mut list_parameters = ListParameters {
+        include_deleted: true,
+        ..ListParameters::default()
+    };
+    list_parameters.add_filter(Filter::Eq("topic_id".to_string(), topic_id.0.into()));
+    let mut comments = vec![];
+    loop {
+        let page = comment_repository
+            .list(list_parameters.clone())
+            .await
+            .map_err(QueueError::from)?;
+        comments.extend(page.items);
+        match page.next_cursor {
+            Some(cursor) => list_parameters.cursor = Some(cursor),
+            None => return Ok(comments),
+        }
+    }
+}
+
+/// Files comments on a single petty matter, counting each towards its activity.
+/// Comments on file already were counted along with them, so they're left out.
+/// The petty matter is held until the transaction is over, so no other count is lost to this one.
+async fn add_comments(
+    comments: Vec<Comment>,
+    topic_repository: &Arc<dyn Repository<TopicId, Topic> + Send + Sync>,
+    comment_repository: &Arc<dyn Repository<CommentId, Comment> + Send + Sync>,
+) -> Result<Vec<DomainEvent>, QueueError> {
+    let mut unfiled = Vec::with_capacity(comments.len());
+    for comment in comments {
+        if comment_repository
+            .get_by_id_including_deleted(&comment.id)
+            .await
+            .map_err(QueueError::from)?
+            .is_none()
+        {
+            unfiled.push(comment);
+        }
+    }
+    let comments = unfiled;
+    let Some(topic_id) = comments.first().map(|comment| comment.topic_id) else {
+        return Ok(vec![]);
+    };
+    let mut topic = topic_repository
+        .get_for_update(&topic_id)
+        .await
+        .map_err(QueueError::from)?
+        .filter(|topic| !topic.is_deleted())
+        .ok_or_else(|| {
+            QueueError::InvalidInput(
+                "Cannot comment on a petty matter that is not on file".to_string(),
+            )
+        })?;
+    for comment in &comments {
+        topic.record_comment(comment.creation_time);
+    }
+    comment_repository
+        .create_many(comments.clone())
+        .await
+        .map_err(QueueError::from)?;
+    topic_repository
+        .overwrite(topic)
+        .await
+        .map_err(QueueError::from)?;
+
+    Ok(comments
+        .into_iter()
+        .map(DomainEvent::CommentAdded)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authn::session::User;
+    use crate::error::AnyError;
+    use crate::persistence::in_memory_repository::InMemoryRepository;
+    use crate::persistence::rdbms::RdbmsRepository;
+    use crate::petty_matters::topic_repository::Entity as TopicDbModel;
+    use crate::queue::base::{Queue, WriteStatus};
+    use crate::queue::events::EventCounts;
+    use crate::queue::idempotency::InMemoryIdempotencyKeys;
+    use crate::queue::journal_outbox::JournalOutbox;
+    use crate::queue::outbox::DeadLetter;
+    use crate::testing::migrated_sqlite;
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn writes_that_cannot_succeed_are_set_aside_without_holding_up_the_rest()
+    -> Result<(), AnyError> {
+        let outbox: Arc<dyn Outbox> = Arc::new(JournalOutbox::in_memory());
+        let topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let topic = Topic::default();
+        let orphan = Comment::new(
+            Topic::default().id,
+            "Nobody's listening".to_string(),
+            User::anonymous(),
+        );
+        outbox.append(&WriteOperation::AddComment(orphan)).await?;
+        outbox
+            .append(&WriteOperation::CreateTopic(topic.clone()))
+            .await?;
+
+        let worker = tokio::spawn(start_outbox_worker(
+            OutboxQueue::new(outbox.clone()),
+            topic_repository.clone(),
+            Arc::new(InMemoryRepository::new()),
+            Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1))),
+            EventBus::default(),
+            UnitOfWork::default(),
+        ));
+        let applied = timeout(Duration::from_secs(5), async {
+            while !matches!(topic_repository.get_by_id(&topic.id).await, Ok(Some(_))) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        worker.abort();
+
+        let dead_letters = outbox.dead_letters(10).await?;
+        assert!(applied.is_ok());
+        assert_eq!(dead_letters.len(), 1);
+        assert!(matches!(
+            dead_letters.first(),
+            Some(DeadLetter {
+                operation: WriteOperation::AddComment(_),
+                attempts: 1,
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_failing_for_a_passing_reason_go_back_to_the_outbox() -> Result<(), AnyError> {
+        let outbox: Arc<dyn Outbox> = Arc::new(JournalOutbox::in_memory());
+        let db = migrated_sqlite().await?;
+        let writer = Writer {
+            topic_repository: Arc::new(RdbmsRepository::<TopicDbModel>::new(db.clone())),
+            comment_repository: Arc::new(InMemoryRepository::new()),
+            idempotency_keys: Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1))),
+            events: EventBus::default(),
+            unit_of_work: UnitOfWork::default(),
+        };
+        db.close().await?;
+        outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+
+        let claimed = outbox.claim(10).await?;
+        apply_in_order(OutboxQueue::new(outbox.clone()), claimed, writer).await;
+
+        assert!(outbox.dead_letters(10).await?.is_empty());
+        assert_eq!(outbox.claim(10).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_failing_for_a_passing_reason_for_too_long_are_set_aside() -> Result<(), AnyError>
+    {
+        let outbox: Arc<dyn Outbox> = Arc::new(JournalOutbox::in_memory());
+        let db = migrated_sqlite().await?;
+        let writer = Writer {
+            topic_repository: Arc::new(RdbmsRepository::<TopicDbModel>::new(db.clone())),
+            comment_repository: Arc::new(InMemoryRepository::new()),
+            idempotency_keys: Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1))),
+            events: EventBus::default(),
+            unit_of_work: UnitOfWork::default(),
+        };
+        db.close().await?;
+        let id = outbox
+            .append(&WriteOperation::CreateTopic(Topic::default()))
+            .await?;
+        outbox.claim(10).await?;
+        outbox
+            .release(id, APP_CONFIG.write_max_total_attempts - 1)
+            .await?;
+
+        let claimed = outbox.claim(10).await?;
+        apply_in_order(OutboxQueue::new(outbox.clone()), claimed, writer).await;
+
+        assert!(outbox.claim(10).await?.is_empty());
+        assert!(matches!(
+            outbox.dead_letters(10).await?.first(),
+            Some(DeadLetter { attempts, .. }) if *attempts >= APP_CONFIG.write_max_total_attempts
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authors_waiting_on_their_writes_learn_how_they_went() -> Result<(), AnyError> {
+        let queue = OutboxQueue::new(Arc::new(JournalOutbox::in_memory()));
+        let worker = tokio::spawn(start_outbox_worker(
+            queue.clone(),
+            Arc::new(InMemoryRepository::new()),
+            Arc::new(InMemoryRepository::new()),
+            Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1))),
+            EventBus::default(),
+            UnitOfWork::default(),
+        ));
+        let orphan = Comment::new(
+            Topic::default().id,
+            "Nobody's listening".to_string(),
+            User::anonymous(),
+        );
+
+        let created = queue
+            .enqueue(WriteOperation::CreateTopic(Topic::default()))
+            .await?
+            .wait(Duration::from_secs(5))
+            .await;
+        let commented = queue
+            .enqueue(WriteOperation::AddComment(orphan))
+            .await?
+            .wait(Duration::from_secs(5))
+            .await;
+        worker.abort();
+
+        assert_eq!(created, Ok(WriteStatus::Applied));
+        assert!(matches!(commented, Err(QueueError::InvalidInput(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn conflicting_edits_are_reported_without_holding_up_the_rest() -> Result<(), AnyError> {
+        let outbox: Arc<dyn Outbox> = Arc::new(JournalOutbox::in_memory());
+        let queue = OutboxQueue::new(outbox.clone());
+        let topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let topic = Topic::default();
+        topic_repository.create(topic.clone()).await?;
+        topic_repository.update(topic.clone()).await?;
+        let worker = tokio::spawn(start_outbox_worker(
+            queue.clone(),
+            topic_repository.clone(),
+            Arc::new(InMemoryRepository::new()),
+            Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1))),
+            EventBus::default(),
+            UnitOfWork::default(),
+        ));
+
+        let stale_edit = queue
+            .enqueue(WriteOperation::UpdateTopic(topic))
+            .await?
+            .wait(Duration::from_secs(5))
+            .await;
+        let created = queue
+            .enqueue(WriteOperation::CreateTopic(Topic::default()))
+            .await?
+            .wait(Duration::from_secs(5))
+            .await;
+        worker.abort();
+
+        assert!(matches!(stale_edit, Err(QueueError::Conflict(_))));
+        assert_eq!(created, Ok(WriteStatus::Applied));
+        assert!(outbox.dead_letters(10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_replayed_after_being_applied_are_not_done_again() -> Result<(), AnyError> {
+        let topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let idempotency_keys: Arc<dyn IdempotencyKeys> =
+            Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1)));
+        let topic = Topic::default();
+        let comment = Comment::new(topic.id, "Twice?".to_string(), User::anonymous());
+        let writes = [
+            WriteOperation::CreateTopic(topic.clone()),
+            WriteOperation::AddComment(comment),
+        ];
+        let apply_all = || async {
+            for write in writes.clone() {
+                apply_write_operation(
+                    write,
+                    &topic_repository,
+                    &comment_repository,
+                    &idempotency_keys,
+                    &EventBus::default(),
+                    &UnitOfWork::default(),
+                )
+                .await?;
+            }
+            Ok::<_, QueueError>(())
+        };
+
+        apply_all().await?;
+        let replayed = apply_all().await;
+
+        assert_eq!(replayed, Ok(()));
+        assert!(
+            topic_repository
+                .get_by_id(&topic.id)
+                .await?
+                .is_some_and(|topic| topic.comment_count == 1)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closing_the_queue_applies_what_was_taken_and_turns_away_the_rest()
+    -> Result<(), AnyError> {
+        let queue = OutboxQueue::new(Arc::new(JournalOutbox::in_memory()));
+        let topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let topic = Topic::default();
+        let _ = queue
+            .enqueue(WriteOperation::CreateTopic(topic.clone()))
+            .await?;
+
+        let worker = tokio::spawn(start_outbox_worker(
+            queue.clone(),
+            topic_repository.clone(),
+            Arc::new(InMemoryRepository::new()),
+            Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1))),
+            EventBus::default(),
+            UnitOfWork::default(),
+        ));
+        let drained = queue.drain(Duration::from_secs(5)).await;
+        let turned_away = queue
+            .enqueue(WriteOperation::CreateTopic(Topic::default()))
+            .await;
+
+        assert!(drained);
+        assert!(timeout(Duration::from_secs(5), worker).await.is_ok());
+        assert!(matches!(
+            topic_repository.get_by_id(&topic.id).await,
+            Ok(Some(_))
+        ));
+        assert!(matches!(turned_away, Err(QueueError::Unavailable(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn writes_about_the_same_petty_matter_stay_in_order_in_one_shard() {
+        let topic = Topic::default();
+        let comment = |content: &str| {
+            WriteOperation::AddComment(Comment::new(
+                topic.id,
+                content.to_string(),
+                User::anonymous(),
+            ))
+        };
+        let entries = vec![
+            WriteOperation::CreateTopic(topic.clone()),
+            WriteOperation::CreateTopic(Topic::default()),
+            comment("First"),
+            WriteOperation::PurgeDeleted(Utc::now()),
+            comment("Second"),
+        ]
+        .into_iter()
+        .zip(1..)
+        .map(|(operation, id)| OutboxEntry {
+            id: OutboxEntryId(id),
+            operation,
+            attempts: 0,
+        })
+        .collect();
+
+        let rounds = into_rounds(entries, 4);
+
+        let ids_about_topic = rounds
+            .iter()
+            .map(|round| {
+                round
+                    .iter()
+                    .flatten()
+                    .filter(|entry| entry.operation.topic_id() == Some(topic.id))
+                    .map(|entry| entry.id.0)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rounds.len(), 3);
+        assert_eq!(ids_about_topic, vec![vec![1, 3], vec![], vec![5]]);
+    }
+
+    #[tokio::test]
+    async fn comments_on_the_same_petty_matter_are_added_together() -> Result<(), AnyError> {
+        let topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let topic = Topic::default();
+        topic_repository.create(topic.clone()).await?;
+        let comments = ["First", "Second"]
+            .into_iter()
+            .map(|content| Comment::new(topic.id, content.to_string(), User::anonymous()))
+            .collect::<Vec<_>>();
+        let group = comments
+            .iter()
+            .zip(1..)
+            .map(|(comment, id)| OutboxEntry {
+                id: OutboxEntryId(id),
+                operation: WriteOperation::AddComment(comment.clone()),
+                attempts: 0,
+            })
+            .collect();
+        let writer = Writer {
+            topic_repository: topic_repository.clone(),
+            comment_repository: comment_repository.clone(),
+            idempotency_keys: Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1))),
+            events: EventBus::default(),
+            unit_of_work: UnitOfWork::default(),
+        };
+
+        let outcomes = apply_group(group, &writer).await;
+
+        assert!(outcomes.iter().all(|(_, outcome)| outcome.is_ok()));
+        for comment in &comments {
+            assert!(matches!(
+                comment_repository.get_by_id(&comment.id).await,
+                Ok(Some(_))
+            ));
+        }
+        assert!(matches!(
+            topic_repository.get_by_id(&topic.id).await,
+            Ok(Some(Topic {
+                comment_count: 2,
+                ..
+            }))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscribers_hear_of_writes_once_applied() {
+        let topic_repository: Arc<dyn Repository<TopicId, Topic> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let comment_repository: Arc<dyn Repository<CommentId, Comment> + Send + Sync> =
+            Arc::new(InMemoryRepository::new());
+        let idempotency_keys: Arc<dyn IdempotencyKeys> =
+            Arc::new(InMemoryIdempotencyKeys::new(Duration::from_mins(1)));
+        let events = EventBus::default();
+        let counts = Arc::new(EventCounts::default());
+        events.subscribe(counts.clone()).await;
+        let topic = Topic::default();
+        let orphan = Comment::new(
+            Topic::default().id,
+            "Nobody's listening".to_string(),
+            User::anonymous(),
+        );
+        let mut withdrawn = topic.clone();
+        withdrawn.deleted_at = Some(Utc::now());
+
+        for operation in [
+            WriteOperation::CreateTopic(topic.clone()),
+            WriteOperation::AddComment(orphan),
+            WriteOperation::UpdateTopic(withdrawn),
+        ] {
+            let _ = apply_write_operation(
+                operation,
+                &topic_repository,
+                &comment_repository,
+                &idempotency_keys,
+                &events,
+                &UnitOfWork::default(),
+            )
+            .await;
+        }
+
+        assert_eq!(
+            counts.counts(),
+            vec![("topic_created", 1), ("topic_deleted", 1)]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::error::AnyError;
+use crate::time::{Hours, Minutes, Seconds};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Timelike, Utc};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use tokio::sync::Mutex;
+
+/// When a job is due, in UTC
+#[allow(dead_code)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Schedule {
+    /// Every so often after the last run, the first as soon as the scheduler starts
+    Every(Seconds),
+    /// Like a crontab entry: at the given minute past every hour, or past the given hour only
+    At {
+        hour: Option<Hours>,
+        minute: Minutes,
+    },
+}
+
+impl Schedule {
+    pub fn every(interval: impl Into<Seconds>) -> Self {
+        Self::Every(interval.into())
+    }
+
+    #[allow(dead_code)]
+    pub const fn hourly(minute: Minutes) -> Self {
+        Self::At { hour: None, minute }
+    }
+
+    #[allow(dead_code)]
+    pub const fn daily(hour: Hours, minute: Minutes) -> Self {
+        Self::At {
+            hour: Some(hour),
+            minute,
+        }
+    }
+
+    /// When the job is next due after its last run, or after `since` if it never ran.
+    /// An hour or minute out of range is never due.
+    pub fn next_due(
+        &self,
+        last_run: Option<DateTime<Utc>>,
+        since: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Every(interval) => Some(last_run.map_or(since, |last_run| {
+                last_run + TimeDelta::seconds(i64::from(interval.0))
+            })),
+            Self::At { hour, minute } => {
+                let after = last_run.unwrap_or(since);
+                if minute.0 >= 60 || hour.as_ref().is_some_and(|hour| hour.0 >= 24) {
+                    return None;
+                }
+                let start_of_hour = after
+                    .with_nanosecond(0)?
+                    .with_second(0)?
+                    .with_minute(u32::from(minute.0))?;
+                // A day's worth of hours along, every hour has come round once
+                (0..=24)
+                    .map(|hours| start_of_hour + TimeDelta::hours(hours))
+                    .find(|due| {
+                        *due > after
+                            && hour
+                                .as_ref()
+                                .is_none_or(|hour| due.hour() == u32::from(hour.0))
+                    })
+            }
+        }
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Every(interval) => write!(f, "every {interval}"),
+            Self::At { hour, minute } => match hour {
+                Some(hour) => write!(f, "{} {} * * *", minute.0, hour.0),
+                None => write!(f, "{} * * * *", minute.0),
+            },
+        }
+    }
+}
+
+/// Periodic work carried out by the scheduler
+#[async_trait]
+pub trait Job: Send + Sync {
+    async fn run(&self) -> Result<(), AnyError>;
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JobOutcome {
+    Succeeded,
+    Failed(String),
+}
+
+impl Display for JobOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Succeeded => write!(f, "succeeded"),
+            Self::Failed(error) => write!(f, "failed: {error}"),
+        }
+    }
+}
+
+/// How a job last went, whichever instance ran it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JobRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub outcome: JobOutcome,
+}
+
+/// Where the last run of each job is kept, so it's only run again once due
+#[async_trait]
+pub trait JobRuns: Send + Sync {
+    async fn last_run(&self, job: &str) -> Result<Option<JobRun>, AnyError>;
+    async fn record(&self, job: &str, run: JobRun) -> Result<(), AnyError>;
+}
+
+/// Runs kept by this instance only, so every job is due again after a restart
+#[derive(Default)]
+pub struct InMemoryJobRuns {
+    runs: Mutex<HashMap<String, JobRun>>,
+}
+
+#[async_trait]
+impl JobRuns for InMemoryJobRuns {
+    async fn last_run(&self, job: &str) -> Result<Option<JobRun>, AnyError> {
+        Ok(self.runs.lock().await.get(job).cloned())
+    }
+
+    async fn record(&self, job: &str, run: JobRun) -> Result<(), AnyError> {
+        self.runs.lock().await.insert(job.to_string(), run);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::time::Days;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> Result<DateTime<Utc>, AnyError> {
+        Ok(Utc
+            .with_ymd_and_hms(2025, 8, 9, hour, minute, 0)
+            .single()
+            .ok_or("Invalid date")?)
+    }
+
+    #[test]
+    fn intervals_are_counted_from_the_last_run() -> Result<(), AnyError> {
+        let schedule = Schedule::every(Days(1));
+
+        assert_eq!(schedule.next_due(None, at(10, 0)?), Some(at(10, 0)?));
+        assert_eq!(
+            schedule.next_due(Some(at(9, 0)?), at(10, 0)?),
+            Some(at(9, 0)? + TimeDelta::days(1))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_times_come_round_after_the_last_run() -> Result<(), AnyError> {
+        let hourly = Schedule::hourly(Minutes(15));
+        let daily = Schedule::daily(Hours(3), Minutes(0));
+
+        assert_eq!(hourly.next_due(None, at(10, 0)?), Some(at(10, 15)?));
+        assert_eq!(
+            hourly.next_due(Some(at(10, 15)?), at(10, 0)?),
+            Some(at(11, 15)?)
+        );
+        assert_eq!(daily.next_due(Some(at(2, 0)?), at(0, 0)?), Some(at(3, 0)?));
+        assert_eq!(
+            daily.next_due(Some(at(3, 0)?), at(0, 0)?),
+            Some(at(3, 0)? + TimeDelta::days(1))
+        );
+        assert_eq!(
+            Schedule::hourly(Minutes(60)).next_due(None, at(0, 0)?),
+            None
+        );
+        assert_eq!(daily.to_string(), "0 3 * * *");
+
+        Ok(())
+    }
+}
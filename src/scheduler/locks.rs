@@ -0,0 +1,112 @@
+use crate::error::AnyError;
+use async_trait::async_trait;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, Statement, TransactionTrait, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Held for as long as a job runs, letting go of its lock when dropped
+pub struct JobGuard {
+    _held: Box<dyn Send>,
+}
+
+/// Keeps a job from running on more than one instance at a time
+#[async_trait]
+pub trait JobLocks: Send + Sync {
+    /// Takes the job's lock, unless it's already taken, in which case it returns `None`
+    async fn try_lock(&self, job: &str) -> Result<Option<JobGuard>, AnyError>;
+}
+
+/// For running a single instance: only keeps the same job from overlapping with itself
+#[derive(Default)]
+pub struct InProcessJobLocks {
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+struct Running {
+    job: String,
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.running.lock() {
+            running.remove(&self.job);
+        }
+    }
+}
+
+#[async_trait]
+impl JobLocks for InProcessJobLocks {
+    async fn try_lock(&self, job: &str) -> Result<Option<JobGuard>, AnyError> {
+        let is_free = self
+            .running
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(job.to_string());
+
+        Ok(is_free.then(|| JobGuard {
+            _held: Box::new(Running {
+                job: job.to_string(),
+                running: self.running.clone(),
+            }),
+        }))
+    }
+}
+
+/// Takes a Postgres advisory lock on the job's name, so only one instance runs it,
+/// however many there are. The lock lasts as long as a transaction kept open with it,
+/// and is let go of by the database should the instance holding it go down.
+pub struct AdvisoryJobLocks {
+    db: DatabaseConnection,
+}
+
+impl AdvisoryJobLocks {
+    pub const fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl JobLocks for AdvisoryJobLocks {
+    async fn try_lock(&self, job: &str) -> Result<Option<JobGuard>, AnyError> {
+        let transaction = self.db.begin().await?;
+        let is_locked = transaction
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT pg_try_advisory_xact_lock(hashtext($1)) AS is_locked",
+                [Value::from(job)],
+            ))
+            .await?
+            .map(|row| row.try_get::<bool>("", "is_locked"))
+            .transpose()?
+            .unwrap_or(false);
+
+        // Dropping the transaction rolls it back, which lets go of the lock
+        Ok(is_locked.then(|| JobGuard {
+            _held: Box::new(transaction),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+
+    #[tokio::test]
+    async fn a_job_is_only_locked_once_at_a_time() -> Result<(), AnyError> {
+        let locks = InProcessJobLocks::default();
+
+        let first = locks.try_lock("purge").await?;
+        let while_held = locks.try_lock("purge").await?;
+        let other_job = locks.try_lock("digest").await?;
+        drop(first);
+        let once_let_go = locks.try_lock("purge").await?;
+
+        assert!(while_held.is_none());
+        assert!(other_job.is_some());
+        assert!(once_let_go.is_some());
+
+        Ok(())
+    }
+}
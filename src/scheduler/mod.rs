@@ -0,0 +1,4 @@
+pub mod base;
+pub mod locks;
+pub mod rdbms_job_runs;
+pub mod runner;
@@ -0,0 +1,107 @@
+use crate::error::AnyError;
+use crate::scheduler::base::{JobOutcome, JobRun, JobRuns};
+use async_trait::async_trait;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{DatabaseConnection, EntityTrait, Set};
+
+mod job_run_record {
+    use chrono::Utc;
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "job_runs")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub job: String,
+        pub started_at: chrono::DateTime<Utc>,
+        pub finished_at: chrono::DateTime<Utc>,
+        /// Left empty when the run succeeded
+        pub error: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+use job_run_record::{ActiveModel, Column, Entity};
+
+/// Keeps the last run of each job in the `job_runs` table, shared by every instance
+pub struct RdbmsJobRuns {
+    db: DatabaseConnection,
+}
+
+impl RdbmsJobRuns {
+    pub const fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl JobRuns for RdbmsJobRuns {
+    async fn last_run(&self, job: &str) -> Result<Option<JobRun>, AnyError> {
+        let record = Entity::find_by_id(job.to_string()).one(&self.db).await?;
+
+        Ok(record.map(|record| JobRun {
+            started_at: record.started_at,
+            finished_at: record.finished_at,
+            outcome: record
+                .error
+                .map_or(JobOutcome::Succeeded, JobOutcome::Failed),
+        }))
+    }
+
+    async fn record(&self, job: &str, run: JobRun) -> Result<(), AnyError> {
+        let error = match run.outcome {
+            JobOutcome::Succeeded => None,
+            JobOutcome::Failed(error) => Some(error),
+        };
+        Entity::insert(ActiveModel {
+            job: Set(job.to_string()),
+            started_at: Set(run.started_at),
+            finished_at: Set(run.finished_at),
+            error: Set(error),
+        })
+        .on_conflict(
+            OnConflict::column(Column::Job)
+                .update_columns([Column::StartedAt, Column::FinishedAt, Column::Error])
+                .to_owned(),
+        )
+        .exec_without_returning(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::testing::migrated_sqlite;
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn sqlite_job_runs_keep_the_last_run_of_each_job() -> Result<(), AnyError> {
+        let db = migrated_sqlite().await?;
+        let runs = RdbmsJobRuns::new(db);
+        let failed = JobRun {
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            outcome: JobOutcome::Failed("Out to lunch".to_string()),
+        };
+        let succeeded = JobRun {
+            outcome: JobOutcome::Succeeded,
+            ..failed.clone()
+        };
+
+        runs.record("purge", failed).await?;
+        runs.record("purge", succeeded.clone()).await?;
+
+        assert_eq!(runs.last_run("purge").await?, Some(succeeded));
+        assert_eq!(runs.last_run("digest").await?, None);
+
+        Ok(())
+    }
+}
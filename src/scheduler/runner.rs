@@ -0,0 +1,201 @@
+use crate::error::AnyError;
+use crate::scheduler::base::{Job, JobOutcome, JobRun, JobRuns, Schedule};
+use crate::scheduler::locks::JobLocks;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// How often a job waiting its turn checks whether another instance has run it meanwhile
+static CHECK_INTERVAL: Duration = Duration::from_mins(1);
+
+struct ScheduledJob {
+    name: &'static str,
+    schedule: Schedule,
+    job: Arc<dyn Job>,
+}
+
+/// Runs jobs on their schedules inside the server process. When there are several instances,
+/// their last runs are shared and a lock on each job keeps any two from running it at once.
+pub struct Scheduler {
+    jobs: Vec<ScheduledJob>,
+    runs: Arc<dyn JobRuns>,
+    locks: Arc<dyn JobLocks>,
+}
+
+impl Scheduler {
+    pub fn new(runs: Arc<dyn JobRuns>, locks: Arc<dyn JobLocks>) -> Self {
+        Self {
+            jobs: vec![],
+            runs,
+            locks,
+        }
+    }
+
+    /// The name identifies the job across instances and restarts, so it must stay the same
+    pub fn with_job(mut self, name: &'static str, schedule: Schedule, job: Arc<dyn Job>) -> Self {
+        self.jobs.push(ScheduledJob {
+            name,
+            schedule,
+            job,
+        });
+        self
+    }
+
+    /// Each job is kept to its schedule in a task of its own, so a slow one doesn't hold up the rest
+    pub fn start(self) {
+        for job in self.jobs {
+            tokio::spawn(keep_to_schedule(job, self.runs.clone(), self.locks.clone()));
+        }
+    }
+}
+
+async fn keep_to_schedule(job: ScheduledJob, runs: Arc<dyn JobRuns>, locks: Arc<dyn JobLocks>) {
+    println!("Scheduling {} {}", job.name, job.schedule);
+    let since = Utc::now();
+    // Kept in case recording the run fails, so the job isn't run again straight away
+    let mut last_started = None;
+    loop {
+        let due = match next_due(&job, runs.as_ref(), since, last_started).await {
+            Ok(Some(due)) => due,
+            Ok(None) => {
+                eprintln!(
+                    "{} is never due {}, giving up on it",
+                    job.name, job.schedule
+                );
+                return;
+            }
+            Err(e) => {
+                eprintln!("Could not tell when {} last ran: {e}", job.name);
+                sleep(CHECK_INTERVAL).await;
+                continue;
+            }
+        };
+        if let Ok(wait) = (due - Utc::now()).to_std() {
+            sleep(wait.min(CHECK_INTERVAL)).await;
+            continue;
+        }
+
+        let guard = match locks.try_lock(job.name).await {
+            Ok(Some(guard)) => guard,
+            Ok(None) => {
+                sleep(CHECK_INTERVAL).await;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Could not lock {}: {e}", job.name);
+                sleep(CHECK_INTERVAL).await;
+                continue;
+            }
+        };
+        // Another instance may have run it while this one was waiting for the lock
+        match next_due(&job, runs.as_ref(), since, last_started).await {
+            Ok(Some(due)) if due <= Utc::now() => {
+                last_started = Some(run(&job, runs.as_ref()).await);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Could not tell when {} last ran: {e}", job.name);
+                sleep(CHECK_INTERVAL).await;
+            }
+        }
+        drop(guard);
+    }
+}
+
+async fn next_due(
+    job: &ScheduledJob,
+    runs: &dyn JobRuns,
+    since: DateTime<Utc>,
+    last_started: Option<DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, AnyError> {
+    let last_run = runs
+        .last_run(job.name)
+        .await?
+        .map(|run| run.started_at)
+        .max(last_started);
+
+    Ok(job.schedule.next_due(last_run, since))
+}
+
+/// Runs the job and records how it went, returning when it started
+async fn run(job: &ScheduledJob, runs: &dyn JobRuns) -> DateTime<Utc> {
+    let started_at = Utc::now();
+    let task = job.job.clone();
+    // A job that crashes is recorded as failed, rather than taking its schedule down with it
+    let outcome = match tokio::spawn(async move { task.run().await }).await {
+        Ok(Ok(())) => JobOutcome::Succeeded,
+        Ok(Err(e)) => JobOutcome::Failed(e.to_string()),
+        Err(e) => JobOutcome::Failed(format!("Crashed: {e}")),
+    };
+    match &outcome {
+        JobOutcome::Succeeded => println!("{} {outcome}", job.name),
+        JobOutcome::Failed(_) => eprintln!("{} {outcome}", job.name),
+    }
+    let run = JobRun {
+        started_at,
+        finished_at: Utc::now(),
+        outcome,
+    };
+    if let Err(e) = runs.record(job.name, run).await {
+        eprintln!("Could not record how {} went: {e}", job.name);
+    }
+
+    started_at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use crate::scheduler::base::InMemoryJobRuns;
+    use crate::scheduler::locks::InProcessJobLocks;
+    use crate::time::Days;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::time::timeout;
+
+    #[derive(Default)]
+    struct Counting {
+        runs: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Job for Counting {
+        async fn run(&self) -> Result<(), AnyError> {
+            self.runs.fetch_add(1, Ordering::AcqRel);
+            Err("Out to lunch".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_due_job_is_run_once_however_many_instances_there_are() -> Result<(), AnyError> {
+        let runs: Arc<dyn JobRuns> = Arc::new(InMemoryJobRuns::default());
+        let locks: Arc<dyn JobLocks> = Arc::new(InProcessJobLocks::default());
+        let job = Arc::new(Counting::default());
+        for _ in 0..2 {
+            Scheduler::new(runs.clone(), locks.clone())
+                .with_job("count", Schedule::every(Days(1)), job.clone())
+                .start();
+        }
+
+        let recorded = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(Some(run)) = runs.last_run("count").await {
+                    return run;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(job.runs.load(Ordering::Acquire), 1);
+        assert_eq!(
+            recorded.outcome,
+            JobOutcome::Failed("Out to lunch".to_string())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+use crate::error::AnyError;
+use crate::persistence::rdbms::connect;
+use crate::persistence::schema::prepare_schema;
+use sea_orm::DatabaseConnection;
+
+/// A fresh in-memory `SQLite` database with every migration applied
+pub async fn migrated_sqlite() -> Result<DatabaseConnection, AnyError> {
+    let db = connect(&"sqlite::memory:".to_string()).await?;
+    prepare_schema(&db, true).await?;
+
+    Ok(db)
+}
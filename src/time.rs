@@ -1,4 +1,5 @@
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Seconds(pub u32);
@@ -66,6 +67,33 @@ impl From<Days> for Minutes {
     }
 }
 
+/// Doubles the wait after every consecutive failure, up to a ceiling
+pub struct Backoff {
+    initial: Duration,
+    maximum: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub const fn new(initial: Duration, maximum: Duration) -> Self {
+        Self {
+            initial,
+            maximum,
+            current: initial,
+        }
+    }
+
+    pub fn next(&mut self) -> Duration {
+        let wait = self.current;
+        self.current = (self.current * 2).min(self.maximum);
+        wait
+    }
+
+    pub const fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -114,4 +142,15 @@ mod tests {
 
         assert_eq!(hour_as_minutes, Minutes(24 * 60));
     }
+
+    #[test]
+    fn backoff_doubles_up_to_the_ceiling_until_reset() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_mins(1));
+
+        let waits: Vec<u64> = (0..8).map(|_| backoff.next().as_secs()).collect();
+        backoff.reset();
+
+        assert_eq!(waits, [1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff.next(), Duration::from_secs(1));
+    }
 }
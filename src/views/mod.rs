@@ -1,2 +1,3 @@
 pub mod pagination;
+pub mod read_your_writes;
 pub mod templates;
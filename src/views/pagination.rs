@@ -1,6 +1,7 @@
 use crate::persistence::repository::{PageNumber, PageSize};
 use serde::Deserialize;
 use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 
 #[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -12,12 +13,22 @@ pub enum Ordering {
     Descending,
 }
 
+impl Display for Ordering {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ascending => write!(f, "ascending"),
+            Self::Descending => write!(f, "descending"),
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct PageFilters {
     pub page: Option<PageNumber>,
     pub page_size: Option<PageSize>,
     pub order_by: Option<String>,
     pub ordering: Option<Ordering>,
+    pub cursor: Option<String>,
     #[serde(flatten)]
     pub filters: BTreeMap<String, String>,
 }
@@ -0,0 +1,90 @@
+use crate::config::APP_CONFIG;
+use crate::persistence::replication::reading_from_primary;
+use axum::extract::Request;
+use axum::http::header::{COOKIE, SET_COOKIE};
+use axum::http::{HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::Utc;
+
+static LAST_WRITE_COOKIE_NAME: &str = "last_write";
+static COOKIE_SEPARATOR: &str = ";";
+
+/// Sends the reads of whoever wrote something recently to the primary, so the petty matter
+/// they just raised is there when they're redirected to it, replica lag or not.
+/// A successful form submission is taken as a write and stamps the time on a cookie.
+pub async fn read_your_writes(request: Request, next: Next) -> Response {
+    let window = i64::from(APP_CONFIG.read_your_writes_window.0);
+    let now = Utc::now().timestamp();
+    let is_write = request.method() == Method::POST;
+    let wrote_recently = last_write(&request).is_some_and(|at| is_within_window(at, now, window));
+
+    let mut response = if wrote_recently {
+        reading_from_primary(next.run(request)).await
+    } else {
+        next.run(request).await
+    };
+
+    let status = response.status();
+    let cookie = HeaderValue::from_str(
+        format!("{LAST_WRITE_COOKIE_NAME}={now}; Max-Age={window}; Path=/; HttpOnly; SameSite=Lax")
+            .as_str(),
+    );
+    if is_write
+        && (status.is_success() || status.is_redirection())
+        && let Ok(cookie) = cookie
+    {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+
+    response
+}
+
+fn last_write(request: &Request) -> Option<i64> {
+    request
+        .headers()
+        .get(COOKIE)?
+        .to_str()
+        .ok()?
+        .split(COOKIE_SEPARATOR)
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(LAST_WRITE_COOKIE_NAME)?
+                .strip_prefix('=')?
+                .parse()
+                .ok()
+        })
+}
+
+/// The cookie expires on its own, but its timestamp is checked too, as clients may keep it
+const fn is_within_window(last_write: i64, now: i64, window: i64) -> bool {
+    last_write <= now && now - last_write < window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AnyError;
+    use axum::body::Body;
+
+    #[test]
+    fn reads_the_time_of_the_last_write_from_the_cookies() -> Result<(), AnyError> {
+        let request = Request::builder()
+            .header(COOKIE, "session=abc; last_write=1700000000; theme=dark")
+            .body(Body::empty())?;
+
+        assert_eq!(last_write(&request), Some(1_700_000_000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_recent_writes_are_read_from_the_primary() {
+        let now = 1_700_000_000;
+
+        assert!(is_within_window(now - 5, now, 10));
+        assert!(!is_within_window(now - 10, now, 10));
+        assert!(!is_within_window(now + 60, now, 10));
+    }
+}
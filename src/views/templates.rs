@@ -1,15 +1,19 @@
 use crate::error::AnyError;
+use crate::persistence::repository::RepositoryError;
+use crate::queue::base::QueueError;
 use crate::templates::Nonce;
 use crate::time::Seconds;
 use crate::{error, render_template};
 use askama::Template;
 use axum::http::{StatusCode, header};
 use axum::response::{Html, IntoResponse, Response};
+use std::fmt::Display;
 
 pub struct HtmlResponse {
     pub response: Html<String>,
     pub status_code: Option<StatusCode>,
     pub max_age: Option<Seconds>,
+    pub retry_after: Option<Seconds>,
 }
 
 impl HtmlResponse {
@@ -18,14 +22,7 @@ impl HtmlResponse {
             response: Html(response),
             status_code: Some(StatusCode::OK),
             max_age: None,
-        }
-    }
-
-    pub const fn cached(response: String, cache_for: Seconds) -> Self {
-        Self {
-            response: Html(response),
-            status_code: Some(StatusCode::OK),
-            max_age: Some(cache_for),
+            retry_after: None,
         }
     }
 }
@@ -44,6 +41,10 @@ impl IntoResponse for HtmlResponse {
                     .unwrap_or(header::HeaderValue::from_static("max-age=60")),
             );
         }
+        if let Some(seconds) = self.retry_after {
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(seconds.0));
+        }
         res
     }
 }
@@ -60,6 +61,40 @@ pub struct NotFoundErrorPage {
     nonce: Nonce,
 }
 
+#[derive(Template)]
+#[template(path = "errors/400.html")]
+pub struct BadRequestErrorPage {
+    nonce: Nonce,
+    message: String,
+}
+
+#[derive(Template)]
+#[template(path = "errors/409.html")]
+pub struct ConflictErrorPage {
+    nonce: Nonce,
+}
+
+#[derive(Template)]
+#[template(path = "errors/503.html")]
+pub struct UnavailableErrorPage {
+    nonce: Nonce,
+}
+
+#[derive(Template)]
+#[template(path = "errors/503_overwhelmed.html")]
+pub struct OverwhelmedErrorPage {
+    nonce: Nonce,
+}
+
+#[derive(Template)]
+#[template(path = "errors/403.html")]
+pub struct ForbiddenErrorPage {
+    nonce: Nonce,
+}
+
+static RETRY_UNAVAILABLE_AFTER: Seconds = Seconds(30);
+static RETRY_OVERWHELMED_AFTER: Seconds = Seconds(5);
+
 pub fn show_error_page<E>(error: E) -> Result<HtmlResponse, StatusCode>
 where
     E: Into<AnyError>,
@@ -73,6 +108,7 @@ where
         response: Html(response),
         status_code: Some(StatusCode::INTERNAL_SERVER_ERROR),
         max_age: None,
+        retry_after: None,
     })
 }
 
@@ -85,5 +121,99 @@ pub fn show_not_found_page() -> Result<HtmlResponse, StatusCode> {
         response: Html(response),
         status_code: Some(StatusCode::NOT_FOUND),
         max_age: Some(Seconds(60)),
+        retry_after: None,
+    })
+}
+
+pub fn show_forbidden_page() -> Result<HtmlResponse, StatusCode> {
+    let response = render_template!(ForbiddenErrorPage {
+        nonce: Nonce::new()
+    });
+
+    Ok(HtmlResponse {
+        response: Html(response),
+        status_code: Some(StatusCode::FORBIDDEN),
+        max_age: None,
+        retry_after: None,
+    })
+}
+
+pub fn show_conflict_page() -> Result<HtmlResponse, StatusCode> {
+    let response = render_template!(ConflictErrorPage {
+        nonce: Nonce::new()
+    });
+
+    Ok(HtmlResponse {
+        response: Html(response),
+        status_code: Some(StatusCode::CONFLICT),
+        max_age: None,
+        retry_after: None,
+    })
+}
+
+pub fn show_bad_request_page<E: Display>(error: &E) -> Result<HtmlResponse, StatusCode> {
+    let response = render_template!(BadRequestErrorPage {
+        nonce: Nonce::new(),
+        message: error.to_string(),
+    });
+
+    Ok(HtmlResponse {
+        response: Html(response),
+        status_code: Some(StatusCode::BAD_REQUEST),
+        max_age: None,
+        retry_after: None,
+    })
+}
+
+pub fn show_unavailable_page() -> Result<HtmlResponse, StatusCode> {
+    let response = render_template!(UnavailableErrorPage {
+        nonce: Nonce::new()
+    });
+
+    Ok(HtmlResponse {
+        response: Html(response),
+        status_code: Some(StatusCode::SERVICE_UNAVAILABLE),
+        max_age: None,
+        retry_after: Some(RETRY_UNAVAILABLE_AFTER.clone()),
+    })
+}
+
+pub fn show_overwhelmed_page() -> Result<HtmlResponse, StatusCode> {
+    let response = render_template!(OverwhelmedErrorPage {
+        nonce: Nonce::new()
+    });
+
+    Ok(HtmlResponse {
+        response: Html(response),
+        status_code: Some(StatusCode::SERVICE_UNAVAILABLE),
+        max_age: None,
+        retry_after: Some(RETRY_OVERWHELMED_AFTER.clone()),
     })
 }
+
+pub fn show_repository_error_page(error: RepositoryError) -> Result<HtmlResponse, StatusCode> {
+    match error {
+        RepositoryError::NotFound(_) => show_not_found_page(),
+        RepositoryError::Conflict(_) | RepositoryError::Duplicate(_) => show_conflict_page(),
+        RepositoryError::ConstraintViolation(_) => show_bad_request_page(&error),
+        e if e.is_transient() => {
+            error::notify_maintainers_on_error(&e.into());
+            show_unavailable_page()
+        }
+        e => show_error_page(e),
+    }
+}
+
+pub fn show_queue_error_page(error: QueueError) -> Result<HtmlResponse, StatusCode> {
+    match error {
+        QueueError::PermissionDenied(_) => show_forbidden_page(),
+        QueueError::Conflict(_) => show_conflict_page(),
+        QueueError::InvalidInput(_) => show_bad_request_page(&error),
+        QueueError::Unavailable(_) => {
+            error::notify_maintainers_on_error(&error.into());
+            show_unavailable_page()
+        }
+        QueueError::Overwhelmed(_) => show_overwhelmed_page(),
+        QueueError::SendError(_) | QueueError::OperationFailed(_) => show_error_page(error),
+    }
+}